@@ -0,0 +1,42 @@
+(function () {
+  var origin = "__PUBLIC_URL__";
+  var frames = {};
+
+  function mount() {
+    var quotes = document.querySelectorAll("blockquote.howtocards-card[data-id]");
+
+    Array.prototype.forEach.call(quotes, function (quote) {
+      var id = quote.getAttribute("data-id");
+      if (!/^[0-9]+$/.test(id)) return;
+
+      var frame = document.createElement("iframe");
+      frame.src = origin + "/open/" + id + "/embed";
+      frame.title = quote.textContent.trim() || "howtocards card";
+      frame.loading = "lazy";
+      frame.setAttribute("frameborder", "0");
+      frame.setAttribute("scrolling", "no");
+      frame.style.width = "100%";
+      frame.style.maxWidth = "550px";
+      frame.style.height = "160px";
+      frame.style.border = "0";
+
+      frames[id] = frames[id] || [];
+      frames[id].push(frame);
+      quote.parentNode.replaceChild(frame, quote);
+    });
+  }
+
+  window.addEventListener("message", function (event) {
+    if (event.origin !== origin || !event.data || !event.data.howtocardsEmbed) return;
+
+    (frames[event.data.howtocardsEmbed] || []).forEach(function (frame) {
+      frame.style.height = event.data.height + "px";
+    });
+  });
+
+  if (document.readyState === "loading") {
+    document.addEventListener("DOMContentLoaded", mount);
+  } else {
+    mount();
+  }
+})();
@@ -0,0 +1,188 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::Error;
+use futures::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+const DEFAULT_FORMAT: &str =
+    r#"{remote} "{method} {path}" {status} {duration}ms "{referer}" "{user_agent}""#;
+
+/// A line per answered request, written to the `access` log target
+/// (route it with `RUST_LOG=access=info`). `ACCESS_LOG_FORMAT` takes `{remote}`, `{method}`,
+/// `{path}`, `{status}`, `{duration}` in milliseconds, `{user_agent}` and `{referer}`,
+/// missing values are logged as `-`. Paths of `ACCESS_LOG_EXCLUDE` aren't logged.
+#[derive(Debug)]
+pub struct AccessLog {
+    /// Empty when the log is off
+    format: Vec<Part>,
+    exclude: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Field {
+    Remote,
+    Method,
+    Path,
+    Status,
+    Duration,
+    UserAgent,
+    Referer,
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// What is known about the request before it is handled
+#[derive(Debug)]
+struct Request {
+    remote: Option<String>,
+    method: String,
+    path: String,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    started: Instant,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "remote" => Some(Field::Remote),
+            "method" => Some(Field::Method),
+            "path" => Some(Field::Path),
+            "status" => Some(Field::Status),
+            "duration" => Some(Field::Duration),
+            "user_agent" => Some(Field::UserAgent),
+            "referer" => Some(Field::Referer),
+            _ => None,
+        }
+    }
+}
+
+impl AccessLog {
+    /// Empty `ACCESS_LOG_FORMAT` turns the log off
+    pub fn from_env() -> Result<Self, String> {
+        let format = std::env::var("ACCESS_LOG_FORMAT").unwrap_or_else(|_| DEFAULT_FORMAT.into());
+
+        Ok(AccessLog {
+            format: parse_format(&format)?,
+            exclude: std::env::var("ACCESS_LOG_EXCLUDE")
+                .unwrap_or_else(|_| "/readyz,/metrics".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    fn line(&self, request: &Request, status: u16) -> String {
+        let mut line = String::new();
+
+        for part in &self.format {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field(Field::Remote) => {
+                    line.push_str(request.remote.as_deref().unwrap_or("-"))
+                }
+                Part::Field(Field::Method) => line.push_str(&request.method),
+                Part::Field(Field::Path) => line.push_str(&request.path),
+                Part::Field(Field::Status) => line.push_str(&status.to_string()),
+                Part::Field(Field::Duration) => line.push_str(&format!(
+                    "{:.3}",
+                    request.started.elapsed().as_secs_f64() * 1000.0
+                )),
+                Part::Field(Field::UserAgent) => {
+                    line.push_str(request.user_agent.as_deref().unwrap_or("-"))
+                }
+                Part::Field(Field::Referer) => {
+                    line.push_str(request.referer.as_deref().unwrap_or("-"))
+                }
+            }
+        }
+
+        line
+    }
+}
+
+/// Text with `{field}` placeholders, unknown fields are refused
+fn parse_format(format: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = format;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(length) => start + length,
+            None => break,
+        };
+
+        let name = &rest[start + 1..end];
+        let field = Field::parse(name).ok_or_else(|| format!("unknown field {{{}}}", name))?;
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        parts.push(Part::Field(field));
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+/// Middleware of the public app: logs the request once the response is ready
+pub fn log<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let access_log = match req.app_data::<Arc<AccessLog>>() {
+        Some(access_log)
+            if !access_log.format.is_empty()
+                && log::log_enabled!(target: "access", log::Level::Info)
+                && !access_log.exclude.iter().any(|path| path == req.path()) =>
+        {
+            access_log
+        }
+        _ => return Box::new(srv.call(req)),
+    };
+
+    let header_value = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let request = Request {
+        // Behind a proxy it's the client address of `Forwarded` or `X-Forwarded-For`
+        remote: req.connection_info().remote().map(|remote| {
+            remote
+                .parse::<std::net::SocketAddr>()
+                .map_or_else(|_| remote.to_string(), |addr| addr.ip().to_string())
+        }),
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        user_agent: header_value(header::USER_AGENT),
+        referer: header_value(header::REFERER),
+        started: Instant::now(),
+    };
+
+    Box::new(srv.call(req).then(move |response| {
+        use log::info;
+
+        let status = match response {
+            Ok(ref response) => response.status().as_u16(),
+            Err(ref err) => err.as_response_error().error_response().status().as_u16(),
+        };
+        info!(target: "access", "{}", access_log.line(&request, status));
+
+        response
+    }))
+}
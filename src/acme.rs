@@ -0,0 +1,56 @@
+use actix_web::{web, HttpResponse};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::metrics;
+
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// ACME HTTP-01 challenges answered from `ACME_CHALLENGE_DIR`, so an ACME client running
+/// next to the service (`certbot certonly --webroot`, `lego --http.webroot`) can provision
+/// certificates for whatever terminates TLS in front of it without stopping the service.
+/// The client writes `{dir}/.well-known/acme-challenge/{token}`, like for any webroot.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        metrics::resource(&format!("{}{{token}}", CHALLENGE_PREFIX))
+            .route(web::get().to(challenge)),
+    );
+}
+
+fn challenge(path: web::Path<(String,)>, config: web::Data<Arc<Config>>) -> HttpResponse {
+    use log::info;
+
+    let dir = match config.acme_challenge_dir {
+        Some(ref dir) => dir,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let token = &path.0;
+    if !valid_token(token) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let file = Path::new(dir)
+        .join(CHALLENGE_PREFIX.trim_start_matches('/'))
+        .join(token);
+
+    match std::fs::read(&file) {
+        Ok(key_authorization) => {
+            info!("Answering ACME challenge {}", token);
+            HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .header("cache-control", "no-store")
+                .body(key_authorization)
+        }
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Tokens are base64url, anything else could escape the challenge directory
+fn valid_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
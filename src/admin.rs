@@ -0,0 +1,435 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::audit::{self, AuditLog};
+use crate::cache::{Cache, Namespace};
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::maintenance;
+use crate::outbound;
+use crate::stats::TopCards;
+use crate::storage::{LocaleTemplates, Storage, Templates};
+
+/// Admin API lives under `/_admin` and requires `Authorization: Bearer {ADMIN_TOKEN}`.
+/// Without `ADMIN_TOKEN` every admin request is rejected. Every call lands in the audit log.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/_admin")
+            .wrap_fn(audit::record)
+            .service(
+                web::resource("/degraded")
+                    .route(web::get().to(degraded_status))
+                    .route(web::post().to(degraded_toggle)),
+            )
+            .service(
+                web::resource("/maintenance")
+                    .route(web::get().to(maintenance_status))
+                    .route(web::post().to(maintenance_toggle)),
+            )
+            .service(
+                web::resource("/backend-log")
+                    .route(web::get().to(backend_log_status))
+                    .route(web::post().to(backend_log_toggle)),
+            )
+            .service(
+                web::resource("/template")
+                    .route(web::get().to(template))
+                    .route(web::post().to(template_reload)),
+            )
+            .service(web::resource("/audit").route(web::get().to(audit_log)))
+            .service(web::resource("/stats/top").route(web::get().to(top_cards)))
+            .service(web::resource("/cache/{namespace}").route(web::delete().to_async(cache_clear)))
+            .service(
+                web::resource("/cache/{namespace}/{key}")
+                    .route(web::delete().to_async(cache_invalidate)),
+            ),
+    )
+    // For deploy pipelines, same as `POST /_admin/template`
+    .service(
+        web::resource("/internal/reload-template")
+            .wrap_fn(audit::record)
+            .route(web::post().to(template_reload)),
+    );
+}
+
+pub fn authorized(req: &HttpRequest, config: &Config) -> bool {
+    bearer_matches(req, config.admin_token.as_deref())
+}
+
+/// Requests are never authorized when the token isn't configured
+pub fn bearer_matches(req: &HttpRequest, expected: Option<&str>) -> bool {
+    let expected = match expected {
+        Some(token) => token,
+        None => return false,
+    };
+
+    req.headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(ErrorBody {
+        ok: false,
+        error: "invalid token".to_string(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    ok: bool,
+    error: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Toggle {
+    enabled: bool,
+}
+
+fn degraded_status(
+    req: HttpRequest,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: flags.degraded(),
+    })
+}
+
+fn degraded_toggle(
+    req: HttpRequest,
+    body: web::Json<Toggle>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+) -> HttpResponse {
+    use log::warn;
+
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    flags.set_degraded(body.enabled);
+    warn!(
+        "Degraded mode is {}",
+        if body.enabled { "on" } else { "off" }
+    );
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: flags.degraded(),
+    })
+}
+
+fn maintenance_status(req: HttpRequest, config: web::Data<Arc<Config>>) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: maintenance::enabled(),
+    })
+}
+
+fn maintenance_toggle(
+    req: HttpRequest,
+    body: web::Json<Toggle>,
+    config: web::Data<Arc<Config>>,
+) -> HttpResponse {
+    use log::warn;
+
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    maintenance::set_enabled(body.enabled);
+    warn!(
+        "Maintenance mode is {}",
+        if body.enabled { "on" } else { "off" }
+    );
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: maintenance::enabled(),
+    })
+}
+
+fn backend_log_status(req: HttpRequest, config: web::Data<Arc<Config>>) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: outbound::enabled(),
+    })
+}
+
+/// Logs every backend request and response at info level, with secrets redacted
+fn backend_log_toggle(
+    req: HttpRequest,
+    body: web::Json<Toggle>,
+    config: web::Data<Arc<Config>>,
+) -> HttpResponse {
+    use log::warn;
+
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    outbound::set_enabled(body.enabled);
+    warn!(
+        "Backend request logging is {}",
+        if body.enabled { "on" } else { "off" }
+    );
+
+    HttpResponse::Ok().json(Toggle {
+        enabled: outbound::enabled(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TemplateBody {
+    path: String,
+    hash: String,
+    loaded_at: String,
+    fragments: Vec<String>,
+}
+
+/// Which frontend build is being served, to verify deploys and rollbacks
+fn template(
+    req: HttpRequest,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    HttpResponse::Ok().json(TemplateBody::new(&templates, &templates.current()))
+}
+
+impl TemplateBody {
+    fn new(templates: &Templates, storage: &Storage) -> Self {
+        TemplateBody {
+            path: templates.path().to_string(),
+            hash: storage.hash.clone(),
+            loaded_at: humantime::format_rfc3339_seconds(storage.loaded_at).to_string(),
+            fragments: storage
+                .fragments
+                .iter()
+                .map(|fragment| fragment.name.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Result of reloading one template
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Reloaded {
+    Loaded(TemplateBody),
+    Failed(ErrorBody),
+}
+
+impl Reloaded {
+    fn of(templates: &Templates) -> Self {
+        match templates.reload() {
+            Ok(storage) => Reloaded::Loaded(TemplateBody::new(templates, &storage)),
+            Err(err) => Reloaded::Failed(ErrorBody {
+                ok: false,
+                error: format!("cannot load template {}: {}", templates.path(), err),
+            }),
+        }
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self, Reloaded::Failed(_))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ReloadBody {
+    #[serde(flatten)]
+    template: Reloaded,
+    /// Templates of `LOCALE_INDEX_HTML`, each with its own result
+    locales: Vec<Reloaded>,
+}
+
+/// Reads the template and the locale ones right away instead of waiting for the watchers.
+/// When any of them is broken the answer is 422, broken ones keep serving what they had.
+fn template_reload(
+    req: HttpRequest,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    locale_templates: web::Data<Arc<LocaleTemplates>>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    let body = ReloadBody {
+        template: Reloaded::of(&templates),
+        locales: locale_templates
+            .iter()
+            .map(|templates| Reloaded::of(templates))
+            .collect(),
+    };
+
+    if body.template.failed() || body.locales.iter().any(Reloaded::failed) {
+        HttpResponse::UnprocessableEntity().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    limit: Option<usize>,
+}
+
+fn audit_log(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    config: web::Data<Arc<Config>>,
+    audit: web::Data<Arc<AuditLog>>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    HttpResponse::Ok().json(audit.latest(query.limit.unwrap_or(100)))
+}
+
+#[derive(Debug, Deserialize)]
+struct TopQuery {
+    window: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct TopCard {
+    id: u32,
+    hits: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct TopBody {
+    window: String,
+    cards: Vec<TopCard>,
+}
+
+fn top_cards(
+    req: HttpRequest,
+    query: web::Query<TopQuery>,
+    config: web::Data<Arc<Config>>,
+    top: web::Data<Arc<TopCards>>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return forbidden();
+    }
+
+    let window = match query.window {
+        Some(ref window) => match humantime::parse_duration(window) {
+            Ok(window) => window,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(ErrorBody {
+                    ok: false,
+                    error: format!("invalid window: {}", err),
+                })
+            }
+        },
+        None => std::time::Duration::from_secs(60 * 60),
+    };
+    let window = window.min(top.retention());
+
+    let cards = top
+        .top(window, query.limit.unwrap_or(20).min(1000))
+        .into_iter()
+        .map(|(id, hits)| TopCard { id, hits })
+        .collect();
+
+    HttpResponse::Ok().json(TopBody {
+        window: humantime::format_duration(window).to_string(),
+        cards,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct CachePath {
+    namespace: String,
+    key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Invalidated {
+    namespace: &'static str,
+    removed: bool,
+}
+
+fn cache_namespace(path: &CachePath) -> Result<Namespace, HttpResponse> {
+    Namespace::parse(&path.namespace).ok_or_else(|| {
+        HttpResponse::NotFound().json(ErrorBody {
+            ok: false,
+            error: format!("unknown cache namespace: {}", path.namespace),
+        })
+    })
+}
+
+/// Drops every entry of the namespace, e.g. after a bulk update on the backend
+fn cache_clear(
+    req: HttpRequest,
+    path: web::Path<CachePath>,
+    config: web::Data<Arc<Config>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if !authorized(&req, &config) {
+        return Box::new(future::ok(forbidden()));
+    }
+
+    let namespace = match cache_namespace(&path) {
+        Ok(namespace) => namespace,
+        Err(response) => return Box::new(future::ok(response)),
+    };
+
+    Box::new(cache.clear(namespace).map(move |()| {
+        HttpResponse::Ok().json(Invalidated {
+            namespace: namespace.name(),
+            removed: true,
+        })
+    }))
+}
+
+/// Drops a single entity: card or user id, collection id, `{listing}:{page}`
+fn cache_invalidate(
+    req: HttpRequest,
+    path: web::Path<CachePath>,
+    config: web::Data<Arc<Config>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if !authorized(&req, &config) {
+        return Box::new(future::ok(forbidden()));
+    }
+
+    let namespace = match cache_namespace(&path) {
+        Ok(namespace) => namespace,
+        Err(response) => return Box::new(future::ok(response)),
+    };
+    let key = path.key.as_deref().unwrap_or_default();
+
+    Box::new(cache.invalidate(namespace, key).map(move |removed| {
+        HttpResponse::Ok().json(Invalidated {
+            namespace: namespace.name(),
+            removed,
+        })
+    }))
+}
@@ -0,0 +1,61 @@
+//! Global allocator selection. The service allocates many medium-sized strings per request,
+//! so `--features jemalloc` or `--features mimalloc` can make a visible difference.
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("features `jemalloc` and `mimalloc` are mutually exclusive");
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+pub fn name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else {
+        "system"
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AllocatorStats {
+    /// Bytes handed out to the application
+    pub allocated: u64,
+    /// Bytes of physical memory held by the allocator
+    pub resident: u64,
+}
+
+/// Allocator's own view on memory usage, when allocator can report it
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Option<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    epoch::advance().ok()?;
+
+    Some(AllocatorStats {
+        allocated: stats::allocated::read().ok()? as u64,
+        resident: stats::resident::read().ok()? as u64,
+    })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn stats() -> Option<AllocatorStats> {
+    None
+}
+
+/// Resident set size of the process, linux only
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    status
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
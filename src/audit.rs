@@ -0,0 +1,116 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use futures::Future;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::config::env_or;
+use crate::images::version_hash;
+
+/// Every admin API call is written to the `audit` log target as a JSON line
+/// (route it with `RUST_LOG=audit=info`) and kept in memory for `GET /_admin/audit`.
+#[derive(Debug)]
+pub struct AuditLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    at: String,
+    method: String,
+    path: String,
+    /// Short hash of the bearer token, the token itself is never logged
+    token_id: Option<String>,
+    ip: Option<String>,
+    status: u16,
+}
+
+impl AuditLog {
+    /// Keeps last `AUDIT_LOG_SIZE` entries
+    pub fn from_env() -> Self {
+        AuditLog {
+            capacity: env_or("AUDIT_LOG_SIZE", 1000),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn push(&self, entry: Entry) {
+        use log::info;
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            info!(target: "audit", "{}", line);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if self.capacity == 0 {
+            return;
+        }
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Latest entries first
+    pub fn latest(&self, limit: usize) -> Vec<Entry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Middleware for admin scopes: records the call once the response is ready
+pub fn record<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let audit = match req.app_data::<Arc<AuditLog>>() {
+        Some(audit) => audit,
+        None => return Box::new(srv.call(req)),
+    };
+
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let ip = req.connection_info().remote().map(|remote| {
+        remote
+            .parse::<std::net::SocketAddr>()
+            .map_or_else(|_| remote.to_string(), |addr| addr.ip().to_string())
+    });
+    let token_id = req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| version_hash(token).chars().take(8).collect());
+
+    Box::new(srv.call(req).then(move |response| {
+        let status = match response {
+            Ok(ref response) => response.status().as_u16(),
+            Err(ref err) => err.as_response_error().error_response().status().as_u16(),
+        };
+
+        audit.push(Entry {
+            at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            method,
+            path,
+            token_id,
+            ip,
+            status,
+        });
+
+        response
+    }))
+}
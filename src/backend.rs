@@ -0,0 +1,824 @@
+use actix_web::http::{header, HeaderValue, StatusCode};
+use actix_web::{client::Client, error, web, Error};
+use futures::{future, Future, Stream};
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::{self, Cache, CacheFuture, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::health;
+use crate::maintenance;
+use crate::metrics::{self, Outcome};
+use crate::outbound::Trace;
+use crate::singleflight::{self, Flight, Leader};
+
+/// Backoff when backend answers 429 without a usable `Retry-After`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// Backend can't switch us off for longer than that
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+lazy_static! {
+    /// Backend asked to slow down, no requests are sent until then
+    static ref THROTTLED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Answer<T> {
+    Err { ok: bool, error: String },
+    Ok { ok: bool, result: T },
+}
+
+/// Every meta endpoint of the backend wraps the entity into `{ "meta": ... }`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MetaWrapper<T> {
+    pub meta: T,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Card {
+    pub title: String,
+    pub description: String,
+    pub id: i32,
+    pub created_at: String,
+    pub updated_at: String,
+    pub preview_url: Option<String>,
+    /// Size and description of the preview, so crawlers don't download it to lay out the card
+    #[serde(default)]
+    pub preview_width: Option<u32>,
+    #[serde(default)]
+    pub preview_height: Option<u32>,
+    #[serde(default)]
+    pub preview_alt: Option<String>,
+    #[serde(default)]
+    pub author: Option<Author>,
+    /// Cards the backend considers adjacent to this one
+    #[serde(default)]
+    pub related: Vec<RelatedCard>,
+    /// Collection the card is shown in, if the author put it into one
+    #[serde(default)]
+    pub collection: Option<CardCollection>,
+    /// Instructions of a step-by-step guide, empty for other cards
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// Size of the card text for the reading time, words preferred over characters
+    #[serde(default)]
+    pub word_count: Option<u64>,
+    #[serde(default)]
+    pub content_length: Option<u64>,
+    /// Language of the card, `ru` or `en-GB`
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// The same card in other languages
+    #[serde(default)]
+    pub translations: Vec<Translation>,
+    /// Names of the card tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Tags added by the backend as they are, e.g. `article:section`
+    #[serde(default)]
+    pub extra_meta: Vec<ExtraMeta>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Who the author shares the card with: unlisted cards are open by link only,
+/// private ones to the author only
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Unlisted,
+    Private,
+    #[default]
+    #[serde(other)]
+    Public,
+}
+
+/// Full text of a card, markdown
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CardContent {
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExtraMeta {
+    pub property: String,
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardCollection {
+    pub id: i32,
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub text: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Translation {
+    pub id: i32,
+    pub lang: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedCard {
+    pub id: i32,
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Author {
+    #[serde(default)]
+    pub id: Option<i32>,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    /// Author page, the user page of `id` when the backend doesn't know another one
+    #[serde(default)]
+    pub profile_url: Option<String>,
+    /// Twitter handle, with or without `@`
+    #[serde(default)]
+    pub twitter: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub id: i32,
+    pub display_name: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Written by the user, preferred over composed stats for the description
+    #[serde(default)]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub cards_count: Option<u64>,
+    #[serde(default)]
+    pub useful_marks_count: Option<u64>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    /// Profiles of the user on other sites
+    #[serde(default)]
+    pub social_links: Vec<String>,
+    /// Tags added by the backend as they are, e.g. `article:section`
+    #[serde(default)]
+    pub extra_meta: Vec<ExtraMeta>,
+}
+
+/// Topic page, cards share the tag
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub slug: String,
+    pub name: String,
+    pub cards_count: u64,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Tags added by the backend as they are, e.g. `article:section`
+    #[serde(default)]
+    pub extra_meta: Vec<ExtraMeta>,
+}
+
+/// Public list of cards the user marked as useful
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsefulList {
+    pub user: User,
+    pub count: u64,
+    /// Most popular cards of the list
+    #[serde(default)]
+    pub cards: Vec<CollectionCard>,
+}
+
+/// One page of a paginated listing like popular or latest cards
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Listing {
+    pub page: u32,
+    pub pages: u32,
+    #[serde(default)]
+    pub count: Option<u64>,
+}
+
+/// Latest published cards for the feed, newest first
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LatestCards {
+    #[serde(default)]
+    pub cards: Vec<Card>,
+}
+
+/// One page of published cards for the sitemap
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedCards {
+    pub page: u32,
+    pub pages: u32,
+    #[serde(default)]
+    pub cards: Vec<PublishedCard>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedCard {
+    pub id: i32,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection {
+    pub id: i32,
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub updated_at: String,
+    /// Set by the author, otherwise the preview is a collage of card previews
+    #[serde(default)]
+    pub cover_url: Option<String>,
+    /// First cards of the collection
+    #[serde(default)]
+    pub cards: Vec<CollectionCard>,
+    /// Tags added by the backend as they are, e.g. `article:section`
+    #[serde(default)]
+    pub extra_meta: Vec<ExtraMeta>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionCard {
+    pub id: i32,
+    pub title: String,
+    #[serde(default)]
+    pub preview_url: Option<String>,
+}
+
+impl Config {
+    pub fn backend_card_url(&self, card_id: u32) -> String {
+        format!("{}/api/cards/{}/meta/", self.backend_url, card_id)
+    }
+
+    /// Cache key and backend URL of an entity in the locale of the path prefix,
+    /// the backend gets it as `?lang=`
+    pub fn localized(&self, key: String, url: String, locale: Option<&str>) -> (String, String) {
+        match locale {
+            Some(locale) => (
+                localized_key(&key, locale),
+                format!("{}?lang={}", url, locale),
+            ),
+            None => (key, url),
+        }
+    }
+
+    pub fn backend_card_content_url(&self, card_id: u32) -> String {
+        format!("{}/api/cards/{}/content/", self.backend_url, card_id)
+    }
+
+    pub fn backend_user_url(&self, user_id: u32) -> String {
+        format!("{}/api/users/{}/meta/", self.backend_url, user_id)
+    }
+
+    pub fn backend_username_url(&self, username: &str) -> String {
+        format!("{}/api/users/@{}/meta/", self.backend_url, username)
+    }
+
+    pub fn backend_tag_url(&self, slug: &str) -> String {
+        format!("{}/api/tags/{}/meta/", self.backend_url, slug)
+    }
+
+    pub fn backend_useful_url(&self, user_id: u32) -> String {
+        format!("{}/api/users/{}/useful/meta/", self.backend_url, user_id)
+    }
+
+    pub fn backend_listing_url(&self, listing: &str, page: u32) -> String {
+        format!(
+            "{}/api/listings/{}/meta/?page={}",
+            self.backend_url, listing, page
+        )
+    }
+
+    pub fn backend_latest_url(&self) -> String {
+        format!("{}/api/cards/latest/meta/", self.backend_url)
+    }
+
+    pub fn backend_published_url(&self, page: u32) -> String {
+        format!(
+            "{}/api/cards/published/meta/?page={}",
+            self.backend_url, page
+        )
+    }
+
+    pub fn backend_collection_url(&self, collection_id: u32) -> String {
+        format!(
+            "{}/api/collections/{}/meta/",
+            self.backend_url, collection_id
+        )
+    }
+}
+
+/// Status and caching directives of a backend meta response
+#[derive(Debug, Default, Clone)]
+pub struct Freshness {
+    pub status: StatusCode,
+    /// `s-maxage` or `max-age` of `Cache-Control`
+    pub max_age: Option<Duration>,
+    /// `Cache-Control: no-store`, entity must not be cached at all
+    pub no_store: bool,
+    /// `Last-Modified` as RFC 3339, older backends don't send `updatedAt` in meta
+    pub last_modified: Option<String>,
+    /// Backend answered 404, the entity doesn't exist
+    pub not_found: bool,
+    /// Backend answered 410 or a `*deleted` error, the entity existed once
+    pub gone: bool,
+}
+
+impl Freshness {
+    fn from_headers(headers: &header::HeaderMap) -> Self {
+        let mut freshness = Freshness::default();
+        let mut shared_max_age = None;
+
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_lowercase());
+
+        for directive in directives {
+            let (name, value) = match directive.find('=') {
+                Some(index) => (
+                    &directive[..index],
+                    directive[index + 1..].trim_matches('"'),
+                ),
+                None => (directive.as_str(), ""),
+            };
+            let seconds = value.parse().ok().map(Duration::from_secs);
+
+            match name {
+                "no-store" => freshness.no_store = true,
+                "max-age" => freshness.max_age = seconds,
+                "s-maxage" => shared_max_age = seconds,
+                _ => {}
+            }
+        }
+
+        freshness.max_age = shared_max_age.or(freshness.max_age);
+        freshness.last_modified = headers
+            .get(header::LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<header::HttpDate>().ok())
+            .map(|date| humantime::format_rfc3339_seconds(SystemTime::from(date)).to_string());
+        freshness
+    }
+}
+
+/// Requests meta of an entity from the backend.
+/// Resolves to `None` when the backend answers 404 or 410, or says the entity is deleted.
+/// Other errors and bodies that aren't meta of the entity fail, so that pages of a broken
+/// backend are answered with 502 or stale meta instead of a 404 for every entity.
+pub fn fetch_meta<T>(
+    client: &Client,
+    chaos: &Arc<Chaos>,
+    url: String,
+) -> impl Future<Item = (Option<T>, Freshness), Error = Error>
+where
+    T: DeserializeOwned + 'static,
+{
+    let fault = chaos.pick();
+    let request = client.get(&url);
+    let before_request = chaos.before_request(fault);
+
+    let allowed = if throttled() {
+        metrics::record_throttled("skipped");
+        Err(error::ErrorServiceUnavailable("backend is throttled"))
+    } else {
+        Ok(())
+    };
+
+    future::result(allowed)
+        .and_then(move |_| before_request)
+        .and_then(move |_| {
+            let trace = Trace::start("GET", &url, request.headers());
+            let timer = metrics::backend_timer();
+
+            request.send().then(move |resp| {
+                health::record_backend(
+                    resp.as_ref()
+                        .is_ok_and(|resp| !resp.status().is_server_error()),
+                );
+                match resp {
+                    Ok(resp) => Ok((resp, trace, timer)),
+                    Err(err) => {
+                        if let Some(ref trace) = trace {
+                            trace.failed(&err);
+                        }
+                        Err(Error::from(err))
+                    }
+                }
+            })
+        })
+        .and_then(|(resp, trace, timer)| {
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                if let Some(ref trace) = trace {
+                    trace.response(resp.status(), &[]);
+                }
+                metrics::record_throttled("limited");
+                throttle(resp.headers().get(header::RETRY_AFTER));
+                return Err(error::ErrorServiceUnavailable("backend answered 429"));
+            }
+            Ok((resp, trace, timer))
+        })
+        .and_then(|(resp, trace, timer)| {
+            let mut freshness = Freshness::from_headers(resp.headers());
+            let status = resp.status();
+            freshness.status = status;
+            freshness.not_found = status == StatusCode::NOT_FOUND;
+            freshness.gone = status == StatusCode::GONE;
+
+            resp.from_err()
+                .fold(web::BytesMut::new(), |mut acc, chunk| {
+                    acc.extend_from_slice(&chunk);
+                    Ok::<_, Error>(acc)
+                })
+                .then(move |body| {
+                    timer.observe_duration();
+                    if let Some(ref trace) = trace {
+                        match body {
+                            Ok(ref body) => trace.response(status, body),
+                            Err(ref err) => trace.failed(err),
+                        }
+                    }
+                    body
+                })
+                .map(move |body| (body, freshness))
+        })
+        .map(move |(body, freshness)| (Chaos::corrupt_body(fault, body), freshness))
+        .and_then(|(body, mut freshness)| {
+            if freshness.not_found || freshness.gone {
+                return Ok((None, freshness));
+            }
+
+            let body: Result<Answer<MetaWrapper<serde_json::Value>>, _> =
+                serde_json::from_slice(&body);
+
+            match body {
+                Ok(Answer::Ok { result, .. }) if freshness.status.is_success() => {
+                    let mut meta = result.meta;
+                    fill_updated_at(&mut meta, freshness.last_modified.as_deref());
+                    match T::deserialize(meta) {
+                        Ok(entity) => Ok((Some(entity), freshness)),
+                        Err(err) => Err(error::ErrorBadGateway(format!(
+                            "unexpected meta from the backend: {}",
+                            err
+                        ))),
+                    }
+                }
+                // `card_deleted` and the like
+                Ok(Answer::Err { ref error, .. }) if error.ends_with("deleted") => {
+                    freshness.gone = true;
+                    Ok((None, freshness))
+                }
+                Ok(Answer::Err { error, .. }) => Err(error::ErrorBadGateway(format!(
+                    "backend answered {} with {}",
+                    freshness.status, error
+                ))),
+                _ => Err(error::ErrorBadGateway(format!(
+                    "backend answered {} without meta",
+                    freshness.status
+                ))),
+            }
+        })
+}
+
+/// `updatedAt` falls back to `Last-Modified` of the response
+fn fill_updated_at(meta: &mut serde_json::Value, last_modified: Option<&str>) {
+    let (meta, last_modified) = match (meta.as_object_mut(), last_modified) {
+        (Some(meta), Some(last_modified)) => (meta, last_modified),
+        _ => return,
+    };
+
+    if meta.get("updatedAt").is_none_or(serde_json::Value::is_null) {
+        meta.insert("updatedAt".to_string(), last_modified.into());
+    }
+}
+
+/// Backend answered 429 recently and its `Retry-After` hasn't passed yet
+pub fn throttled() -> bool {
+    let mut until = THROTTLED_UNTIL.lock().unwrap();
+
+    match *until {
+        Some(deadline) if deadline > Instant::now() => true,
+        Some(_) => {
+            *until = None;
+            false
+        }
+        None => false,
+    }
+}
+
+fn throttle(retry_after: Option<&HeaderValue>) {
+    use log::warn;
+
+    let backoff = retry_after
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+        .min(MAX_RETRY_AFTER);
+
+    warn!(
+        "Backend is throttling requests, backing off for {:?}",
+        backoff
+    );
+
+    let deadline = Instant::now() + backoff;
+    let mut until = THROTTLED_UNTIL.lock().unwrap();
+    if until.is_none_or(|current| current < deadline) {
+        *until = Some(deadline);
+    }
+}
+
+/// `Retry-After` is either delay in seconds or HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = value.parse::<header::HttpDate>().ok()?;
+    Some(
+        SystemTime::from(date)
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+/// Entity looked up through the meta cache
+#[derive(Debug)]
+pub struct Fetched<T> {
+    pub entity: Option<T>,
+    /// Served from the cache, backend wasn't called
+    pub cached: bool,
+    /// Backend has deleted the entity, answered with 410 instead of 404
+    pub gone: bool,
+}
+
+impl<T> Fetched<T> {
+    pub fn outcome(&self) -> Outcome {
+        match (&self.entity, self.cached) {
+            (Some(_), true) => Outcome::Hit,
+            (Some(_), false) => Outcome::Miss,
+            (None, _) => Outcome::Fallback,
+        }
+    }
+}
+
+impl Fetched<Card> {
+    /// Private cards are missing for everyone but the author, who never comes through SSI
+    pub fn without_private(self) -> Self {
+        match self.entity {
+            Some(ref card) if card.visibility == Visibility::Private => Fetched {
+                entity: None,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+/// Entries of other locales are kept apart, `1@ru`
+pub fn localized_key(key: &str, locale: &str) -> String {
+    format!("{}@{}", key, locale)
+}
+
+/// Same as `fetch_meta`, but looks into the cache namespace first
+/// and stores successfully fetched entities there, for as long as backend's `max-age` allows.
+/// While the backend fails or is throttling, expired entries are served as well,
+/// in maintenance mode they are served without asking the backend.
+/// Entities the backend doesn't have are remembered for `CACHE_NOT_FOUND_TTL`.
+/// Concurrent misses of the same entity share a single backend request.
+pub fn fetch_cached<T>(
+    client: &Client,
+    chaos: &Arc<Chaos>,
+    cache: &Arc<Cache>,
+    namespace: Namespace,
+    key: String,
+    url: String,
+) -> Box<dyn Future<Item = Fetched<T>, Error = Error>>
+where
+    T: DeserializeOwned + Serialize + 'static,
+{
+    let cached = if maintenance::enabled() {
+        cache.get_stale(namespace, &key)
+    } else {
+        cache.get(namespace, &key)
+    };
+    let (client, chaos, cache) = (client.clone(), chaos.clone(), cache.clone());
+
+    Box::new(cached.and_then(
+        move |cached| -> Box<dyn Future<Item = Fetched<T>, Error = Error>> {
+            if let Some(fetched) = cached.and_then(|value| from_cached(&value)) {
+                return Box::new(future::ok(fetched));
+            }
+
+            Box::new(
+                cache
+                    .get_revalidating(namespace, &key)
+                    .and_then(move |revalidating| {
+                        let revalidating =
+                            revalidating.and_then(|value| T::deserialize(&*value).ok());
+                        fetch_missing(&client, &chaos, &cache, namespace, key, url, revalidating)
+                    }),
+            )
+        },
+    ))
+}
+
+/// Rest of `fetch_cached` once there is no fresh entry
+fn fetch_missing<T>(
+    client: &Client,
+    chaos: &Arc<Chaos>,
+    cache: &Arc<Cache>,
+    namespace: Namespace,
+    key: String,
+    url: String,
+    revalidating: Option<T>,
+) -> Box<dyn Future<Item = Fetched<T>, Error = Error>>
+where
+    T: DeserializeOwned + Serialize + 'static,
+{
+    if let Some(entity) = revalidating {
+        // Followers don't refresh, whoever is fetching already stores the fresh entry
+        if let Flight::Leader(leader) = singleflight::join(namespace, &key) {
+            metrics::record_revalidated(namespace.name());
+            actix_rt::spawn(
+                fetch_leader::<T>(client, chaos, cache, namespace, key, url, leader).then(
+                    move |fetched| {
+                        if let Err(err) = fetched {
+                            log::warn!("Failed to revalidate {}: {}", namespace.name(), err);
+                        }
+                        Ok(())
+                    },
+                ),
+            );
+        }
+
+        return Box::new(future::ok(Fetched {
+            entity: Some(entity),
+            cached: true,
+            gone: false,
+        }));
+    }
+
+    let leader = match singleflight::join(namespace, &key) {
+        Flight::Leader(leader) => leader,
+        Flight::Follower(receiver) => {
+            metrics::record_coalesced(namespace.name());
+            let (client, chaos, cache) = (client.clone(), chaos.clone(), cache.clone());
+
+            return Box::new(receiver.then(
+                move |shared| -> Box<dyn Future<Item = Fetched<T>, Error = Error>> {
+                    match shared {
+                        Ok(Ok(value)) => Box::new(future::ok(
+                            value
+                                .and_then(|value| from_cached(&value))
+                                .unwrap_or(Fetched {
+                                    entity: None,
+                                    cached: true,
+                                    gone: false,
+                                }),
+                        )),
+                        Ok(Err(message)) => {
+                            Box::new(future::err(error::ErrorServiceUnavailable(message)))
+                        }
+                        // Request of the leader was dropped before the backend answered
+                        Err(_) => fetch_cached(&client, &chaos, &cache, namespace, key, url),
+                    }
+                },
+            ));
+        }
+    };
+
+    fetch_leader(client, chaos, cache, namespace, key, url, leader)
+}
+
+/// Entity of a cached or shared value, `null` stands for a missing entity
+/// and `cache::GONE` for a deleted one. `None` when the value doesn't fit the entity type.
+fn from_cached<T: DeserializeOwned>(value: &serde_json::Value) -> Option<Fetched<T>> {
+    let missing = |gone| Fetched {
+        entity: None,
+        cached: true,
+        gone,
+    };
+
+    if value.is_null() {
+        return Some(missing(false));
+    }
+    if cache::is_gone(value) {
+        return Some(missing(true));
+    }
+
+    T::deserialize(value).ok().map(|entity| Fetched {
+        entity: Some(entity),
+        cached: true,
+        gone: false,
+    })
+}
+
+/// Cached entity, expired one too, for when the backend can't be asked
+pub fn stale<T: DeserializeOwned + 'static>(
+    cache: &Cache,
+    namespace: Namespace,
+    key: &str,
+) -> CacheFuture<Option<T>> {
+    Box::new(cache.get_stale(namespace, key).map(|value| {
+        value
+            .filter(|value| !value.is_null() && !cache::is_gone(value))
+            .and_then(|value| T::deserialize(&*value).ok())
+    }))
+}
+
+/// Fetches the entity into the cache and hands it to requests waiting for it
+fn fetch_leader<T>(
+    client: &Client,
+    chaos: &Arc<Chaos>,
+    cache: &Arc<Cache>,
+    namespace: Namespace,
+    key: String,
+    url: String,
+    leader: Leader,
+) -> Box<dyn Future<Item = Fetched<T>, Error = Error>>
+where
+    T: DeserializeOwned + Serialize + 'static,
+{
+    let cache = cache.clone();
+
+    Box::new(fetch_meta::<T>(client, chaos, url).then(move |fetched| {
+        let entity = fetched.map(|(entity, freshness)| {
+            match entity {
+                _ if freshness.no_store => {
+                    actix_rt::spawn(cache.invalidate(namespace, &key).then(|_| Ok(())));
+                }
+                Some(ref entity) => {
+                    if let Ok(value) = serde_json::to_value(entity) {
+                        cache.put(namespace, key.clone(), value, freshness.max_age);
+                    }
+                }
+                None if freshness.gone => cache.put_gone(namespace, key.clone()),
+                None if freshness.not_found => cache.put_missing(namespace, key.clone()),
+                None => {}
+            }
+            (entity, freshness.gone)
+        });
+
+        let fetched: Box<dyn Future<Item = Fetched<T>, Error = Error>> = match entity {
+            Ok((entity, gone)) => Box::new(future::ok(Fetched {
+                entity,
+                cached: false,
+                gone,
+            })),
+            // Stale meta is better than an error page while the backend fails
+            Err(err) => Box::new(stale::<T>(&cache, namespace, &key).then(
+                move |stale| match stale {
+                    Ok(Some(entity)) => Ok(Fetched {
+                        entity: Some(entity),
+                        cached: true,
+                        gone: false,
+                    }),
+                    _ => Err(err),
+                },
+            )),
+        };
+
+        fetched.then(move |fetched| {
+            leader.finish(|| match fetched {
+                Ok(Fetched { gone: true, .. }) => {
+                    Ok(Some(Arc::new(serde_json::Value::from(cache::GONE))))
+                }
+                Ok(ref fetched) => Ok(fetched
+                    .entity
+                    .as_ref()
+                    .and_then(|entity| serde_json::to_value(entity).ok())
+                    .map(Arc::new)),
+                Err(ref err) => Err(err.to_string()),
+            });
+
+            fetched
+        })
+    }))
+}
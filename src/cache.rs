@@ -0,0 +1,489 @@
+use actix_web::{web, Error};
+use futures::{future, Future};
+use lru::LruCache;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{env_duration, env_or};
+use crate::memory;
+use crate::metrics;
+use crate::redis_cache::RedisStore;
+
+/// Bounds for TTLs requested by the backend via `Cache-Control`
+const MIN_TTL: Duration = Duration::from_secs(10);
+const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Cached in place of entities the backend answered 410 for
+pub const GONE: &str = "\u{0}gone";
+
+pub fn is_gone(value: &Value) -> bool {
+    value.as_str() == Some(GONE)
+}
+
+/// Cached entities are split into namespaces, each with its own TTL and size budget:
+/// profiles change far less often than freshly edited cards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    Card,
+    User,
+    UsefulList,
+    Collection,
+    Listing,
+    Tag,
+    /// Entities of `ROUTES_FILE` routes, keyed by backend path
+    Route,
+    /// Card markdown rendered for crawlers with `RENDER_CARD_BODY`
+    Content,
+    /// Whether card previews exist, keyed by image URL, with `PREVIEW_CHECK`
+    Preview,
+    /// Pre-rendered card pages for visitors without a preferred locale, keyed by the template hash
+    Page,
+    /// Pages of published cards for `/sitemap.xml`, keyed by page
+    Sitemap,
+    /// Latest cards for `/feed.xml`
+    Feed,
+}
+
+impl Namespace {
+    pub const ALL: [Namespace; 12] = [
+        Namespace::Card,
+        Namespace::User,
+        Namespace::UsefulList,
+        Namespace::Collection,
+        Namespace::Listing,
+        Namespace::Tag,
+        Namespace::Route,
+        Namespace::Content,
+        Namespace::Preview,
+        Namespace::Page,
+        Namespace::Sitemap,
+        Namespace::Feed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Namespace::Card => "card",
+            Namespace::User => "user",
+            Namespace::UsefulList => "useful_list",
+            Namespace::Collection => "collection",
+            Namespace::Listing => "listing",
+            Namespace::Tag => "tag",
+            Namespace::Route => "route",
+            Namespace::Content => "content",
+            Namespace::Preview => "preview",
+            Namespace::Page => "page",
+            Namespace::Sitemap => "sitemap",
+            Namespace::Feed => "feed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Namespace> {
+        Namespace::ALL
+            .iter()
+            .cloned()
+            .find(|namespace| namespace.name() == name)
+    }
+
+    /// Default TTL, entries count and bytes budget
+    pub fn defaults(self) -> (Duration, usize, usize) {
+        const MB: usize = 1024 * 1024;
+
+        match self {
+            Namespace::Card => (Duration::from_secs(5 * 60), 10_000, 32 * MB),
+            Namespace::User => (Duration::from_secs(60 * 60), 5_000, 8 * MB),
+            Namespace::UsefulList => (Duration::from_secs(15 * 60), 2_000, 8 * MB),
+            Namespace::Collection => (Duration::from_secs(15 * 60), 2_000, 8 * MB),
+            Namespace::Listing => (Duration::from_secs(60), 500, 2 * MB),
+            Namespace::Tag => (Duration::from_secs(15 * 60), 2_000, 4 * MB),
+            Namespace::Route => (Duration::from_secs(5 * 60), 2_000, 8 * MB),
+            Namespace::Content => (Duration::from_secs(5 * 60), 1_000, 32 * MB),
+            Namespace::Preview => (Duration::from_secs(60 * 60), 10_000, 4 * MB),
+            Namespace::Page => (Duration::from_secs(5 * 60), 1_000, 32 * MB),
+            Namespace::Sitemap => (Duration::from_secs(60 * 60), 1_000, 64 * MB),
+            Namespace::Feed => (Duration::from_secs(5 * 60), 10, 4 * MB),
+        }
+    }
+
+    pub fn env_prefix(self) -> String {
+        format!("CACHE_{}", self.name().to_uppercase())
+    }
+}
+
+/// Where cached meta lives, process memory or shared between replicas
+pub trait CacheStore: Send + Sync + std::fmt::Debug {
+    /// Value regardless of its TTL, with how long ago it expired
+    fn lookup(&self, namespace: Namespace, key: &str) -> Option<Lookup>;
+    /// `ttl` overrides TTL of the namespace within its min/max bounds
+    fn put(&self, namespace: Namespace, key: String, value: Value, ttl: Option<Duration>);
+    fn invalidate(&self, namespace: Namespace, key: &str) -> bool;
+    fn clear(&self, namespace: Namespace);
+
+    /// Calls of remote stores wait for the network, they are kept off the event loop
+    fn remote(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct Lookup {
+    pub value: Arc<Value>,
+    /// `None` while the entry is fresh
+    pub expired_for: Option<Duration>,
+}
+
+/// Result of a cache call, remote stores answer on the blocking thread pool.
+/// Never fails, a store that can't answer counts as a miss.
+pub type CacheFuture<T> = Box<dyn Future<Item = T, Error = Error>>;
+
+/// Backend meta cache keyed by namespace and entity key (id, page, ...).
+/// In memory of every replica by default, in Redis when `CACHE_REDIS_URL` is set.
+#[derive(Debug)]
+pub struct Cache {
+    store: Arc<dyn CacheStore>,
+    /// `CACHE_STALE_WHILE_REVALIDATE`, 0 disables it
+    stale_while_revalidate: Duration,
+    /// `CACHE_NOT_FOUND_TTL`, 0 disables negative caching
+    not_found_ttl: Duration,
+}
+
+impl Cache {
+    /// In memory with the default settings
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Cache {
+            store: Arc::new(MemoryStore::from_env()),
+            stale_while_revalidate: Duration::ZERO,
+            not_found_ttl: Duration::from_secs(30),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let store: Arc<dyn CacheStore> = match std::env::var("CACHE_REDIS_URL") {
+            Ok(ref url) if !url.is_empty() => Arc::new(RedisStore::from_env(url)?),
+            _ => Arc::new(MemoryStore::from_env()),
+        };
+
+        Ok(Cache {
+            store,
+            stale_while_revalidate: env_duration("CACHE_STALE_WHILE_REVALIDATE", Duration::ZERO),
+            not_found_ttl: env_duration("CACHE_NOT_FOUND_TTL", Duration::from_secs(30)),
+        })
+    }
+
+    /// Fresh value only
+    pub fn get(&self, namespace: Namespace, key: &str) -> CacheFuture<Option<Arc<Value>>> {
+        let key = key.to_string();
+
+        self.run(None, move |store| {
+            let value = store
+                .lookup(namespace, &key)
+                .filter(|lookup| lookup.expired_for.is_none())
+                .map(|lookup| lookup.value);
+            metrics::record_cache_lookup(namespace.name(), value.is_some());
+            value
+        })
+    }
+
+    /// Value regardless of its TTL, for when the backend can't be asked
+    pub fn get_stale(&self, namespace: Namespace, key: &str) -> CacheFuture<Option<Arc<Value>>> {
+        let key = key.to_string();
+
+        self.run(None, move |store| {
+            let value = store.lookup(namespace, &key).map(|lookup| lookup.value);
+            metrics::record_cache_lookup(namespace.name(), value.is_some());
+            value
+        })
+    }
+
+    /// Value expired within the stale-while-revalidate window,
+    /// it may be served while a fresh one is fetched in the background
+    pub fn get_revalidating(
+        &self,
+        namespace: Namespace,
+        key: &str,
+    ) -> CacheFuture<Option<Arc<Value>>> {
+        let window = self.stale_while_revalidate;
+        if window.is_zero() {
+            return Box::new(future::ok(None));
+        }
+
+        let key = key.to_string();
+        self.run(None, move |store| {
+            store
+                .lookup(namespace, &key)
+                .filter(|lookup| {
+                    lookup
+                        .expired_for
+                        .is_some_and(|expired_for| expired_for <= window)
+                })
+                .map(|lookup| lookup.value)
+        })
+    }
+
+    /// Stored in the background, the caller doesn't wait for remote stores
+    pub fn put(&self, namespace: Namespace, key: String, value: Value, ttl: Option<Duration>) {
+        self.spawn(move |store| store.put(namespace, key, value, ttl))
+    }
+
+    /// Remembers that the backend has no such entity, cached as `null`
+    pub fn put_missing(&self, namespace: Namespace, key: String) {
+        if !self.not_found_ttl.is_zero() {
+            self.put(namespace, key, Value::Null, Some(self.not_found_ttl))
+        }
+    }
+
+    /// Entities the backend has deleted are remembered like missing ones
+    pub fn put_gone(&self, namespace: Namespace, key: String) {
+        if !self.not_found_ttl.is_zero() {
+            self.put(
+                namespace,
+                key,
+                Value::String(GONE.to_string()),
+                Some(self.not_found_ttl),
+            )
+        }
+    }
+
+    /// Whether there was an entry
+    pub fn invalidate(&self, namespace: Namespace, key: &str) -> CacheFuture<bool> {
+        let key = key.to_string();
+
+        self.run(false, move |store| store.invalidate(namespace, &key))
+    }
+
+    pub fn clear(&self, namespace: Namespace) -> CacheFuture<()> {
+        self.run((), move |store| store.clear(namespace))
+    }
+
+    /// Calls the memory store in place and remote ones on the blocking thread pool,
+    /// `fallback` is the answer when the pool is gone
+    fn run<T, F>(&self, fallback: T, call: F) -> CacheFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn CacheStore) -> T + Send + 'static,
+    {
+        if !self.store.remote() {
+            return Box::new(future::ok(call(&*self.store)));
+        }
+
+        let store = self.store.clone();
+        Box::new(
+            web::block(move || Ok::<_, ()>(call(&*store)))
+                .then(|result| Ok(result.unwrap_or(fallback))),
+        )
+    }
+
+    /// Same without waiting for the answer. The call may outlive the request,
+    /// so it is spawned on the current system.
+    fn spawn<F>(&self, call: F)
+    where
+        F: FnOnce(&dyn CacheStore) + Send + 'static,
+    {
+        if !self.store.remote() {
+            return call(&*self.store);
+        }
+
+        actix_rt::spawn(self.run((), call).map_err(|_| ()));
+    }
+}
+
+/// TTL and size settings of a namespace, `None` when its capacity is 0
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub ttl: Duration,
+    pub min_ttl: Duration,
+    pub max_ttl: Duration,
+    pub capacity: std::num::NonZeroUsize,
+    pub max_bytes: usize,
+}
+
+impl Settings {
+    /// `CACHE_{NAMESPACE}_TTL`, `CACHE_{NAMESPACE}_CAPACITY` and `CACHE_{NAMESPACE}_MAX_BYTES`
+    /// configure every namespace, capacity 0 disables it.
+    /// `CACHE_{NAMESPACE}_MIN_TTL` and `CACHE_{NAMESPACE}_MAX_TTL` bound TTLs set by the backend.
+    pub fn from_env(namespace: Namespace) -> Option<Self> {
+        let (ttl, capacity, max_bytes) = namespace.defaults();
+        let prefix = namespace.env_prefix();
+
+        Some(Settings {
+            ttl: env_duration(&format!("{}_TTL", prefix), ttl),
+            min_ttl: env_duration(&format!("{}_MIN_TTL", prefix), MIN_TTL),
+            max_ttl: env_duration(&format!("{}_MAX_TTL", prefix), MAX_TTL),
+            capacity: std::num::NonZeroUsize::new(env_or(
+                &format!("{}_CAPACITY", prefix),
+                capacity,
+            ))?,
+            max_bytes: env_or(&format!("{}_MAX_BYTES", prefix), max_bytes),
+        })
+    }
+
+    /// TTL of an entry with `value`. Not-found entries keep `CACHE_NOT_FOUND_TTL`
+    /// below the minimum, it only bounds TTLs set by the backend.
+    pub fn ttl_for(&self, requested: Option<Duration>, value: &Value) -> Duration {
+        match requested {
+            Some(ttl) if value.is_null() || is_gone(value) => ttl.min(self.max_ttl),
+            Some(ttl) => ttl.max(self.min_ttl).min(self.max_ttl),
+            None => self.ttl,
+        }
+    }
+}
+
+/// In-memory LRU, every replica has its own
+#[derive(Debug)]
+pub struct MemoryStore {
+    stores: HashMap<Namespace, Store>,
+}
+
+#[derive(Debug)]
+struct Store {
+    settings: Settings,
+    inner: Mutex<StoreInner>,
+}
+
+#[derive(Debug)]
+struct StoreInner {
+    entries: LruCache<String, Entry>,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    value: Arc<Value>,
+    expires: Instant,
+    size: usize,
+}
+
+impl MemoryStore {
+    pub fn from_env() -> Self {
+        let stores = Namespace::ALL
+            .iter()
+            .filter_map(|namespace| {
+                let settings = Settings::from_env(*namespace)?;
+
+                Some((
+                    *namespace,
+                    Store {
+                        settings,
+                        inner: Mutex::new(StoreInner {
+                            entries: LruCache::new(settings.capacity),
+                            bytes: 0,
+                        }),
+                    },
+                ))
+            })
+            .collect();
+
+        MemoryStore { stores }
+    }
+}
+
+impl CacheStore for MemoryStore {
+    /// Expired entries are kept until evicted
+    fn lookup(&self, namespace: Namespace, key: &str) -> Option<Lookup> {
+        let store = self.stores.get(&namespace)?;
+        let mut inner = store.inner.lock().unwrap();
+        let now = Instant::now();
+
+        inner.entries.get(key).map(|entry| Lookup {
+            value: entry.value.clone(),
+            expired_for: Some(now.saturating_duration_since(entry.expires))
+                .filter(|_| entry.expires <= now),
+        })
+    }
+
+    fn put(&self, namespace: Namespace, key: String, value: Value, ttl: Option<Duration>) {
+        let store = match self.stores.get(&namespace) {
+            Some(store) => store,
+            None => return,
+        };
+
+        let size = key.len() + value.to_string().len();
+        if size > store.settings.max_bytes {
+            return;
+        }
+
+        let entry = Entry {
+            expires: Instant::now() + store.settings.ttl_for(ttl, &value),
+            value: Arc::new(value),
+            size,
+        };
+
+        let mut inner = store.inner.lock().unwrap();
+        inner.bytes += size;
+
+        if let Some((_, replaced)) = inner.entries.push(key, entry) {
+            inner.bytes -= replaced.size;
+        }
+
+        while inner.bytes > store.settings.max_bytes {
+            match inner.entries.pop_lru() {
+                Some((_, evicted)) => inner.bytes -= evicted.size,
+                None => break,
+            }
+        }
+
+        memory::report_cache(namespace.name(), inner.bytes as u64);
+    }
+
+    fn invalidate(&self, namespace: Namespace, key: &str) -> bool {
+        let store = match self.stores.get(&namespace) {
+            Some(store) => store,
+            None => return false,
+        };
+
+        let mut inner = store.inner.lock().unwrap();
+        match inner.entries.pop(key) {
+            Some(entry) => {
+                inner.bytes -= entry.size;
+                memory::report_cache(namespace.name(), inner.bytes as u64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn clear(&self, namespace: Namespace) {
+        if let Some(store) = self.stores.get(&namespace) {
+            let mut inner = store.inner.lock().unwrap();
+            inner.entries.clear();
+            inner.bytes = 0;
+            memory::report_cache(namespace.name(), 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            ttl: Duration::from_secs(60),
+            min_ttl: MIN_TTL,
+            max_ttl: MAX_TTL,
+            capacity: std::num::NonZeroUsize::new(10).unwrap(),
+            max_bytes: 1024,
+        }
+    }
+
+    #[test]
+    fn ttl_for_clamps_backend_ttls() {
+        let ttl = settings().ttl_for(Some(Duration::from_secs(1)), &serde_json::json!({"id": 1}));
+
+        assert_eq!(ttl, MIN_TTL);
+    }
+
+    #[test]
+    fn ttl_for_keeps_short_not_found_ttls() {
+        let settings = settings();
+        let ttl = Some(Duration::from_secs(2));
+
+        assert_eq!(settings.ttl_for(ttl, &Value::Null), Duration::from_secs(2));
+        assert_eq!(
+            settings.ttl_for(ttl, &Value::from(GONE)),
+            Duration::from_secs(2)
+        );
+    }
+}
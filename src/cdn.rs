@@ -0,0 +1,135 @@
+use serde_json::json;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::metrics;
+
+const FASTLY_API: &str = "https://api.fastly.com";
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// CDN in front of SSI, its copies of a card page are dropped together with the meta cache.
+/// Pages are purged by their surrogate key when they get one, by URL otherwise.
+#[derive(Debug)]
+pub enum CdnPurge {
+    /// `CDN_PURGE_ZONE` is the service id, `CDN_PURGE_TOKEN` an API token with purge scope
+    Fastly { service_id: String, token: String },
+    /// `CDN_PURGE_ZONE` is the zone id, `CDN_PURGE_TOKEN` an API token with Cache Purge permission
+    Cloudflare { zone_id: String, token: String },
+}
+
+impl CdnPurge {
+    /// `CDN_PURGE=fastly` or `cloudflare`, `None` when empty
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let kind = std::env::var("CDN_PURGE")
+            .unwrap_or_default()
+            .to_lowercase();
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("{} is required with CDN_PURGE", name))
+        };
+
+        match kind.as_str() {
+            "" => Ok(None),
+            "fastly" => Ok(Some(CdnPurge::Fastly {
+                service_id: var("CDN_PURGE_ZONE")?,
+                token: var("CDN_PURGE_TOKEN")?,
+            })),
+            "cloudflare" => Ok(Some(CdnPurge::Cloudflare {
+                zone_id: var("CDN_PURGE_ZONE")?,
+                token: var("CDN_PURGE_TOKEN")?,
+            })),
+            _ => Err(format!(
+                "unknown CDN_PURGE {}, expected fastly or cloudflare",
+                kind
+            )),
+        }
+    }
+
+    /// Drops every page of the card at the edge, `false` when the CDN refused.
+    /// Blocks, CDN APIs are HTTPS only.
+    pub fn purge_card(&self, config: &Config, card_id: u32) -> bool {
+        // Pages carry the key along with `CDN_MAX_AGE` only
+        let by_key = config.cdn_max_age.is_some() && config.surrogate_key_header.is_some();
+        let key = card_key(card_id);
+        let urls = card_urls(config, card_id);
+
+        let purged = match self {
+            CdnPurge::Fastly { service_id, token } if by_key => send(
+                ureq::post(&format!(
+                    "{}/service/{}/purge/{}",
+                    FASTLY_API, service_id, key
+                ))
+                .set("fastly-key", token),
+                None,
+            ),
+            // Every URL is purged even if one fails
+            CdnPurge::Fastly { token, .. } => {
+                urls.iter()
+                    .filter(|url| !send(ureq::request("PURGE", url).set("fastly-key", token), None))
+                    .count()
+                    == 0
+            }
+            CdnPurge::Cloudflare { zone_id, token } => send(
+                ureq::post(&format!("{}/zones/{}/purge_cache", CLOUDFLARE_API, zone_id))
+                    .set("authorization", &format!("Bearer {}", token)),
+                Some(if by_key {
+                    json!({ "tags": [key] })
+                } else {
+                    json!({ "files": urls })
+                }),
+            ),
+        };
+
+        if !purged {
+            log::warn!("CDN refused to purge card {}", card_id);
+        }
+        metrics::record_cdn_purge(if purged { "purged" } else { "failed" });
+        purged
+    }
+}
+
+/// Surrogate key of the card pages of every locale
+pub fn card_key(card_id: u32) -> String {
+    format!("card-{}", card_id)
+}
+
+/// Public URLs of the card pages, with and without the trailing slash
+fn card_urls(config: &Config, card_id: u32) -> Vec<String> {
+    std::iter::once(String::new())
+        .chain(
+            config
+                .path_locales
+                .iter()
+                .map(|locale| format!("/{}", locale)),
+        )
+        .flat_map(|prefix| {
+            let url = format!("{}{}/open/{}", config.public_url, prefix, card_id);
+            vec![format!("{}/", url), url]
+        })
+        .collect()
+}
+
+fn send(request: ureq::Request, body: Option<serde_json::Value>) -> bool {
+    let request = request.timeout(TIMEOUT);
+    let response = match body {
+        Some(body) => request
+            .set("content-type", "application/json")
+            .send_string(&body.to_string()),
+        None => request.call(),
+    };
+
+    match response {
+        Ok(_) => true,
+        Err(ureq::Error::Status(status, _)) => {
+            log::warn!("CDN purge answered {}", status);
+            false
+        }
+        Err(err) => {
+            log::warn!("CDN purge failed: {}", err);
+            false
+        }
+    }
+}
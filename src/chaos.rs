@@ -0,0 +1,142 @@
+use actix_web::{error, web, Error};
+use futures::{future, Future};
+use std::time::{Duration, Instant};
+
+/// Fault injection for backend calls. Meant for staging only: lets resilience
+/// features (retries, fallbacks, timeouts) be exercised without breaking the backend.
+///
+/// Disabled unless `CHAOS_RATE` is greater than zero.
+#[derive(Debug)]
+pub struct Chaos {
+    rate: f64,
+    faults: Vec<Fault>,
+    latency: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Delay backend request by `CHAOS_LATENCY_MS`
+    Latency,
+    /// Wait `CHAOS_LATENCY_MS` then fail as if backend timed out
+    Timeout,
+    /// Replace backend body with invalid JSON
+    MalformedJson,
+    /// Cut backend body in half
+    PartialBody,
+}
+
+impl Fault {
+    fn parse(name: &str) -> Option<Fault> {
+        match name.trim() {
+            "latency" => Some(Fault::Latency),
+            "timeout" => Some(Fault::Timeout),
+            "malformed" => Some(Fault::MalformedJson),
+            "partial" => Some(Fault::PartialBody),
+            _ => None,
+        }
+    }
+}
+
+impl Chaos {
+    pub fn from_env() -> Self {
+        use log::warn;
+
+        let rate = std::env::var("CHAOS_RATE")
+            .ok()
+            .and_then(|rate| rate.parse::<f64>().ok())
+            .unwrap_or(0.0)
+            .clamp(0.0, 1.0);
+
+        let faults = std::env::var("CHAOS_FAULTS")
+            .unwrap_or_else(|_| "latency,timeout,malformed,partial".to_string())
+            .split(',')
+            .filter_map(|name| {
+                let fault = Fault::parse(name);
+                if fault.is_none() {
+                    warn!("Unknown fault in CHAOS_FAULTS: {:?}", name);
+                }
+                fault
+            })
+            .collect::<Vec<_>>();
+
+        let latency = std::env::var("CHAOS_LATENCY_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map_or(Duration::from_secs(3), Duration::from_millis);
+
+        let chaos = Chaos {
+            rate,
+            faults,
+            latency,
+        };
+
+        if chaos.enabled() {
+            warn!(
+                "Chaos mode is ON: {:?} injected into {}% of backend requests",
+                chaos.faults,
+                chaos.rate * 100.0
+            );
+        }
+
+        chaos
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.rate > 0.0 && !self.faults.is_empty()
+    }
+
+    /// Roll the dice for a single backend request
+    pub fn pick(&self) -> Option<Fault> {
+        use rand::seq::SliceRandom;
+        use rand::Rng;
+
+        if !self.enabled() {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f64>() < self.rate {
+            self.faults.choose(&mut rng).cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Resolves when backend request may be sent
+    pub fn before_request(
+        &self,
+        fault: Option<Fault>,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        use tokio_timer::Delay;
+
+        let deadline = Instant::now() + self.latency;
+
+        match fault {
+            Some(Fault::Latency) => {
+                Box::new(Delay::new(deadline).map_err(error::ErrorInternalServerError))
+            }
+            Some(Fault::Timeout) => Box::new(
+                Delay::new(deadline)
+                    .map_err(error::ErrorInternalServerError)
+                    .and_then(|_| {
+                        Err(error::ErrorGatewayTimeout(
+                            "chaos: injected backend timeout",
+                        ))
+                    }),
+            ),
+            _ => Box::new(future::ok(())),
+        }
+    }
+
+    /// Mangles backend response body
+    pub fn corrupt_body(fault: Option<Fault>, body: web::BytesMut) -> web::BytesMut {
+        match fault {
+            Some(Fault::MalformedJson) => web::BytesMut::from(&b"{\"ok\": tru"[..]),
+            Some(Fault::PartialBody) => {
+                let half = body.len() / 2;
+                web::BytesMut::from(&body[..half])
+            }
+            _ => body,
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+/// Injects meta tags of cards, users and collections into the frontend template.
+/// Configured with environment variables (or `.env`), see `ssi config-schema`.
+#[derive(Debug, Parser)]
+#[command(name = "ssi", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the server (default)
+    Serve,
+    /// Print JSON Schema of every configuration option
+    ConfigSchema,
+    /// Print shell completion script, e.g. `ssi completions bash > /etc/bash_completion.d/ssi`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+pub fn print_completions(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
@@ -0,0 +1,178 @@
+use actix_web::body::{Body, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::web::Bytes;
+use actix_web::Error;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use futures::Future;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::storage::{PageBody, Templates};
+
+/// Member header: deflate, no name or time, unknown OS
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Template up to the title and from the end of the head, deflated once it is loaded.
+/// Pages made of the template are gzipped by deflating what is between them only:
+/// independently deflated parts joined with sync flushes are one valid deflate stream.
+#[derive(Debug)]
+pub struct Precompressed {
+    prefix: Part,
+    suffix: Part,
+}
+
+#[derive(Debug)]
+struct Part {
+    text: Bytes,
+    deflated: Vec<u8>,
+    crc: Crc,
+}
+
+impl Part {
+    fn new(text: &Bytes, last: bool) -> Self {
+        let mut crc = Crc::new();
+        crc.update(text);
+
+        Part {
+            text: text.clone(),
+            deflated: deflate(text, Compression::best(), last),
+            crc,
+        }
+    }
+}
+
+impl Precompressed {
+    pub fn new(prefix: &Bytes, suffix: &Bytes) -> Self {
+        Precompressed {
+            prefix: Part::new(prefix, false),
+            suffix: Part::new(suffix, true),
+        }
+    }
+
+    /// Gzipped page, `None` when it isn't made of the template
+    pub fn gzip(&self, page: &PageBody) -> Option<Vec<u8>> {
+        let (prefix, suffix) = (&self.prefix.text, &self.suffix.text);
+        let middle = match page.chunks() {
+            // Put together of the template parts, what is between them is per page
+            [first, middle @ .., last] if first == prefix && last == suffix => {
+                Cow::Owned(middle.concat())
+            }
+            [page]
+                if page.len() >= prefix.len() + suffix.len()
+                    && page.starts_with(prefix)
+                    && page.ends_with(suffix) =>
+            {
+                Cow::Borrowed(&page[prefix.len()..page.len() - suffix.len()])
+            }
+            _ => return None,
+        };
+
+        let mut crc = Crc::new();
+        crc.combine(&self.prefix.crc);
+        crc.update(&middle);
+        crc.combine(&self.suffix.crc);
+        let middle = deflate(&middle, Compression::fast(), false);
+
+        let mut gzip = Vec::with_capacity(
+            GZIP_HEADER.len()
+                + self.prefix.deflated.len()
+                + middle.len()
+                + self.suffix.deflated.len()
+                + 8,
+        );
+        gzip.extend_from_slice(&GZIP_HEADER);
+        gzip.extend_from_slice(&self.prefix.deflated);
+        gzip.extend_from_slice(&middle);
+        gzip.extend_from_slice(&self.suffix.deflated);
+        gzip.extend_from_slice(&crc.sum().to_le_bytes());
+        gzip.extend_from_slice(&(page.len() as u32).to_le_bytes());
+        Some(gzip)
+    }
+}
+
+/// Raw deflate of `data`, ending with a sync flush unless it is the `last` part of a stream
+fn deflate(data: &[u8], level: Compression, last: bool) -> Vec<u8> {
+    let mut compress = Compress::new(level, false);
+    let mut output = Vec::with_capacity(data.len() / 3 + 64);
+    let flush = if last {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Sync
+    };
+
+    loop {
+        let input = &data[compress.total_in() as usize..];
+        let status = compress
+            .compress_vec(input, &mut output, flush)
+            .expect("deflate of a buffer can't fail");
+
+        let done = if last {
+            status == Status::StreamEnd
+        } else {
+            compress.total_in() as usize == data.len() && output.len() < output.capacity()
+        };
+        if done {
+            return output;
+        }
+        output.reserve(output.capacity().max(1024));
+    }
+}
+
+/// Pages made of the template are gzipped from its pre-compressed parts when the client takes gzip,
+/// everything else is left to the `Compress` middleware around it
+pub fn precompressed<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let templates = req
+        .app_data::<Arc<Templates>>()
+        .filter(|_| accepts_gzip(req.headers()))
+        .map(|templates| templates.current());
+
+    Box::new(srv.call(req).map(move |res| {
+        let storage = match templates {
+            Some(ref storage) if !res.headers().contains_key(header::CONTENT_ENCODING) => storage,
+            _ => return res,
+        };
+        let gzip = match (storage.precompressed.as_ref(), PageBody::of(res.response())) {
+            (Some(precompressed), Some(ref page)) => precompressed.gzip(page),
+            _ => None,
+        };
+
+        match gzip {
+            Some(gzip) => res.map_body(|head, _| {
+                head.headers
+                    .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+                head.headers
+                    .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+                head.extensions_mut().remove::<PageBody>();
+                ResponseBody::Body(Body::Bytes(Bytes::from(gzip)))
+            }),
+            None => res,
+        }
+    }))
+}
+
+/// `gzip` or `*` of `Accept-Encoding` without `q=0`
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (name == "gzip" || name == "x-gzip" || name == "*") && !rejected
+        })
+}
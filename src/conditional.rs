@@ -0,0 +1,150 @@
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue, HttpDate};
+use actix_web::http::{Method, StatusCode};
+use actix_web::HttpResponse;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::backend::Card;
+use crate::config::Config;
+use crate::images::version_hash;
+use crate::storage::Storage;
+
+/// `ETag` and `Last-Modified` of a page: it changes with the entity and with the template
+#[derive(Debug)]
+pub struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+    cdn: Vec<(HeaderName, HeaderValue)>,
+    vary: Option<HeaderValue>,
+}
+
+impl Validators {
+    /// `variant` tells apart pages of the same card, like locales
+    pub fn for_card(storage: &Storage, card: &Card, variant: &str) -> Self {
+        let etag = format!(
+            "\"{}\"",
+            version_hash(&format!(
+                "{}\n{}\n{}\n{}",
+                card.id, card.updated_at, storage.hash, variant
+            ))
+        );
+        let last_modified = humantime::parse_rfc3339_weak(&card.updated_at)
+            .ok()
+            .map(|updated_at| updated_at.max(storage.loaded_at))
+            .map(whole_seconds);
+
+        Validators {
+            etag,
+            last_modified,
+            cdn: Vec::new(),
+            vary: None,
+        }
+    }
+
+    /// Page and its 304s may be cached by a CDN, see `cdn_headers`
+    pub fn cdn(mut self, config: &Config, surrogate_key: &str) -> Self {
+        self.cdn = cdn_headers(config, surrogate_key);
+        self
+    }
+
+    /// Request headers the page depends on besides its URL,
+    /// a CDN or browser must not give the page to a client that differs by them
+    pub fn vary(mut self, headers: &[HeaderName]) -> Self {
+        let names = headers
+            .iter()
+            .map(HeaderName::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.vary = HeaderValue::from_str(&names)
+            .ok()
+            .filter(|_| !names.is_empty());
+        self
+    }
+
+    /// Client's copy is still valid. `If-None-Match` wins over `If-Modified-Since`,
+    /// only GET and HEAD are conditional.
+    pub fn fresh(&self, method: &Method, headers: &HeaderMap) -> bool {
+        if method != Method::GET && method != Method::HEAD {
+            return false;
+        }
+
+        if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+            return value.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag)
+            });
+        }
+
+        match (self.last_modified, headers.get(header::IF_MODIFIED_SINCE)) {
+            (Some(last_modified), Some(value)) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<HttpDate>().ok())
+                .is_some_and(|since| SystemTime::from(since) >= last_modified),
+            _ => false,
+        }
+    }
+
+    /// Validators of successful pages, errors are not cached by clients
+    pub fn apply(&self, response: &mut HttpResponse) {
+        if response.status() == StatusCode::OK {
+            self.insert(response.headers_mut());
+        }
+    }
+
+    pub fn not_modified(&self) -> HttpResponse {
+        let mut response = HttpResponse::NotModified().finish();
+        self.insert(response.headers_mut());
+        response
+    }
+
+    fn insert(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.cdn {
+            headers.insert(name.clone(), value.clone());
+        }
+        if let Some(ref vary) = self.vary {
+            headers.append(header::VARY, vary.clone());
+        }
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(value) = HeaderValue::from_str(&HttpDate::from(last_modified).to_string()) {
+                headers.insert(header::LAST_MODIFIED, value);
+            }
+        }
+    }
+}
+
+/// `Cache-Control` with `CDN_MAX_AGE` for shared caches and the surrogate key to purge
+/// the page by, nothing without `CDN_MAX_AGE`. Only pages of fetched entities are cacheable,
+/// fallbacks would stay at the edge after the backend is back.
+fn cdn_headers(config: &Config, surrogate_key: &str) -> Vec<(HeaderName, HeaderValue)> {
+    let max_age = match config.cdn_max_age {
+        Some(max_age) => max_age,
+        None => return Vec::new(),
+    };
+
+    let mut headers = vec![(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!(
+            "public, max-age=0, s-maxage={}",
+            max_age.as_secs()
+        ))
+        .expect("valid cache-control"),
+    )];
+    if let (Some(name), Ok(value)) = (
+        config.surrogate_key_header.as_ref(),
+        HeaderValue::from_str(surrogate_key),
+    ) {
+        headers.push((name.clone(), value));
+    }
+    headers
+}
+
+/// HTTP dates have no fractions, a page modified at 10:00:00.5 is not newer than 10:00:00
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| UNIX_EPOCH + Duration::from_secs(since.as_secs()))
+        .unwrap_or(time)
+}
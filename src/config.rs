@@ -0,0 +1,295 @@
+use actix_web::http::HeaderName;
+use std::time::Duration;
+
+use crate::cdn::CdnPurge;
+use crate::images;
+use crate::locale::Localized;
+use crate::meta::{twitter_handle, CARD_TEMPLATE};
+
+#[derive(Debug)]
+pub struct Config {
+    pub public_url: String,
+    pub image_url: String,
+    pub backend_url: String,
+    pub sitename: Localized,
+    /// Locales of `/{locale}/open/{card_id}` pages, lowercase
+    pub path_locales: Vec<String>,
+    /// Description of pages without an entity, plain or per locale like `sitename`
+    pub site_description: Option<Localized>,
+    /// Preview of pages without an entity, absolute or relative to `public_url`
+    pub site_image: Option<String>,
+    /// Preview of cards without their own preview and author avatar, same form as `site_image`
+    pub default_og_image: Option<String>,
+    /// Card previews are checked with a HEAD request before they are put into meta
+    pub preview_check: bool,
+    /// Card previews are served through `/img/{size}/` cropped to social card sizes
+    pub image_proxy: bool,
+    /// `OG_IMAGE_FONT`, cards without a preview get a generated image with their title
+    pub social_card: Option<images::SocialCard>,
+    /// `@handle` of the site for `twitter:site`
+    pub twitter_site: Option<String>,
+    pub index_html_path: String,
+    /// Non-production deployment, kept out of search engines
+    pub staging: bool,
+    /// `NOINDEX`, on with `STAGING` as well: every response gets `X-Robots-Tag`
+    pub noindex: bool,
+    /// Content of `ROBOTS_TXT`, the built-in one allows everything
+    pub robots_txt: Option<String>,
+    /// Responses are gzip or brotli encoded for clients that accept it
+    pub compression: bool,
+    /// `s-maxage` of entity pages for a CDN in front, pages aren't public without it
+    pub cdn_max_age: Option<Duration>,
+    /// `Surrogate-Key` of Fastly or `Cache-Tag` of Cloudflare, pages are purged by it
+    pub surrogate_key_header: Option<HeaderName>,
+    /// `CDN_PURGE`, card pages are purged at the edge along with the meta cache
+    pub cdn_purge: Option<CdnPurge>,
+    pub admin_token: Option<String>,
+    pub hooks_token: Option<String>,
+    pub card_id_max_length: usize,
+    pub acme_challenge_dir: Option<String>,
+    pub feed_url: Option<String>,
+    /// `{id}` is replaced with the user id
+    pub user_feed_url: Option<String>,
+    pub feed_type: String,
+    /// Frontend search with `{search_term_string}` placeholder
+    pub search_url: Option<String>,
+    pub related_link_tags: bool,
+    /// Tags with fewer cards are kept out of search results
+    pub tag_min_cards: u64,
+    /// Pages with `BreadcrumbList` structured data: `card`, `collection`, `tag`
+    pub breadcrumbs: Vec<String>,
+    /// Global the fetched card is put into for the frontend, `window.{name}`
+    pub card_state_var: Option<String>,
+    /// Crawlers get the card text rendered into `#root`
+    pub render_card_body: bool,
+    /// `CARD_META_TEMPLATE`, replaces the built-in card meta
+    pub card_meta_template: Option<tera::Tera>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            public_url: std::env::var("PUBLIC_URL").expect("please, provide PUBLIC_URL"),
+            image_url: std::env::var("IMAGE_URL").expect("please, provide IMAGE_URL"),
+            backend_url: std::env::var("BACKEND_URL").expect("please, provide BACKEND_URL"),
+            sitename: Localized::parse(
+                &std::env::var("SITENAME").expect("please, provide SITENAME"),
+            )
+            .expect("invalid SITENAME"),
+            path_locales: std::env::var("PATH_LOCALES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|locale| locale.trim().to_lowercase())
+                .filter(|locale| !locale.is_empty())
+                .inspect(|locale| {
+                    assert!(
+                        locale
+                            .bytes()
+                            .all(|byte| byte.is_ascii_alphabetic() || byte == b'-'),
+                        "PATH_LOCALES must be a comma separated list of locales like en,ru"
+                    )
+                })
+                .collect(),
+            site_description: std::env::var("SITE_DESCRIPTION")
+                .ok()
+                .filter(|description| !description.is_empty())
+                .map(|description| {
+                    Localized::parse(&description).expect("invalid SITE_DESCRIPTION")
+                }),
+            site_image: std::env::var("SITE_IMAGE")
+                .ok()
+                .filter(|image| !image.is_empty()),
+            default_og_image: std::env::var("DEFAULT_OG_IMAGE")
+                .ok()
+                .filter(|image| !image.is_empty()),
+            preview_check: crate::flags::env_flag("PREVIEW_CHECK"),
+            image_proxy: crate::flags::env_flag("IMAGE_PROXY"),
+            social_card: std::env::var("OG_IMAGE_FONT")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(|path| load_social_card(&path).expect("invalid OG_IMAGE_FONT")),
+            twitter_site: match std::env::var("TWITTER_SITE") {
+                Ok(handle) if handle.is_empty() => None,
+                Ok(handle) => Some(twitter_handle(&handle).expect("invalid TWITTER_SITE")),
+                Err(_) => Some("@howtocards_io".to_string()),
+            },
+            index_html_path: std::env::var("INDEX_HTML_PATH")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .or_else(|| std::env::var("INDEX_HTML_URL").ok())
+                .expect("please, provide INDEX_HTML_PATH or INDEX_HTML_URL"),
+            staging: crate::flags::env_flag("STAGING"),
+            noindex: crate::flags::env_flag("NOINDEX") || crate::flags::env_flag("STAGING"),
+            robots_txt: std::env::var("ROBOTS_TXT")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(|path| std::fs::read_to_string(path).expect("cannot read ROBOTS_TXT")),
+            compression: std::env::var("COMPRESSION")
+                .map_or(true, |_| crate::flags::env_flag("COMPRESSION")),
+            cdn_max_age: Some(env_duration("CDN_MAX_AGE", Duration::from_secs(0)))
+                .filter(|max_age| !max_age.is_zero()),
+            surrogate_key_header: match std::env::var("SURROGATE_KEY_HEADER") {
+                Ok(name) if name.is_empty() => None,
+                Ok(name) => Some(
+                    HeaderName::from_bytes(name.as_bytes()).expect("invalid SURROGATE_KEY_HEADER"),
+                ),
+                Err(_) => Some(HeaderName::from_static("surrogate-key")),
+            },
+            cdn_purge: CdnPurge::from_env().expect("invalid CDN_PURGE"),
+            admin_token: std::env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            hooks_token: std::env::var("HOOKS_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            card_id_max_length: env_or("CARD_ID_MAX_LENGTH", 10),
+            acme_challenge_dir: std::env::var("ACME_CHALLENGE_DIR")
+                .ok()
+                .filter(|dir| !dir.is_empty()),
+            feed_url: std::env::var("FEED_URL").ok().filter(|url| !url.is_empty()),
+            user_feed_url: std::env::var("USER_FEED_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            feed_type: env_or("FEED_TYPE", "application/rss+xml".to_string()),
+            search_url: match std::env::var("SEARCH_URL") {
+                Ok(url) => Some(url).filter(|url| !url.is_empty()),
+                Err(_) => Some("/search?q={search_term_string}".to_string()),
+            },
+            related_link_tags: crate::flags::env_flag("RELATED_LINK_TAGS"),
+            tag_min_cards: env_or("TAG_MIN_CARDS", 3),
+            breadcrumbs: env_or("BREADCRUMBS", "card,collection,tag".to_string())
+                .split(',')
+                .map(|page| page.trim().to_lowercase())
+                .filter(|page| !page.is_empty())
+                .collect(),
+            card_state_var: match std::env::var("CARD_STATE_VAR") {
+                Ok(name) if name.is_empty() => None,
+                Ok(name) => {
+                    assert!(
+                        is_identifier(&name),
+                        "CARD_STATE_VAR must be a JavaScript identifier"
+                    );
+                    Some(name)
+                }
+                Err(_) => Some("__CARD_META__".to_string()),
+            },
+            render_card_body: crate::flags::env_flag("RENDER_CARD_BODY"),
+            card_meta_template: std::env::var("CARD_META_TEMPLATE")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(|path| load_template(&path).expect("invalid CARD_META_TEMPLATE")),
+        }
+    }
+
+    /// Pages without an entity are negotiated against locales of the site texts
+    pub fn site_locales(&self) -> impl Iterator<Item = &str> {
+        self.sitename.locales().chain(
+            self.site_description
+                .iter()
+                .flat_map(|description| description.locales()),
+        )
+    }
+}
+
+/// Values are HTML-escaped unless marked `| safe`
+fn load_template(path: &str) -> Result<tera::Tera, String> {
+    let source = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+
+    let mut tera = tera::Tera::default();
+    tera.add_raw_template(CARD_TEMPLATE, &source)
+        .map_err(|err| format!("{}: {:?}", path, err))?;
+    tera.autoescape_on(vec![CARD_TEMPLATE]);
+    // Same escaping as the built-in tags, URLs keep their slashes
+    tera.set_escape_fn(htmlescape::encode_minimal);
+    Ok(tera)
+}
+
+fn load_social_card(font: &str) -> Result<images::SocialCard, String> {
+    let background = std::env::var("OG_IMAGE_BACKGROUND")
+        .ok()
+        .filter(|path| !path.is_empty());
+    let color = env_or("OG_IMAGE_COLOR", "#1b1b1f".to_string());
+    let color = parse_color(&color).ok_or_else(|| format!("invalid OG_IMAGE_COLOR {}", color))?;
+
+    images::SocialCard::load(font, background.as_deref(), color)
+}
+
+/// `#rrggbb`
+fn parse_color(color: &str) -> Option<image::Rgb<u8>> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let channel = |range| u8::from_str_radix(hex.get(range)?, 16).ok();
+    Some(image::Rgb([channel(0..2)?, channel(2..4)?, channel(4..6)?]))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == '$')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '$')
+}
+
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn env_duration(name: &str, default: Duration) -> Duration {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| humantime::parse_duration(&value).ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+impl Config {
+    /// Defaults of `from_env` without reading the environment, tests run in parallel
+    pub fn example() -> Self {
+        Config {
+            public_url: "https://howtocards.io".to_string(),
+            image_url: "https://images.howtocards.io".to_string(),
+            backend_url: "http://backend".to_string(),
+            sitename: Localized::parse("Howtocards").unwrap(),
+            path_locales: Vec::new(),
+            site_description: None,
+            site_image: None,
+            default_og_image: None,
+            preview_check: false,
+            image_proxy: false,
+            social_card: None,
+            twitter_site: Some("@howtocards_io".to_string()),
+            index_html_path: "index.html".to_string(),
+            staging: false,
+            noindex: false,
+            robots_txt: None,
+            compression: true,
+            cdn_max_age: None,
+            surrogate_key_header: Some(HeaderName::from_static("surrogate-key")),
+            cdn_purge: None,
+            admin_token: None,
+            hooks_token: None,
+            card_id_max_length: 10,
+            acme_challenge_dir: None,
+            feed_url: None,
+            user_feed_url: None,
+            feed_type: "application/rss+xml".to_string(),
+            search_url: Some("/search?q={search_term_string}".to_string()),
+            related_link_tags: false,
+            tag_min_cards: 3,
+            breadcrumbs: vec![
+                "card".to_string(),
+                "collection".to_string(),
+                "tag".to_string(),
+            ],
+            card_state_var: Some("__CARD_META__".to_string()),
+            render_card_body: false,
+            card_meta_template: None,
+        }
+    }
+}
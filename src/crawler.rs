@@ -0,0 +1,43 @@
+use actix_web::http::HeaderMap;
+use lazy_static::lazy_static;
+
+/// User-Agent substrings of search engines and link preview bots
+const DEFAULT_PATTERNS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "facebookexternalhit",
+    "facebookcatalog",
+    "embedly",
+    "whatsapp",
+    "skypeuripreview",
+    "vkshare",
+    "pinterest",
+    "preview",
+];
+
+lazy_static! {
+    /// `CRAWLER_USER_AGENTS`, comma separated and case-insensitive
+    static ref PATTERNS: Vec<String> = match std::env::var("CRAWLER_USER_AGENTS") {
+        Ok(patterns) if !patterns.trim().is_empty() => patterns
+            .split(',')
+            .map(|pattern| pattern.trim().to_lowercase())
+            .filter(|pattern| !pattern.is_empty())
+            .collect(),
+        _ => DEFAULT_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+    };
+}
+
+/// Request comes from a bot that reads meta tags rather than a browser that runs the SPA
+pub fn is_crawler(headers: &HeaderMap) -> bool {
+    headers
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|user_agent| {
+            let user_agent = user_agent.to_lowercase();
+            PATTERNS
+                .iter()
+                .any(|pattern| user_agent.contains(pattern.as_str()))
+        })
+}
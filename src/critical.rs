@@ -0,0 +1,78 @@
+/// Critical CSS goes here when the template has this comment,
+/// otherwise before the first stylesheet link or before the injection marker
+pub const MARKER: &str = "<!-- critical-css -->";
+
+/// Inlines `css` into the template head. With `defer` stylesheet links are loaded
+/// without blocking the first paint: `media="print"` swapped to `all` once loaded,
+/// with the original link kept in `<noscript>`.
+pub fn inline(html: &str, css: &str, defer: bool, fallback: &str) -> String {
+    let style = format!(
+        "<style>{}</style>",
+        css.trim().replace("</style", "<\\/style")
+    );
+
+    let html = if defer {
+        defer_stylesheets(html)
+    } else {
+        html.to_string()
+    };
+
+    let position = html
+        .find(MARKER)
+        .or_else(|| stylesheet_links(&html).first().map(|&(start, _)| start))
+        .or_else(|| html.find(fallback));
+
+    match position {
+        Some(position) => format!("{}{}{}", &html[..position], style, &html[position..]),
+        None => html,
+    }
+}
+
+fn defer_stylesheets(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut last = 0;
+
+    for (start, end) in stylesheet_links(html) {
+        let tag = &html[start..end];
+        result.push_str(&html[last..start]);
+
+        if tag.to_ascii_lowercase().contains("media=") {
+            result.push_str(tag);
+        } else {
+            let attributes = tag.trim_end_matches('>').trim_end_matches('/').trim_end();
+            result.push_str(&format!(
+                "{} media=\"print\" onload=\"this.media='all'\"><noscript>{}</noscript>",
+                attributes, tag
+            ));
+        }
+
+        last = end;
+    }
+
+    result.push_str(&html[last..]);
+    result
+}
+
+/// Byte ranges of `<link rel="stylesheet" ...>` tags
+fn stylesheet_links(html: &str) -> Vec<(usize, usize)> {
+    let lower = html.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut from = 0;
+
+    while let Some(offset) = lower[from..].find("<link") {
+        let start = from + offset;
+        let end = match lower[start..].find('>') {
+            Some(length) => start + length + 1,
+            None => break,
+        };
+
+        let tag = lower[start..end].replace(['"', '\''], "");
+        if tag.contains("rel=stylesheet") {
+            links.push((start, end));
+        }
+
+        from = end;
+    }
+
+    links
+}
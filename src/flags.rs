@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Runtime switches that operators can flip without restarting the service.
+#[derive(Debug, Default)]
+pub struct Flags {
+    degraded: AtomicBool,
+}
+
+impl Flags {
+    pub fn from_env() -> Self {
+        Flags {
+            degraded: AtomicBool::new(env_flag("DEGRADED_MODE")),
+        }
+    }
+
+    /// When degraded, the service never calls the backend and serves cached or default meta only.
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn set_degraded(&self, value: bool) {
+        self.degraded.store(value, Ordering::Relaxed);
+    }
+}
+
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| {
+            matches!(
+                value.trim().to_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+        .unwrap_or(false)
+}
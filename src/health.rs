@@ -0,0 +1,145 @@
+use actix_web::{web, HttpResponse};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{env_duration, env_or};
+use crate::metrics;
+
+const BUCKET: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref BACKEND: ErrorRate = ErrorRate::from_env();
+}
+
+/// Rolling rate of failed backend requests.
+/// The instance is not ready while more than `READY_ERROR_RATE` of backend requests
+/// failed during the last `READY_ERROR_WINDOW`, given at least `READY_MIN_REQUESTS` were sent,
+/// so the orchestrator shifts traffic to replicas that can reach the backend.
+#[derive(Debug)]
+struct ErrorRate {
+    threshold: f64,
+    window: Duration,
+    min_requests: u64,
+    buckets: Mutex<VecDeque<Bucket>>,
+    ready: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    started: Instant,
+    total: u64,
+    failed: u64,
+}
+
+impl ErrorRate {
+    fn from_env() -> Self {
+        ErrorRate {
+            threshold: env_or("READY_ERROR_RATE", 0.5),
+            window: env_duration("READY_ERROR_WINDOW", Duration::from_secs(60)),
+            min_requests: env_or("READY_MIN_REQUESTS", 20),
+            buckets: Mutex::new(VecDeque::new()),
+            ready: AtomicBool::new(true),
+        }
+    }
+
+    fn record(&self, ok: bool) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        while buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.started) > self.window)
+        {
+            buckets.pop_front();
+        }
+
+        let fresh = buckets
+            .back()
+            .is_some_and(|bucket| now.duration_since(bucket.started) < BUCKET);
+
+        if !fresh {
+            buckets.push_back(Bucket {
+                started: now,
+                total: 0,
+                failed: 0,
+            });
+        }
+
+        if let Some(bucket) = buckets.back_mut() {
+            bucket.total += 1;
+            if !ok {
+                bucket.failed += 1;
+            }
+        }
+    }
+
+    /// Requests and failures during the window
+    fn totals(&self) -> (u64, u64) {
+        let now = Instant::now();
+
+        self.buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|bucket| now.duration_since(bucket.started) <= self.window)
+            .fold((0, 0), |(total, failed), bucket| {
+                (total + bucket.total, failed + bucket.failed)
+            })
+    }
+}
+
+/// Backend connectivity: transport errors and 5xx are failures, 4xx are not
+pub fn record_backend(ok: bool) {
+    BACKEND.record(ok);
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics::resource("/readyz").route(web::get().to(readyz)));
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    ready: bool,
+    backend_requests: u64,
+    backend_errors: u64,
+}
+
+fn readyz() -> HttpResponse {
+    use log::{info, warn};
+
+    let (total, failed) = BACKEND.totals();
+    let rate = if total > 0 {
+        failed as f64 / total as f64
+    } else {
+        0.0
+    };
+    let ready = total < BACKEND.min_requests || rate <= BACKEND.threshold;
+
+    if BACKEND.ready.swap(ready, Ordering::Relaxed) != ready {
+        if ready {
+            info!("Backend error rate is back to normal, instance is ready");
+        } else {
+            warn!(
+                "{} of {} backend requests failed during {:?}, instance is not ready",
+                failed, total, BACKEND.window
+            );
+        }
+    }
+
+    let body = Readiness {
+        ready,
+        backend_requests: total,
+        backend_errors: failed,
+    };
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
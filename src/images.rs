@@ -0,0 +1,437 @@
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use actix_web::http::StatusCode;
+use actix_web::{client::Client, Error};
+use futures::{future, Future};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use serde_json::Value;
+use siphasher::sip::SipHasher;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::{Card, Fetched};
+use crate::cache::{Cache, Namespace};
+use crate::config::Config;
+use crate::metrics;
+
+/// Size of generated social images, recommended by Facebook and Twitter
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+/// Twitter shows large cards smaller, in about the same ratio
+pub const TWITTER_WIDTH: u32 = 800;
+pub const TWITTER_HEIGHT: u32 = 418;
+/// Sizes the image proxy crops to, `{width}x{height}` in its URLs
+const PROXY_SIZES: [(u32, u32); 2] = [(WIDTH, HEIGHT), (TWITTER_WIDTH, TWITTER_HEIGHT)];
+
+const MAX_SOURCE_SIZE: usize = 10 * 1024 * 1024;
+/// Slow image server must not hold the page for long, the preview is kept then
+const PREVIEW_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const BACKGROUND: Rgb<u8> = Rgb([0xf4, 0xf4, 0xf6]);
+/// Text of social cards keeps this distance from the image edges
+const PADDING: f32 = 80.0;
+const TITLE_SIZE: f32 = 72.0;
+const TITLE_MAX_LINES: usize = 4;
+const AUTHOR_SIZE: f32 = 40.0;
+
+/// Generated images are kept on disk, so they survive restarts and don't use memory.
+/// Keys must include everything the image depends on (like `updated_at`),
+/// so entries never need invalidation.
+#[derive(Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+}
+
+impl ImageCache {
+    pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(ImageCache { dir })
+    }
+
+    pub fn from_env() -> std::io::Result<Self> {
+        Self::new(
+            std::env::var("IMAGE_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| std::env::temp_dir().join("howtocards-ssi")),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let bytes = std::fs::read(self.dir.join(key)).ok();
+        metrics::record_cache_lookup("image", bytes.is_some());
+        bytes
+    }
+
+    pub fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+        // write then rename, so readers never see a half written file.
+        // Other requests and instances sharing the dir may write the same key at the same time.
+        let temporary = self.dir.join(format!(
+            ".{}.{}.{}.tmp",
+            key,
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = std::fs::write(&temporary, bytes)
+            .and_then(|_| std::fs::rename(&temporary, self.dir.join(key)));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+        written
+    }
+}
+
+/// Short stable hash to put versions like `updated_at` into cache keys and urls.
+/// SipHash with fixed keys over the bytes, so it is the same across builds and Rust releases.
+pub fn version_hash(value: &str) -> String {
+    let mut hasher = SipHasher::new();
+    hasher.write(value.as_bytes());
+    format!("{:x}", hasher.finish())
+}
+
+/// Everything the generated image of a card depends on,
+/// the author may be renamed without the card being updated
+pub fn card_version(card: &Card) -> String {
+    version_hash(&format!(
+        "{}\n{}\n{}",
+        card.updated_at,
+        card.title,
+        card.author
+            .as_ref()
+            .and_then(|author| author.display_name.as_deref())
+            .unwrap_or_default()
+    ))
+}
+
+/// Downloads and decodes an image. Resolves to `None` on any failure,
+/// a missing picture should not break the whole image.
+pub fn fetch(
+    client: &Client,
+    url: String,
+) -> impl Future<Item = Option<DynamicImage>, Error = Error> {
+    use log::warn;
+
+    client
+        .get(&url)
+        .send()
+        .map_err(Error::from)
+        .and_then(|mut resp| resp.body().limit(MAX_SOURCE_SIZE).map_err(Error::from))
+        .map(|body| image::load_from_memory(&body).ok())
+        .or_else(move |err| {
+            warn!("Failed to fetch image {}: {}", url, err);
+            Ok(None)
+        })
+}
+
+/// With `PREVIEW_CHECK` a preview the image server answers 404 or 410 for is dropped,
+/// so the card falls back to the author avatar or `DEFAULT_OG_IMAGE`.
+/// Answers are cached by URL, failed checks keep the preview and aren't cached.
+pub fn check_preview(
+    client: &Client,
+    cache: &Arc<Cache>,
+    config: &Config,
+    fetched: Fetched<Card>,
+) -> Box<dyn Future<Item = Fetched<Card>, Error = Error>> {
+    let url = match fetched.entity {
+        Some(Card {
+            preview_url: Some(ref preview_url),
+            ..
+        }) if config.preview_check => config.image_url_for(preview_url),
+        _ => return Box::new(future::ok(fetched)),
+    };
+
+    let (client, cache) = (client.clone(), cache.clone());
+    Box::new(cache.get(Namespace::Preview, &url).and_then(
+        move |exists| -> Box<dyn Future<Item = Fetched<Card>, Error = Error>> {
+            match exists {
+                Some(exists) => Box::new(future::ok(drop_missing(
+                    fetched,
+                    exists.as_bool() != Some(false),
+                ))),
+                None => Box::new(check_missing(&client, cache, url, fetched)),
+            }
+        },
+    ))
+}
+
+/// HEAD request to the preview, its answer is cached
+fn check_missing(
+    client: &Client,
+    cache: Arc<Cache>,
+    url: String,
+    fetched: Fetched<Card>,
+) -> impl Future<Item = Fetched<Card>, Error = Error> {
+    use log::warn;
+
+    client
+        .head(&url)
+        .timeout(PREVIEW_CHECK_TIMEOUT)
+        .send()
+        .then(move |response| {
+            let exists = match response {
+                Ok(response) => {
+                    let missing = response.status() == StatusCode::NOT_FOUND
+                        || response.status() == StatusCode::GONE;
+                    if missing {
+                        warn!("Preview {} is missing, the fallback image is used", url);
+                    }
+                    cache.put(Namespace::Preview, url, Value::Bool(!missing), None);
+                    !missing
+                }
+                Err(err) => {
+                    warn!("Failed to check preview {}: {}", url, err);
+                    true
+                }
+            };
+
+            Ok(drop_missing(fetched, exists))
+        })
+}
+
+fn drop_missing(mut fetched: Fetched<Card>, exists: bool) -> Fetched<Card> {
+    if !exists {
+        if let Some(ref mut card) = fetched.entity {
+            card.preview_url = None;
+        }
+    }
+    fetched
+}
+
+/// Composes up to four pictures into a social image:
+/// one fills the whole image, two are put side by side, more make a 2×2 grid.
+pub fn collage(pictures: &[DynamicImage]) -> RgbImage {
+    let mut canvas = RgbImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+
+    let cells: &[(u32, u32, u32, u32)] = match pictures.len() {
+        0 => &[],
+        1 => &[(0, 0, WIDTH, HEIGHT)],
+        2 => &[(0, 0, WIDTH / 2, HEIGHT), (WIDTH / 2, 0, WIDTH / 2, HEIGHT)],
+        _ => &[
+            (0, 0, WIDTH / 2, HEIGHT / 2),
+            (WIDTH / 2, 0, WIDTH / 2, HEIGHT / 2),
+            (0, HEIGHT / 2, WIDTH / 2, HEIGHT / 2),
+            (WIDTH / 2, HEIGHT / 2, WIDTH / 2, HEIGHT / 2),
+        ],
+    };
+
+    for (picture, (x, y, width, height)) in pictures.iter().zip(cells) {
+        let cell = picture.resize_to_fill(*width, *height, imageops::FilterType::Triangle);
+        imageops::replace(&mut canvas, &cell.to_rgb8(), *x as i64, *y as i64);
+    }
+
+    canvas
+}
+
+/// Generated `og:image` of cards without a preview: the title and the author
+/// over `OG_IMAGE_BACKGROUND` or the plain background, like GitHub social cards
+pub struct SocialCard {
+    font: FontArc,
+    background: Option<RgbImage>,
+    color: Rgb<u8>,
+}
+
+impl std::fmt::Debug for SocialCard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SocialCard")
+            .field("background", &self.background.is_some())
+            .field("color", &self.color)
+            .finish()
+    }
+}
+
+impl SocialCard {
+    pub fn load(font: &str, background: Option<&str>, color: Rgb<u8>) -> Result<Self, String> {
+        let bytes = std::fs::read(font).map_err(|err| format!("{}: {}", font, err))?;
+        let font = FontArc::try_from_vec(bytes).map_err(|err| format!("{}: {}", font, err))?;
+        let background = match background {
+            Some(path) => Some(
+                image::open(path)
+                    .map_err(|err| format!("{}: {}", path, err))?
+                    .resize_to_fill(WIDTH, HEIGHT, imageops::FilterType::Triangle)
+                    .to_rgb8(),
+            ),
+            None => None,
+        };
+
+        Ok(SocialCard {
+            font,
+            background,
+            color,
+        })
+    }
+
+    /// Title is wrapped by words into at most four lines, the author is put at the bottom
+    pub fn render(&self, title: &str, author: Option<&str>) -> RgbImage {
+        let mut canvas = self
+            .background
+            .clone()
+            .unwrap_or_else(|| RgbImage::from_pixel(WIDTH, HEIGHT, BACKGROUND));
+
+        let title_scale = PxScale::from(TITLE_SIZE);
+        let line_height = self.font.as_scaled(title_scale).height() * 1.15;
+        let mut y = PADDING;
+        for line in self.wrap(title, title_scale, WIDTH as f32 - PADDING * 2.0) {
+            self.draw(&mut canvas, &line, title_scale, PADDING, y);
+            y += line_height;
+        }
+
+        if let Some(author) = author.map(str::trim).filter(|author| !author.is_empty()) {
+            let scale = PxScale::from(AUTHOR_SIZE);
+            let height = self.font.as_scaled(scale).height();
+            self.draw(
+                &mut canvas,
+                author,
+                scale,
+                PADDING,
+                HEIGHT as f32 - PADDING - height,
+            );
+        }
+
+        canvas
+    }
+
+    fn width(&self, text: &str, scale: PxScale) -> f32 {
+        let font = self.font.as_scaled(scale);
+        let mut previous = None;
+
+        text.chars()
+            .map(|char| {
+                let glyph = font.glyph_id(char);
+                let kern = previous.map_or(0.0, |previous| font.kern(previous, glyph));
+                previous = Some(glyph);
+                kern + font.h_advance(glyph)
+            })
+            .sum()
+    }
+
+    /// Greedy word wrap, the last line gets an ellipsis when the text doesn't fit
+    fn wrap(&self, text: &str, scale: PxScale, max_width: f32) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+
+        for word in text.split_whitespace() {
+            let fits = lines
+                .last()
+                .is_some_and(|line| self.width(&format!("{} {}", line, word), scale) <= max_width);
+
+            if fits {
+                let line = lines.last_mut().expect("fits only the last line");
+                line.push(' ');
+                line.push_str(word);
+                continue;
+            }
+
+            if lines.len() == TITLE_MAX_LINES {
+                let last = lines.last_mut().expect("lines are full");
+                while !last.is_empty() && self.width(&format!("{}…", last), scale) > max_width {
+                    last.pop();
+                }
+                last.truncate(last.trim_end().len());
+                last.push('…');
+                break;
+            }
+
+            // A word wider than the whole line is cut
+            let mut line = word.to_string();
+            while line.chars().count() > 1 && self.width(&line, scale) > max_width {
+                line.pop();
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Text with the top of its line at `y`
+    fn draw(&self, canvas: &mut RgbImage, text: &str, scale: PxScale, x: f32, y: f32) {
+        let font = self.font.as_scaled(scale);
+        let baseline = y + font.ascent();
+        let mut caret = x;
+        let mut previous = None;
+
+        for char in text.chars() {
+            let id = font.glyph_id(char);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            previous = Some(id);
+
+            let glyph = id.with_scale_and_position(scale, ab_glyph::point(caret, baseline));
+            caret += font.h_advance(id);
+
+            if let Some(outlined) = self.font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x as i32 + gx as i32;
+                    let py = bounds.min.y as i32 + gy as i32;
+                    if px < 0 || py < 0 || px >= WIDTH as i32 || py >= HEIGHT as i32 {
+                        return;
+                    }
+
+                    let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+                    for channel in 0..3 {
+                        pixel[channel] = (pixel[channel] as f32 * (1.0 - coverage)
+                            + self.color[channel] as f32 * coverage)
+                            .round() as u8;
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// `1200x630` of proxy URLs, only the sizes the proxy serves
+pub fn proxy_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    PROXY_SIZES.contains(&size).then_some(size)
+}
+
+/// Fills the whole size, the center of the picture is kept
+pub fn crop(picture: &DynamicImage, (width, height): (u32, u32)) -> RgbImage {
+    picture
+        .resize_to_fill(width, height, imageops::FilterType::Triangle)
+        .to_rgb8()
+}
+
+pub fn encode_png(image: &RgbImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut bytes = Vec::new();
+    image.write_to(
+        &mut std::io::Cursor::new(&mut bytes),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(bytes)
+}
+
+pub fn encode_jpeg(image: &RgbImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut bytes = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, 85).encode_image(image)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hashes are in cached image names and ETags, they must not change between builds
+    #[test]
+    fn version_hash_is_pinned() {
+        assert_eq!(version_hash(""), "1e924b9d737700d7");
+        assert_eq!(version_hash("2020-01-01T00:00:00Z"), "688b62ccba90d581");
+    }
+
+    #[test]
+    fn put_replaces_images_without_leftovers() {
+        let dir = std::env::temp_dir().join(format!("ssi-image-cache-{}", std::process::id()));
+        let cache = ImageCache::new(dir.clone()).unwrap();
+
+        cache.put("card-1.jpg", b"old").unwrap();
+        cache.put("card-1.jpg", b"new").unwrap();
+
+        assert_eq!(cache.get("card-1.jpg").as_deref(), Some(&b"new"[..]));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
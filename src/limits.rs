@@ -0,0 +1,188 @@
+use actix_server_config::IoStream;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpResponse};
+use futures::{future, Async, Future, Poll};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+use crate::config::{env_duration, env_or};
+use crate::metrics;
+
+/// Protection against clients holding connections or piling up requests.
+///
+/// Connections over `MAX_CONNECTIONS` wait in the socket backlog until others are closed,
+/// requests over `MAX_IN_FLIGHT` are answered with 503 right away.
+/// Clients that don't send request headers within `CLIENT_HEADER_TIMEOUT` are disconnected.
+#[derive(Debug)]
+pub struct Limits {
+    pub workers: usize,
+    /// Across all workers, 0 keeps the actix default of 25k per worker
+    pub max_connections: usize,
+    /// Request headers must arrive within it, otherwise the connection is dropped
+    pub header_timeout: Duration,
+    pub keep_alive: Duration,
+    /// 0 disables the limit
+    pub max_in_flight: usize,
+    in_flight: AtomicUsize,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+
+        Limits {
+            workers: env_or("WORKERS", cpus).max(1),
+            max_connections: env_or("MAX_CONNECTIONS", 0),
+            header_timeout: env_duration("CLIENT_HEADER_TIMEOUT", Duration::from_secs(5)),
+            keep_alive: env_duration("KEEP_ALIVE", Duration::from_secs(5)),
+            max_in_flight: env_or("MAX_IN_FLIGHT", 0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Every worker accepts its share of connections
+    pub fn max_connections_per_worker(&self) -> Option<usize> {
+        match self.max_connections {
+            0 => None,
+            max => Some(max.div_ceil(self.workers)),
+        }
+    }
+}
+
+/// Decrements the counter however the request ends, including panics and dropped futures
+struct InFlight(Arc<Limits>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answers 503 with `Retry-After` when `MAX_IN_FLIGHT` requests are being handled already
+pub fn in_flight<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let limits = match req.app_data::<Arc<Limits>>() {
+        Some(limits) if limits.max_in_flight > 0 => Arc::clone(&limits),
+        _ => return Box::new(srv.call(req)),
+    };
+
+    let current = limits.in_flight.fetch_add(1, Ordering::Relaxed);
+    let guard = InFlight(limits.clone());
+
+    if current >= limits.max_in_flight {
+        metrics::record_overloaded("in_flight");
+        return Box::new(future::ok(
+            req.into_response(
+                HttpResponse::ServiceUnavailable()
+                    .header("retry-after", "1")
+                    .header("cache-control", "no-store")
+                    .finish(),
+            ),
+        ));
+    }
+
+    Box::new(srv.call(req).then(move |response| {
+        drop(guard);
+        response
+    }))
+}
+
+/// actix reads the first bytes of a connection to tell HTTP/1 from the HTTP/2 preface
+const PREFACE_LEN: usize = 14;
+
+/// Connection that has to start talking before the deadline.
+///
+/// actix arms its slow request timer only once the protocol is detected,
+/// so a client that connects and stays silent would hold the connection forever.
+/// After the first bytes arrive, actix timers take over.
+#[derive(Debug)]
+pub struct FirstBytes<T> {
+    io: T,
+    deadline: Option<Delay>,
+    received: usize,
+}
+
+impl<T> FirstBytes<T> {
+    pub fn new(io: T, timeout: Duration) -> Self {
+        FirstBytes {
+            io,
+            deadline: Some(Delay::new(Instant::now() + timeout)),
+            received: 0,
+        }
+    }
+
+    fn expired(&mut self) -> bool {
+        match self.deadline.as_mut().map(Future::poll) {
+            Some(Ok(Async::NotReady)) | None => false,
+            Some(Ok(Async::Ready(()))) | Some(Err(_)) => true,
+        }
+    }
+}
+
+impl<T: Read> Read for FirstBytes<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use log::debug;
+
+        match self.io.read(buf) {
+            Ok(read) => {
+                self.received += read;
+                if self.received >= PREFACE_LEN {
+                    self.deadline = None;
+                }
+                Ok(read)
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock && self.expired() => {
+                debug!("Dropping connection that sent no request in time");
+                metrics::record_dropped_connection("slow_client");
+                Err(io::ErrorKind::TimedOut.into())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<T: Write> Write for FirstBytes<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for FirstBytes<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for FirstBytes<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl<T: IoStream> IoStream for FirstBytes<T> {
+    fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+        self.io.peer_addr()
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.io.set_nodelay(nodelay)
+    }
+
+    fn set_linger(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.io.set_linger(dur)
+    }
+
+    fn set_keepalive(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.io.set_keepalive(dur)
+    }
+}
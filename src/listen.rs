@@ -0,0 +1,154 @@
+use actix_http::body::MessageBody;
+use actix_http::error::DispatchError;
+use actix_http::{Error, HttpService, KeepAlive, Request, Response};
+use actix_server::ServerBuilder;
+use actix_server_config::{Io, ServerConfig};
+use actix_service::{service_fn, IntoNewService, NewService};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use tokio_tcp::TcpStream;
+
+use crate::limits::{FirstBytes, Limits};
+
+const DEFAULT_BACKLOG: i32 = 1024;
+
+/// One entry of `LISTEN_HOST`: `host:port` followed by optional `;name=value` settings.
+///
+/// `0.0.0.0:8080,[::]:8080` binds both stacks with separate sockets,
+/// `[::]:8080;v6only=false` accepts both through a single one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub addr: String,
+    /// Pending connections queue of the socket
+    pub backlog: i32,
+    /// IPv6 sockets don't accept IPv4-mapped connections, so they don't clash with `0.0.0.0`
+    pub v6only: bool,
+}
+
+impl Listener {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let mut parts = entry.split(';').map(str::trim);
+        let addr = parts.next().unwrap_or_default();
+        if addr.is_empty() {
+            return Err(format!("empty address in {:?}", entry));
+        }
+
+        let mut listener = Listener {
+            addr: addr.to_string(),
+            backlog: DEFAULT_BACKLOG,
+            v6only: true,
+        };
+
+        for option in parts.filter(|option| !option.is_empty()) {
+            let (name, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=value, got {:?}", option))?;
+            let invalid = || format!("invalid {} for {}: {:?}", name, addr, value);
+
+            match name.trim() {
+                "backlog" => listener.backlog = value.trim().parse().map_err(|_| invalid())?,
+                "v6only" => listener.v6only = value.trim().parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown listener option {:?}", name)),
+            }
+        }
+
+        Ok(listener)
+    }
+
+    /// Host names bind every address they resolve to, e.g. `localhost` is both `127.0.0.1` and `::1`
+    pub fn bind(&self) -> std::io::Result<Vec<TcpListener>> {
+        self.addr
+            .to_socket_addrs()?
+            .map(|addr| self.bind_addr(addr))
+            .collect()
+    }
+
+    fn bind_addr(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(self.v6only)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog)?;
+
+        Ok(socket.into())
+    }
+}
+
+/// Sockets for every listener, logged as they are bound
+pub fn bind_all(listeners: &[Listener], role: &str) -> std::io::Result<Vec<TcpListener>> {
+    use log::info;
+
+    let mut sockets = Vec::new();
+    for listener in listeners {
+        for socket in listener.bind()? {
+            info!("Listening for {} traffic on {}", role, socket.local_addr()?);
+            sockets.push(socket);
+        }
+    }
+
+    Ok(sockets)
+}
+
+/// Comma separated list of listeners
+pub fn parse(value: &str) -> Result<Vec<Listener>, String> {
+    let listeners = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(Listener::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if listeners.is_empty() {
+        return Err("no addresses to listen".to_string());
+    }
+
+    Ok(listeners)
+}
+
+/// Serves apps built by `factory` on the sockets, like `HttpServer::listen` does,
+/// but with connections guarded by `FirstBytes`
+pub fn serve<F, I, S, B>(
+    mut builder: ServerBuilder,
+    sockets: Vec<TcpListener>,
+    limits: &Limits,
+    factory: F,
+) -> std::io::Result<ServerBuilder>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoNewService<S>,
+    S: NewService<Config = ServerConfig, Request = Request>,
+    S::Error: Into<Error>,
+    S::InitError: std::fmt::Debug,
+    S::Response: Into<Response<B>>,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let header_timeout = limits.header_timeout;
+    let keep_alive = limits.keep_alive.as_secs() as usize;
+    let client_timeout = limits.header_timeout.as_millis() as u64;
+
+    for socket in sockets {
+        let factory = factory.clone();
+
+        builder = builder.listen(format!("ssi-{}", socket.local_addr()?), socket, move || {
+            service_fn(move |io: Io<TcpStream>| {
+                let (stream, params, protocol) = io.into_parts();
+                Ok::<_, DispatchError>(Io::from_parts(
+                    FirstBytes::new(stream, header_timeout),
+                    params,
+                    protocol,
+                ))
+            })
+            .and_then(
+                HttpService::build()
+                    .keep_alive(KeepAlive::from(keep_alive))
+                    .client_timeout(client_timeout)
+                    .finish(factory()),
+            )
+        })?;
+    }
+
+    Ok(builder)
+}
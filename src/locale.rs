@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// Value that can differ per locale, e.g. `SITENAME`.
+/// Configured either as a plain string or as a JSON object
+/// `{"default": "How to cards", "ru": "Как сделать карточки"}`.
+#[derive(Debug, Clone)]
+pub struct Localized {
+    default: String,
+    locales: HashMap<String, String>,
+}
+
+impl Localized {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if !value.trim_start().starts_with('{') {
+            return Ok(Localized {
+                default: value.to_string(),
+                locales: HashMap::new(),
+            });
+        }
+
+        let mut locales: HashMap<String, String> =
+            serde_json::from_str(value).map_err(|err| err.to_string())?;
+        let default = locales
+            .remove("default")
+            .ok_or_else(|| "\"default\" key is required".to_string())?;
+
+        Ok(Localized {
+            default,
+            locales: locales
+                .into_iter()
+                .map(|(locale, value)| (locale.to_lowercase(), value))
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, locale: Option<&str>) -> &str {
+        locale
+            .and_then(|locale| self.locales.get(&locale.to_lowercase()))
+            .unwrap_or(&self.default)
+    }
+
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.locales.keys().map(String::as_str)
+    }
+}
+
+/// Picks the best of `available` locales for an Accept-Language header.
+/// Both exact tags (`pt-br`) and primary languages (`pt`) are matched.
+pub fn negotiate<'a, I>(accept_language: &str, available: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let available = available
+        .into_iter()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    let mut ranges = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if tag.is_empty() || tag == "*" || quality <= 0.0 {
+                None
+            } else {
+                Some((tag, quality))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // stable sort keeps header order for equal weights
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    ranges.iter().find_map(|(tag, _)| {
+        let primary = tag.split('-').next().unwrap_or(tag);
+
+        available
+            .iter()
+            .find(|locale| *locale == tag)
+            .or_else(|| available.iter().find(|locale| *locale == primary))
+            .cloned()
+    })
+}
@@ -1,217 +1,277 @@
-use actix_web::{client::Client, web, App, Error, HttpResponse, HttpServer};
-use futures::{Future, Stream};
-use serde::{Deserialize, Serialize};
+use actix_server::ServerBuilder;
+use actix_web::dev::Service;
+use actix_web::http::{ContentEncoding, Method};
+use actix_web::{client::Client, middleware, web, App, HttpResponse};
+use clap::Parser;
+use futures::future::Either;
 use std::sync::Arc;
 
+use config::{env_duration, env_or, Config};
+use storage::Templates;
+
+mod access_log;
+mod acme;
+mod admin;
+mod alloc;
+mod audit;
+mod backend;
+mod cache;
+mod cdn;
+mod chaos;
+mod cli;
+mod compression;
+mod conditional;
+mod config;
+mod crawler;
+mod critical;
+mod flags;
+mod health;
+mod images;
+mod limits;
+mod listen;
+mod locale;
+mod maintenance;
+mod markdown;
+mod memory;
+mod meta;
+mod metrics;
+mod microcache;
+mod outbound;
+mod privileges;
+mod profiling;
+mod publish;
+mod recover;
+mod redirect;
+mod redis_cache;
+mod routes;
+mod routing;
+mod schema;
+mod singleflight;
+mod stats;
+mod storage;
+
 /// 1. browser requests this service
-/// 2. this service handles {LISTEN_HOST}/open/{card_id}
+/// 2. this service handles {LISTEN_HOST}/open/{card_id} (and /user/{user_id})
 /// 3. sends request to {BACKEND_URL}/cards/{card_id}/meta/
 /// 4. converts meta to html meta tags
-/// 5. add meta tags to html before </head>
+/// 5. add meta tags to html before {INJECT_MARKER} (</head> by default)
 /// 6. sends html to user
-
 fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     pretty_env_logger::init();
 
-    let listen_host = std::env::var("LISTEN_HOST").expect("please, provide LISTEN_HOST");
+    match cli::Cli::parse().command {
+        Some(cli::Command::ConfigSchema) => {
+            println!("{:#}", schema::config_schema());
+            return Ok(());
+        }
+        Some(cli::Command::Completions { shell }) => {
+            cli::print_completions(shell);
+            return Ok(());
+        }
+        Some(cli::Command::Serve) | None => {}
+    }
 
-    let config = Arc::new(Config {
-        public_url: std::env::var("PUBLIC_URL").expect("please, provide PUBLIC_URL"),
-        image_url: std::env::var("IMAGE_URL").expect("please, provide IMAGE_URL"),
-        backend_url: std::env::var("BACKEND_URL").expect("please, provide BACKEND_URL"),
-        sitename: std::env::var("SITENAME").expect("please, provide SITENAME"),
-        index_html_path: std::env::var("INDEX_HTML_PATH").expect("please, provide INDEX_HTML_PATH"),
-    });
+    let _sentry = recover::init();
 
-    let storage = Arc::new(
-        Storage::read_from(config.clone().index_html_path.clone())
-            .expect("cannot read INDEX_HTML_FILE"),
-    );
+    let listeners =
+        listen::parse(&std::env::var("LISTEN_HOST").expect("please, provide LISTEN_HOST"))
+            .expect("invalid LISTEN_HOST");
+    let admin_listeners = std::env::var("ADMIN_LISTEN_HOST")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| listen::parse(&value).expect("invalid ADMIN_LISTEN_HOST"));
 
-    HttpServer::new(move || {
-        App::new()
-            .data(Client::default())
-            .data(config.clone())
-            .data(storage.clone())
-            .service(web::resource("/open/{card_id}").to_async(card))
-            .service(web::resource("/open/{card_id}/").to_async(card))
-    })
-    .bind(listen_host)?
-    .run()
-}
+    let public_sockets = listen::bind_all(&listeners, "public")?;
+    let admin_sockets = match admin_listeners {
+        Some(ref listeners) => Some(listen::bind_all(listeners, "internal")?),
+        None => None,
+    };
+    privileges::drop_from_env().expect("cannot drop privileges");
 
-#[derive(Debug)]
-struct Storage {
-    index_html: String,
-}
+    let config = Arc::new(Config::from_env());
 
-impl Storage {
-    pub fn read_from(path: String) -> Result<Self, std::io::Error> {
-        let source = std::fs::read_to_string(path)?;
+    let flags = Arc::new(flags::Flags::from_env());
+    outbound::init();
+    let chaos = Arc::new(chaos::Chaos::from_env());
+    let top_cards = Arc::new(stats::TopCards::new(env_duration(
+        "STATS_TOP_RETENTION",
+        std::time::Duration::from_secs(24 * 60 * 60),
+    )));
 
-        Ok(Storage { index_html: source })
+    let templates = Arc::new(
+        Templates::load(
+            config.clone().index_html_path.clone(),
+            storage::Options::from_env(),
+        )
+        .expect("cannot read INDEX_HTML_PATH or INDEX_HTML_URL"),
+    );
+    let locale_templates = Arc::new(
+        storage::LocaleTemplates::from_env(&storage::Options::from_env())
+            .expect("cannot read LOCALE_INDEX_HTML"),
+    );
+    for templates in std::iter::once(&templates).chain(locale_templates.iter()) {
+        templates.spawn_watcher(if templates.is_remote() {
+            env_duration(
+                "INDEX_HTML_REFRESH_INTERVAL",
+                std::time::Duration::from_secs(60),
+            )
+        } else {
+            env_duration("TEMPLATE_CHECK_INTERVAL", std::time::Duration::from_secs(2))
+        });
     }
-}
 
-fn create_meta<P, C>(prop: P, content: C) -> String
-where
-    P: AsRef<str>,
-    C: AsRef<str>,
-{
-    format!(
-        r#"<meta property="{}" content="{}" />"#,
-        htmlescape::encode_minimal(prop.as_ref()),
-        htmlescape::encode_minimal(content.as_ref())
-    )
-}
+    memory::spawn_watchdog(
+        env_or("MEMORY_WARN_FRACTION", 0.5),
+        env_duration("MEMORY_CHECK_INTERVAL", std::time::Duration::from_secs(30)),
+    );
 
-#[derive(Debug)]
-struct Config {
-    public_url: String,
-    image_url: String,
-    backend_url: String,
-    sitename: String,
-    index_html_path: String,
-}
+    let cache = Arc::new(cache::Cache::from_env().expect("invalid cache configuration"));
+    let image_cache =
+        Arc::new(images::ImageCache::from_env().expect("cannot create IMAGE_CACHE_DIR"));
+    let microcache = Arc::new(microcache::Microcache::from_env());
 
-impl Config {
-    fn meta_for_card(&self, card: &Card) -> String {
-        let public_url = self.public_url.to_string();
+    let publisher = Arc::new(publish::Publisher::spawn(
+        config.clone(),
+        templates.clone(),
+        chaos.clone(),
+        cache.clone(),
+        flags.clone(),
+    ));
 
-        let title = create_meta("title", &card.title);
-        let description = create_meta("description", &card.description);
+    let audit = Arc::new(audit::AuditLog::from_env());
+    let access_log =
+        Arc::new(access_log::AccessLog::from_env().expect("invalid ACCESS_LOG_FORMAT"));
 
-        let og_sitename = create_meta("og:site_name", &self.sitename);
-        let og_type = create_meta("og:type", "article");
-        let og_title = create_meta("og:title", &card.title);
-        let og_description = create_meta("og:description", &card.description);
-        let og_url = create_meta("og:url", format!("{}/open/{}", public_url, card.id));
-        let og_image = (card.preview_url.clone()).map_or("".to_string(), |url| {
-            create_meta("og_image", format!("{}/{}", self.image_url, url))
-        });
-        // let og_locale = create_meta("og:locale", "en_US");
-        // let og_article_author = create_meta("article:author", "Sergey Sova");
-        // let og_article_tag = create_meta("article:tag", "react");
-        // https://developer.twitter.com/en/docs/tweets/optimize-with-cards/overview/summary-card-with-large-image
-        let og_article_published = create_meta("article:published_time", &card.created_at);
-        let og_article_modified = create_meta("article:modified_time", &card.updated_at);
-
-        let twitter_card = create_meta(
-            "twitter:card",
-            (card.preview_url.clone()).map_or("summary", |_| "summary_large_image"),
-        );
-        let twitter_site = create_meta("twitter:site", "@howtocards_io");
-        let twitter_title = create_meta("twitter:title", &card.title);
-        let twitter_description = create_meta("twitter:description", &card.description);
-        let twitter_image = (card.preview_url.clone()).map_or("".to_string(), |url| {
-            create_meta("twitter:image", format!("{}{}", self.image_url, url))
-        });
+    let canonical = redirect::Canonical::from_env();
+    let limits = Arc::new(limits::Limits::from_env());
+    let maintenance =
+        Arc::new(maintenance::Maintenance::from_env().expect("cannot read MAINTENANCE_PAGE"));
+
+    let sys = actix_rt::System::new("ssi");
 
-        vec![
-            title,
-            description,
-            og_sitename,
-            og_type,
-            og_title,
-            og_description,
-            og_url,
-            og_image,
-            og_article_published,
-            og_article_modified,
-            twitter_card,
-            twitter_site,
-            twitter_title,
-            twitter_description,
-            twitter_image,
-        ]
-        .iter()
-        .fold(String::new(), |acc, meta| format!("{}\n{}", acc, meta))
+    let public_internal = admin_sockets.is_none();
+
+    if let Some(admin_sockets) = admin_sockets {
+        let config = config.clone();
+        let templates = templates.clone();
+        let locale_templates = locale_templates.clone();
+        let flags = flags.clone();
+        let top_cards = top_cards.clone();
+        let cache = cache.clone();
+        let audit = audit.clone();
+
+        listen::serve(ServerBuilder::new(), admin_sockets, &limits, move || {
+            App::new()
+                .data(config.clone())
+                .data(templates.clone())
+                .data(locale_templates.clone())
+                .data(flags.clone())
+                .data(top_cards.clone())
+                .data(cache.clone())
+                .data(audit.clone())
+                .configure(internal)
+                .wrap_fn(recover::catch_panics)
+        })?
+        .start();
     }
 
-    fn backend_card_url(&self, card_id: u32) -> String {
-        format!("{}/api/cards/{}/meta/", self.backend_url, card_id)
+    let mut builder = ServerBuilder::new().workers(limits.workers);
+    if let Some(max) = limits.max_connections_per_worker() {
+        builder = builder.maxconn(max);
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct CardPath {
-    card_id: u32,
-}
+    let routing = Arc::new(routing::RoutingTable::from_env().expect("invalid ROUTES_FILE"));
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(untagged)]
-enum Answer<T> {
-    Err { ok: bool, error: String },
-    Ok { ok: bool, result: T },
-}
+    let app_limits = limits.clone();
+    listen::serve(builder, public_sockets, &limits, move || {
+        let canonical = canonical.clone();
+        let routing = routing.clone();
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct Card {
-    pub title: String,
-    pub description: String,
-    pub id: i32,
-    pub created_at: String,
-    pub updated_at: String,
-    pub preview_url: Option<String>,
-}
+        App::new()
+            .data(Client::default())
+            .data(config.clone())
+            .data(templates.clone())
+            .data(locale_templates.clone())
+            .data(flags.clone())
+            .data(chaos.clone())
+            .data(top_cards.clone())
+            .data(cache.clone())
+            .data(image_cache.clone())
+            .data(microcache.clone())
+            .data(publisher.clone())
+            .data(audit.clone())
+            .data(access_log.clone())
+            .data(app_limits.clone())
+            .data(maintenance.clone())
+            .configure(acme::configure)
+            .configure(|cfg| {
+                if public_internal {
+                    internal(cfg)
+                }
+            })
+            .configure(publish::configure)
+            .service(metrics::resource("/").route(web::get().to(routes::home::home)))
+            .configure(|cfg| routes::card::configure(cfg, &config.path_locales))
+            .service(
+                metrics::resource("/api/meta/cards/{card_id}")
+                    .route(web::get().to_async(routes::api::card_meta))
+                    .route(web::method(Method::OPTIONS).to(routes::api::preflight)),
+            )
+            .service(metrics::resource("/embed.js").route(web::get().to(routes::embed::loader)))
+            .service(metrics::resource("/open/{card_id}/embed").to_async(routes::embed::embed))
+            .configure(routes::sitemap::configure)
+            .service(metrics::resource("/feed.xml").route(web::get().to_async(routes::feed::feed)))
+            .service(metrics::resource("/robots.txt").route(web::get().to(routes::robots::robots)))
+            .configure(routes::provider::configure)
+            .service(
+                metrics::resource("/{listing:popular|latest}").to_async(routes::listing::listing),
+            )
+            .service(
+                metrics::resource("/img/{size}/{path:.+}")
+                    .route(web::get().to_async(routes::proxy::proxy)),
+            )
+            .service(
+                metrics::resource("/og-image/{card_id}.png").to_async(routes::card::social_image),
+            )
+            .service(
+                metrics::resource("/og-image/collection/{collection_id}.jpg")
+                    .to_async(routes::collection::collage),
+            )
+            .configure(move |cfg| routing.configure(cfg))
+            .default_service(web::route().to(routes::home::page))
+            .wrap_fn(compression::precompressed)
+            .wrap_fn(maintenance::guard)
+            .wrap_fn(routes::robots::noindex)
+            .wrap_fn(move |req, srv| match canonical.redirect_for(&req) {
+                Some(location) => Either::B(futures::future::ok(
+                    req.into_response(
+                        HttpResponse::MovedPermanently()
+                            .header("location", location)
+                            .finish(),
+                    ),
+                )),
+                None => Either::A(srv.call(req)),
+            })
+            .wrap_fn(limits::in_flight)
+            .wrap_fn(recover::catch_panics)
+            .wrap_fn(metrics::track)
+            .wrap_fn(access_log::log)
+            .wrap(middleware::Compress::new(if config.compression {
+                ContentEncoding::Auto
+            } else {
+                ContentEncoding::Identity
+            }))
+    })?
+    .start();
 
-#[derive(Debug, Deserialize, Serialize)]
-struct CardWrapper {
-    meta: Card,
+    sys.run()
 }
 
-fn card(
-    path: web::Path<CardPath>,
-    client: web::Data<Client>,
-    config: web::Data<Arc<Config>>,
-    storage: web::Data<Arc<Storage>>,
-) -> impl Future<Item = HttpResponse, Error = Error> {
-    let storage_copy = storage.clone();
-    client
-        .get(config.backend_card_url(path.card_id))
-        .send()
-        .map_err(Error::from)
-        .and_then(|resp| {
-            resp.from_err()
-                .fold(web::BytesMut::new(), |mut acc, chunk| {
-                    acc.extend_from_slice(&chunk);
-                    Ok::<_, Error>(acc)
-                })
-        })
-        .and_then(|body| {
-            let body: Result<Answer<CardWrapper>, _> = serde_json::from_slice(&body);
-
-            match body {
-                Ok(Answer::Ok { result, .. }) => Ok(Some(result.meta)),
-                _ => Ok(None),
-            }
-        })
-        .map(move |card| {
-            if let Some(card) = card {
-                config.meta_for_card(&card)
-            } else {
-                "<div></div>".to_string()
-            }
-        })
-        .map(move |html| {
-            let index_html = storage.index_html.clone();
-            let replace_to = format!("{}</head>", html);
-            let body = index_html.replace("</head>", &replace_to);
-
-            HttpResponse::build(actix_web::http::StatusCode::OK)
-                .content_type("text/html; charset=utf-8")
-                .body(&body)
-        })
-        .or_else(move |err| {
-            use log::error;
-            let index_html = storage_copy.clone().index_html.clone();
-
-            error!("Failed to get info about card: {:#?}", err);
-
-            HttpResponse::build(actix_web::http::StatusCode::OK)
-                .content_type("text/html; charset=utf-8")
-                .body(index_html)
-        })
+/// Operator endpoints, served on `ADMIN_LISTEN_HOST` when it's set
+fn internal(cfg: &mut web::ServiceConfig) {
+    admin::configure(cfg);
+    health::configure(cfg);
+    metrics::configure(cfg);
+    profiling::configure(cfg);
 }
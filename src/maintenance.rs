@@ -0,0 +1,91 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpResponse};
+use futures::{future, Future};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::env_duration;
+use crate::crawler;
+use crate::flags::env_flag;
+use crate::redirect::EXEMPT_PREFIXES;
+use crate::storage::{Storage, Templates};
+
+/// Planned downtime: browsers get the maintenance page with 503 and `Retry-After`,
+/// crawlers keep getting pages with meta from the cache, so link previews survive.
+/// The backend is only called for entities that aren't cached at all.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(value: bool) {
+    ENABLED.store(value, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct Maintenance {
+    /// `MAINTENANCE_PAGE`, 503 error page of the template otherwise
+    page: Option<String>,
+    retry_after: Duration,
+}
+
+impl Maintenance {
+    pub fn from_env() -> Result<Self, std::io::Error> {
+        set_enabled(env_flag("MAINTENANCE_MODE"));
+
+        let page = match std::env::var("MAINTENANCE_PAGE") {
+            Ok(ref path) if !path.is_empty() => Some(std::fs::read_to_string(path)?),
+            _ => None,
+        };
+
+        Ok(Maintenance {
+            page,
+            retry_after: env_duration("MAINTENANCE_RETRY_AFTER", Duration::from_secs(60 * 60)),
+        })
+    }
+
+    fn response(&self, storage: Option<Arc<Storage>>) -> HttpResponse {
+        let page = self
+            .page
+            .clone()
+            .or_else(|| storage.and_then(|storage| storage.errors.get(503)))
+            .unwrap_or_else(|| "Service is under maintenance".to_string());
+
+        HttpResponse::ServiceUnavailable()
+            .content_type("text/html; charset=utf-8")
+            .header("retry-after", self.retry_after.as_secs().to_string())
+            .header("cache-control", "no-store")
+            .body(page)
+    }
+}
+
+/// Answers browser requests with the maintenance page while maintenance mode is on
+pub fn guard<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    if !enabled()
+        || crawler::is_crawler(req.headers())
+        || EXEMPT_PREFIXES
+            .iter()
+            .any(|prefix| req.path().starts_with(prefix))
+    {
+        return Box::new(srv.call(req));
+    }
+
+    let response = match req.app_data::<Arc<Maintenance>>() {
+        Some(maintenance) => maintenance.response(
+            req.app_data::<Arc<Templates>>()
+                .map(|templates| templates.current()),
+        ),
+        None => return Box::new(srv.call(req)),
+    };
+
+    Box::new(future::ok(req.into_response(response)))
+}
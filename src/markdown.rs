@@ -0,0 +1,86 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+/// Card content as HTML for crawlers. Raw HTML of the markdown is escaped
+/// and links other than http(s), mailto and relative ones lose their target.
+pub fn to_html(markdown: &str) -> String {
+    let events = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+    .map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Plain text of markdown for descriptions: no syntax, no HTML tags, whitespace collapsed.
+/// Link texts are kept, their URLs are dropped.
+pub fn to_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+
+    for event in Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    ) {
+        match event {
+            Event::Text(part) | Event::Code(part) => text.push_str(&part),
+            Event::SoftBreak | Event::HardBreak | Event::Rule => text.push(' '),
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Heading(_))
+            | Event::End(TagEnd::Item)
+            | Event::End(TagEnd::CodeBlock)
+            | Event::End(TagEnd::TableCell) => text.push(' '),
+            _ => {}
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn safe_url(url: CowStr) -> CowStr {
+    // Browsers skip whitespace and control characters in schemes, "java\tscript:" still runs
+    let compact = url
+        .chars()
+        .filter(|char| !char.is_ascii_whitespace() && !char.is_control())
+        .collect::<String>();
+    let scheme = compact
+        .find(':')
+        .map(|end| &compact[..end])
+        .filter(|scheme| {
+            scheme.chars().all(|char| {
+                char.is_ascii_alphanumeric() || char == '+' || char == '-' || char == '.'
+            })
+        })
+        .map(str::to_ascii_lowercase);
+
+    match scheme.as_deref() {
+        None | Some("http") | Some("https") | Some("mailto") => url,
+        Some(_) => CowStr::Borrowed(""),
+    }
+}
@@ -0,0 +1,74 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::metrics;
+
+lazy_static! {
+    static ref CACHES: Mutex<HashMap<&'static str, u64>> = Mutex::new(HashMap::new());
+}
+
+/// Caches report their approximate size here after every change
+pub fn report_cache(name: &'static str, bytes: u64) {
+    CACHES.lock().unwrap().insert(name, bytes);
+    metrics::set_cache_memory(name, bytes);
+}
+
+pub fn caches_total() -> u64 {
+    CACHES.lock().unwrap().values().sum()
+}
+
+/// Memory limit of the container: `MEMORY_LIMIT_BYTES`, or cgroup v2/v1 limit
+pub fn container_limit() -> Option<u64> {
+    if let Some(limit) = std::env::var("MEMORY_LIMIT_BYTES")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+    {
+        return Some(limit);
+    }
+
+    [
+        "/sys/fs/cgroup/memory.max",
+        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+    ]
+    .iter()
+    .filter_map(|path| std::fs::read_to_string(path).ok())
+    .filter_map(|limit| limit.trim().parse::<u64>().ok())
+    // cgroup v1 reports "no limit" as a huge number
+    .find(|limit| *limit < (1 << 60))
+}
+
+/// Periodically compares caches size against the container limit and warns
+/// before cache growth turns into an OOM kill.
+pub fn spawn_watchdog(fraction: f64, interval: Duration) {
+    use log::{info, warn};
+
+    let limit = match container_limit() {
+        Some(limit) => limit,
+        None => {
+            info!("Memory limit is unknown, cache memory watchdog is disabled");
+            return;
+        }
+    };
+    metrics::set_memory_limit(limit);
+
+    let threshold = (limit as f64 * fraction) as u64;
+
+    std::thread::Builder::new()
+        .name("memory-watchdog".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+
+            let used = caches_total();
+            if used > threshold {
+                warn!(
+                    "Caches use {} bytes, more than {}% of memory limit {} bytes",
+                    used,
+                    fraction * 100.0,
+                    limit
+                );
+            }
+        })
+        .expect("cannot spawn memory watchdog");
+}
@@ -0,0 +1,1471 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::backend::{
+    Author, Card, Collection, ExtraMeta, Listing, Tag, UsefulList, User, Visibility,
+};
+use crate::config::Config;
+use crate::images;
+use crate::markdown;
+
+/// Content longer than the platform shows is cut here, see `length_limit`
+pub fn create_meta<P, C>(prop: P, content: C) -> String
+where
+    P: AsRef<str>,
+    C: AsRef<str>,
+{
+    let content = match length_limit(prop.as_ref()) {
+        Some(limit) => Cow::Owned(truncate(content.as_ref(), limit)),
+        None => Cow::Borrowed(content.as_ref()),
+    };
+
+    format!(
+        r#"<meta property="{}" content="{}" />"#,
+        htmlescape::encode_minimal(prop.as_ref()),
+        htmlescape::encode_minimal(&content)
+    )
+}
+
+/// `og:image:*` of the card preview, each one only when the backend knows it.
+/// Proxied previews are JPEG of the proxy size.
+fn preview_details(card: &Card, preview_url: &str, proxied: bool) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    let (content_type, size) = if proxied {
+        (Some("image/jpeg"), Some((images::WIDTH, images::HEIGHT)))
+    } else {
+        (
+            image_type(preview_url),
+            card.preview_width.zip(card.preview_height),
+        )
+    };
+    if let Some(content_type) = content_type {
+        tags.push(create_meta("og:image:type", content_type));
+    }
+    if let Some((width, height)) = size {
+        tags.push(create_meta("og:image:width", width.to_string()));
+        tags.push(create_meta("og:image:height", height.to_string()));
+    }
+    if let Some(alt) = card.preview_alt.as_deref().map(str::trim) {
+        if !alt.is_empty() {
+            tags.push(create_meta("og:image:alt", alt));
+        }
+    }
+
+    tags
+}
+
+/// Lowercase `ru` or `en-gb`, `None` for anything else
+fn language_tag(lang: &str) -> Option<String> {
+    let lang = lang.trim().replace('_', "-").to_lowercase();
+    let mut parts = lang.split('-');
+    let language = parts.next()?;
+    let region = parts.next();
+
+    let valid = (2..=3).contains(&language.len())
+        && language.bytes().all(|byte| byte.is_ascii_alphabetic())
+        && region.is_none_or(|region| {
+            (2..=3).contains(&region.len())
+                && region.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        })
+        && parts.next().is_none();
+
+    valid.then_some(lang)
+}
+
+/// `en_US` form of a language tag, languages of the site get their main region
+fn og_locale(lang: &str) -> String {
+    match lang.split_once('-') {
+        Some((language, region)) => format!("{}_{}", language, region.to_uppercase()),
+        None => match lang {
+            "en" => "en_US".to_string(),
+            "ru" => "ru_RU".to_string(),
+            _ => lang.to_string(),
+        },
+    }
+}
+
+/// `@handle` of a Twitter account, `None` for anything that can't be one
+pub fn twitter_handle(handle: &str) -> Option<String> {
+    let name = handle.trim().trim_start_matches('@');
+    let valid = !name.is_empty()
+        && name.len() <= 15
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+
+    valid.then(|| format!("@{}", name))
+}
+
+/// Content type by the file extension of an image URL
+fn image_type(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "gif" => Some("image/gif"),
+        _ => None,
+    }
+}
+
+/// `extraMeta` of the backend, escaped like the built-in tags.
+/// Properties are plain names like `article:section`, anything else is skipped.
+fn extra_meta(extra: &[ExtraMeta]) -> Vec<String> {
+    extra
+        .iter()
+        .filter(|meta| {
+            !meta.property.is_empty()
+                && meta.property.len() <= 64
+                && meta.property.bytes().all(|byte| {
+                    byte.is_ascii_alphanumeric() || matches!(byte, b':' | b'_' | b'-' | b'.')
+                })
+        })
+        .take(MAX_EXTRA_META)
+        .map(|meta| create_meta(&meta.property, &meta.content))
+        .collect()
+}
+
+/// Longest texts link previews show in full, in graphemes. Platforms cut longer ones
+/// wherever they like or drop them.
+fn length_limit(prop: &str) -> Option<usize> {
+    match prop {
+        "twitter:title" => Some(70),
+        "og:title" => Some(95),
+        "description" | "og:description" | "twitter:description" => Some(DESCRIPTION_LIMIT),
+        _ => None,
+    }
+}
+
+pub fn create_link<R, H>(rel: R, href: H) -> String
+where
+    R: AsRef<str>,
+    H: AsRef<str>,
+{
+    format!(
+        r#"<link rel="{}" href="{}" />"#,
+        htmlescape::encode_minimal(rel.as_ref()),
+        htmlescape::encode_minimal(href.as_ref())
+    )
+}
+
+pub fn create_alternate<T, N, H>(content_type: T, title: N, href: H) -> String
+where
+    T: AsRef<str>,
+    N: AsRef<str>,
+    H: AsRef<str>,
+{
+    format!(
+        r#"<link rel="alternate" type="{}" title="{}" href="{}" />"#,
+        htmlescape::encode_minimal(content_type.as_ref()),
+        htmlescape::encode_minimal(title.as_ref()),
+        htmlescape::encode_minimal(href.as_ref())
+    )
+}
+
+pub fn create_hreflang<L, H>(lang: L, href: H) -> String
+where
+    L: AsRef<str>,
+    H: AsRef<str>,
+{
+    format!(
+        r#"<link rel="alternate" hreflang="{}" href="{}" />"#,
+        htmlescape::encode_minimal(lang.as_ref()),
+        htmlescape::encode_minimal(href.as_ref())
+    )
+}
+
+/// Structured data block, `</` is escaped so the JSON can't close the script early
+pub fn create_json_ld(value: &Value) -> String {
+    format!(
+        r#"<script type="application/ld+json">{}</script>"#,
+        value.to_string().replace("</", "<\\/")
+    )
+}
+
+/// `<title>` element, the template's own title is replaced with it on injection
+pub fn create_title<T: AsRef<str>>(text: T) -> String {
+    format!(
+        "<title>{}</title>",
+        htmlescape::encode_minimal(text.as_ref())
+    )
+}
+
+/// `window.{name} = {...}` for the frontend, escaped like structured data
+pub fn create_state_script<T: Serialize>(name: &str, value: &T) -> String {
+    let json = serde_json::to_string(value)
+        .unwrap_or_else(|_| "null".to_string())
+        .replace("</", "<\\/")
+        .replace("<!--", "<\\u0021--")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029");
+
+    format!("<script>window.{} = {};</script>", name, json)
+}
+
+pub fn create_named_meta<N, C>(name: N, content: C) -> String
+where
+    N: AsRef<str>,
+    C: AsRef<str>,
+{
+    format!(
+        r#"<meta name="{}" content="{}" />"#,
+        htmlescape::encode_minimal(name.as_ref()),
+        htmlescape::encode_minimal(content.as_ref())
+    )
+}
+
+/// `rel="prev"` and `rel="next"` links, so crawlers walk paginated pages in order.
+/// First page has no `?page=1` to avoid duplicates.
+pub fn pagination_links(base_url: &str, page: u32, pages: u32) -> Vec<String> {
+    let page_url = |page: u32| {
+        if page == 1 {
+            base_url.to_string()
+        } else {
+            format!("{}?page={}", base_url, page)
+        }
+    };
+
+    let mut links = Vec::new();
+    if page > 1 {
+        links.push(create_link("prev", page_url(page - 1)));
+    }
+    if page < pages {
+        links.push(create_link("next", page_url(page + 1)));
+    }
+    links
+}
+
+/// Card preview as served by the public meta API
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardMeta {
+    pub title: String,
+    pub description: String,
+    pub image: Option<String>,
+    pub canonical: String,
+    pub published: String,
+    pub modified: String,
+}
+
+pub fn join_meta(tags: Vec<String>) -> String {
+    tags.iter()
+        .fold(String::new(), |acc, meta| format!("{}\n{}", acc, meta))
+}
+
+impl Config {
+    pub fn meta_for_card(&self, card: &Card, locale: Option<&str>) -> String {
+        if card.visibility != Visibility::Public {
+            return self.meta_for_unlisted_card(card, locale);
+        }
+
+        let public_url = self.public_url.to_string();
+
+        let title = create_meta("title", &card.title);
+        let page_title = self.page_title(&card.title, locale);
+        let about = plain_description(&card.description);
+        let description = create_meta("description", &about);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "article");
+        let og_title = create_meta("og:title", &card.title);
+        let og_description = create_meta("og:description", &about);
+        let og_url = create_meta("og:url", format!("{}/open/{}", public_url, card.id));
+        // Author avatar is better than the generic image, but too small for a large card
+        let (image, card_type) = match card.preview_url {
+            Some(ref url) => (
+                Some(
+                    self.proxied_image_url(url, (images::WIDTH, images::HEIGHT))
+                        .unwrap_or_else(|| self.image_url_for(url)),
+                ),
+                "summary_large_image",
+            ),
+            None if self.social_card.is_some() => {
+                (Some(self.social_card_url(card)), "summary_large_image")
+            }
+            None => (
+                card.author
+                    .as_ref()
+                    .and_then(|author| author.avatar_url.as_ref())
+                    .map(|url| self.image_url_for(url))
+                    .or_else(|| {
+                        self.default_og_image
+                            .as_ref()
+                            .map(|image| self.absolute_url(image))
+                    }),
+                "summary",
+            ),
+        };
+
+        // Structured properties belong right after the image they describe
+        let og_image = image.as_ref().map_or("".to_string(), |url| {
+            let mut og_image = vec![create_meta("og:image", url)];
+            match card.preview_url {
+                Some(ref preview_url) => og_image.extend(preview_details(
+                    card,
+                    preview_url,
+                    self.proxies_image(preview_url),
+                )),
+                None if self.social_card.is_some() => og_image.extend(vec![
+                    create_meta("og:image:type", "image/png"),
+                    create_meta("og:image:width", images::WIDTH.to_string()),
+                    create_meta("og:image:height", images::HEIGHT.to_string()),
+                ]),
+                None => {}
+            }
+            og_image.join("\n")
+        });
+        // https://developer.twitter.com/en/docs/tweets/optimize-with-cards/overview/summary-card-with-large-image
+        let og_locale = self.card_languages(card);
+        let og_article_published = create_meta("article:published_time", &card.created_at);
+        let og_article_modified = create_meta("article:modified_time", &card.updated_at);
+        let og_article_tags = card
+            .tags
+            .iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .take(MAX_ARTICLE_TAGS)
+            .map(|tag| create_meta("article:tag", tag))
+            .collect::<Vec<_>>();
+        let mut og_article_author = Vec::new();
+        if let Some(ref author) = card.author {
+            if let Some(url) = self.author_url(author) {
+                og_article_author.push(create_meta("article:author", url));
+            }
+            if let Some(ref name) = author.display_name {
+                og_article_author.push(create_meta("og:article:author", name));
+            }
+        }
+
+        let twitter_card = create_meta("twitter:card", card_type);
+        let twitter_site = self.twitter_site();
+        let twitter_creator = card
+            .author
+            .as_ref()
+            .and_then(|author| author.twitter.as_deref())
+            .and_then(twitter_handle)
+            .map_or("".to_string(), |handle| {
+                create_meta("twitter:creator", handle)
+            });
+        let twitter_labels = twitter_labels(card, locale);
+        let twitter_title = create_meta("twitter:title", &card.title);
+        let twitter_description = create_meta("twitter:description", &about);
+        let twitter_image = card
+            .preview_url
+            .as_ref()
+            .and_then(|url| {
+                self.proxied_image_url(url, (images::TWITTER_WIDTH, images::TWITTER_HEIGHT))
+            })
+            .or_else(|| image.clone());
+        let twitter_image = twitter_image.as_ref().map_or("".to_string(), |url| {
+            match card.preview_alt.as_deref().map(str::trim) {
+                Some(alt) if card.preview_url.is_some() && !alt.is_empty() => format!(
+                    "{}\n{}",
+                    create_meta("twitter:image", url),
+                    create_meta("twitter:image:alt", alt)
+                ),
+                _ => create_meta("twitter:image", url),
+            }
+        });
+
+        let mut tags = vec![
+            page_title,
+            title,
+            description,
+            og_sitename,
+            og_type,
+            og_title,
+            og_description,
+            og_url,
+            og_locale,
+            og_image,
+            og_article_published,
+            og_article_modified,
+            og_article_author.join("\n"),
+            og_article_tags.join("\n"),
+            twitter_card,
+            twitter_site,
+            twitter_creator,
+            twitter_title,
+            twitter_description,
+            twitter_image,
+            twitter_labels,
+        ];
+        tags.push(create_json_ld(&self.article_json_ld(
+            card,
+            image.as_deref(),
+            locale,
+        )));
+        if let Some(how_to) = self.how_to_json_ld(card, image.as_deref()) {
+            tags.push(create_json_ld(&how_to));
+        }
+        let mut trail = Vec::new();
+        if let Some(ref collection) = card.collection {
+            trail.push((
+                collection.title.as_str(),
+                format!("{}/open/collection/{}", self.public_url, collection.id),
+            ));
+        }
+        trail.push((
+            card.title.as_str(),
+            format!("{}/open/{}", public_url, card.id),
+        ));
+        tags.extend(self.breadcrumbs("card", &trail, locale));
+        // The frontend renders the card header right away instead of asking the API again
+        if let Some(ref name) = self.card_state_var {
+            tags.push(create_state_script(name, card));
+        }
+        tags.extend(self.related_cards(card));
+        tags.extend(extra_meta(&card.extra_meta));
+        tags.extend(self.feed_links(None, locale));
+
+        let meta = join_meta(tags);
+        match self.card_meta_template {
+            Some(ref template) => self
+                .render_card_template(template, card, image.as_deref(), &meta, locale)
+                .unwrap_or_else(|err| {
+                    log::error!("Failed to render CARD_META_TEMPLATE: {:?}", err);
+                    meta
+                }),
+            None => meta,
+        }
+    }
+
+    /// `CARD_META_TEMPLATE` gets the card as the backend sends it, the built-in meta
+    /// as `default` and the values the built-in meta is made of
+    fn render_card_template(
+        &self,
+        template: &tera::Tera,
+        card: &Card,
+        image: Option<&str>,
+        default: &str,
+        locale: Option<&str>,
+    ) -> tera::Result<String> {
+        let mut context = tera::Context::new();
+        context.insert("card", card);
+        context.insert("url", &format!("{}/open/{}", self.public_url, card.id));
+        context.insert("description", &plain_description(&card.description));
+        context.insert("image", &image);
+        context.insert("sitename", self.sitename.get(locale));
+        context.insert("locale", &locale);
+        context.insert("public_url", &self.public_url);
+        context.insert("default", default);
+
+        template.render(CARD_TEMPLATE, &context)
+    }
+
+    pub fn meta_for_home(&self, locale: Option<&str>) -> String {
+        let mut tags = self.site_tags(&self.public_url, locale);
+        tags.push(create_json_ld(&self.website_json_ld(locale)));
+
+        join_meta(tags)
+    }
+
+    /// Site meta for SPA routes without their own handler (`/about`, `/login`, ...),
+    /// `path` without query
+    pub fn meta_for_page(&self, path: &str, locale: Option<&str>) -> String {
+        join_meta(self.site_tags(&format!("{}{}", self.public_url, path), locale))
+    }
+
+    /// `<title>` of an entity page, "{title} — {SITENAME}"
+    pub fn page_title(&self, title: &str, locale: Option<&str>) -> String {
+        create_title(format!("{} — {}", title.trim(), self.sitename.get(locale)))
+    }
+
+    /// Sitename with `SITE_DESCRIPTION` and `SITE_IMAGE` for pages without an entity
+    fn site_tags(&self, url: &str, locale: Option<&str>) -> Vec<String> {
+        let sitename = self.sitename.get(locale);
+        let image = self
+            .site_image
+            .as_ref()
+            .map(|image| self.absolute_url(image));
+
+        let mut tags = vec![
+            create_title(sitename),
+            create_meta("title", sitename),
+            create_meta("og:site_name", sitename),
+            create_meta("og:type", "website"),
+            create_meta("og:title", sitename),
+            create_meta("og:url", url),
+            create_meta(
+                "twitter:card",
+                if image.is_some() {
+                    "summary_large_image"
+                } else {
+                    "summary"
+                },
+            ),
+            self.twitter_site(),
+            create_meta("twitter:title", sitename),
+        ];
+
+        if let Some(ref description) = self.site_description {
+            let description = description.get(locale);
+            tags.push(create_meta("description", description));
+            tags.push(create_meta("og:description", description));
+            tags.push(create_meta("twitter:description", description));
+        }
+
+        if let Some(ref image) = image {
+            tags.push(create_meta("og:image", image));
+            tags.push(create_meta("twitter:image", image));
+        }
+
+        tags.extend(self.feed_links(None, locale));
+        tags
+    }
+
+    /// schema.org `WebSite`, with `SearchAction` for the sitelinks search box when `SEARCH_URL` is set
+    pub fn website_json_ld(&self, locale: Option<&str>) -> Value {
+        let mut website = json!({
+            "@context": "https://schema.org",
+            "@type": "WebSite",
+            "name": self.sitename.get(locale),
+            "url": self.public_url,
+        });
+
+        if let Some(ref search_url) = self.search_url {
+            website["potentialAction"] = json!({
+                "@type": "SearchAction",
+                "target": {
+                    "@type": "EntryPoint",
+                    "urlTemplate": self.absolute_url(search_url),
+                },
+                "query-input": "required name=search_term_string",
+            });
+        }
+
+        website
+    }
+
+    /// schema.org `Article` of a card for rich results, the site is the publisher
+    fn article_json_ld(&self, card: &Card, image: Option<&str>, locale: Option<&str>) -> Value {
+        let url = format!("{}/open/{}", self.public_url, card.id);
+        let mut article = json!({
+            "@context": "https://schema.org",
+            "@type": "Article",
+            "headline": truncate(&card.title, HEADLINE_LIMIT),
+            "description": plain_description(&card.description),
+            "url": url,
+            "mainEntityOfPage": url,
+            "datePublished": card.created_at,
+            "dateModified": card.updated_at,
+            "publisher": {
+                "@type": "Organization",
+                "name": self.sitename.get(locale),
+                "url": self.public_url,
+            },
+        });
+
+        if let Some(image) = image {
+            article["image"] = json!(image);
+        }
+
+        if let Some(ref author) = card.author {
+            if let Some(ref name) = author.display_name {
+                let mut person = json!({ "@type": "Person", "name": name });
+                if let Some(url) = self.author_url(author) {
+                    person["url"] = json!(url);
+                }
+                article["author"] = person;
+            }
+        }
+
+        article
+    }
+
+    /// schema.org `HowTo` for cards with steps, in the order of the backend
+    fn how_to_json_ld(&self, card: &Card, image: Option<&str>) -> Option<Value> {
+        if card.steps.is_empty() {
+            return None;
+        }
+
+        let url = format!("{}/open/{}", self.public_url, card.id);
+        let steps = card
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let mut how_to_step = json!({
+                    "@type": "HowToStep",
+                    "position": index + 1,
+                    "text": step.text,
+                });
+                if let Some(ref title) = step.title {
+                    how_to_step["name"] = json!(title);
+                }
+                if let Some(ref image_url) = step.image_url {
+                    how_to_step["image"] = json!(self.image_url_for(image_url));
+                }
+                how_to_step
+            })
+            .collect::<Vec<_>>();
+
+        let mut how_to = json!({
+            "@context": "https://schema.org",
+            "@type": "HowTo",
+            "name": card.title,
+            "description": plain_description(&card.description),
+            "url": url,
+            "step": steps,
+        });
+
+        if let Some(image) = image {
+            how_to["image"] = json!(image);
+        }
+
+        Some(how_to)
+    }
+
+    /// schema.org `BreadcrumbList` from the home page down to the page, `(name, url)` of
+    /// every level after the home page. Nothing when `page` isn't listed in `BREADCRUMBS`.
+    fn breadcrumbs(
+        &self,
+        page: &str,
+        trail: &[(&str, String)],
+        locale: Option<&str>,
+    ) -> Option<String> {
+        if !self.breadcrumbs.iter().any(|enabled| enabled == page) {
+            return None;
+        }
+
+        let items = std::iter::once((self.sitename.get(locale), self.public_url.clone()))
+            .chain(trail.iter().cloned())
+            .enumerate()
+            .map(|(index, (name, url))| {
+                json!({
+                    "@type": "ListItem",
+                    "position": index + 1,
+                    "name": name,
+                    "item": url,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Some(create_json_ld(&json!({
+            "@context": "https://schema.org",
+            "@type": "BreadcrumbList",
+            "itemListElement": items,
+        })))
+    }
+
+    /// `og:see_also` for every related card, `relatedLink` structured data
+    /// and `<link rel="related">` when `RELATED_LINK_TAGS` is on
+    fn related_cards(&self, card: &Card) -> Vec<String> {
+        let urls = card
+            .related
+            .iter()
+            .filter(|related| related.id != card.id)
+            .take(MAX_RELATED)
+            .map(|related| format!("{}/open/{}", self.public_url, related.id))
+            .collect::<Vec<_>>();
+
+        if urls.is_empty() {
+            return Vec::new();
+        }
+
+        let mut tags = urls
+            .iter()
+            .map(|url| create_meta("og:see_also", url))
+            .collect::<Vec<_>>();
+
+        tags.push(create_json_ld(&json!({
+            "@context": "https://schema.org",
+            "@type": "WebPage",
+            "url": format!("{}/open/{}", self.public_url, card.id),
+            "isPartOf": { "@type": "WebSite", "url": self.public_url },
+            "relatedLink": urls,
+        })));
+
+        if self.related_link_tags {
+            tags.extend(urls.iter().map(|url| create_link("related", url)));
+        }
+
+        tags
+    }
+
+    /// schema.org `Person` of a profile page, `sameAs` lists the user's profiles on other sites
+    pub fn person_json_ld(&self, user: &User) -> Value {
+        let mut person = json!({
+            "@context": "https://schema.org",
+            "@type": "Person",
+            "name": user.display_name,
+            "url": format!("{}/user/{}", self.public_url, user.id),
+        });
+
+        if let Some(ref avatar_url) = user.avatar_url {
+            person["image"] = json!(self.image_url_for(avatar_url));
+        }
+
+        let same_as = user
+            .social_links
+            .iter()
+            .filter(|link| link.starts_with("https://") || link.starts_with("http://"))
+            .collect::<Vec<_>>();
+        if !same_as.is_empty() {
+            person["sameAs"] = json!(same_as);
+        }
+
+        person
+    }
+
+    /// Cards open by link only are kept out of search results and link previews
+    fn meta_for_unlisted_card(&self, card: &Card, locale: Option<&str>) -> String {
+        join_meta(vec![
+            self.page_title(&card.title, locale),
+            create_named_meta("robots", "noindex"),
+        ])
+    }
+
+    /// Site-wide meta for missing entities, kept out of search results
+    pub fn meta_for_not_found(&self, locale: Option<&str>) -> String {
+        let mut tags = vec![
+            create_named_meta("robots", "noindex"),
+            create_meta("og:site_name", self.sitename.get(locale)),
+            self.twitter_site(),
+        ];
+        tags.extend(self.feed_links(None, locale));
+
+        join_meta(tags)
+    }
+
+    pub fn normalized_card(&self, card: &Card) -> CardMeta {
+        CardMeta {
+            title: card.title.trim().to_string(),
+            description: plain_description(&card.description),
+            image: card.preview_url.as_ref().map(|url| self.image_url_for(url)),
+            canonical: format!("{}/open/{}", self.public_url, card.id),
+            published: card.created_at.clone(),
+            modified: card.updated_at.clone(),
+        }
+    }
+
+    pub fn meta_for_user(&self, user: &User, locale: Option<&str>) -> String {
+        let about = match user.bio.as_deref().map(str::trim) {
+            Some(bio) if !bio.is_empty() => truncate(bio, DESCRIPTION_LIMIT),
+            _ => profile_description(user, locale),
+        };
+
+        let title = create_meta("title", &user.display_name);
+        let page_title = self.page_title(&user.display_name, locale);
+        let description = create_meta("description", &about);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "profile");
+        let og_title = create_meta("og:title", &user.display_name);
+        let og_description = create_meta("og:description", &about);
+        let og_url = create_meta("og:url", format!("{}/user/{}", self.public_url, user.id));
+
+        let twitter_card = create_meta("twitter:card", "summary");
+        let twitter_site = self.twitter_site();
+        let twitter_title = create_meta("twitter:title", &user.display_name);
+        let twitter_description = create_meta("twitter:description", &about);
+
+        let mut tags = vec![
+            page_title,
+            title,
+            description,
+            og_sitename,
+            og_type,
+            og_title,
+            og_description,
+            og_url,
+            twitter_card,
+            twitter_site,
+            twitter_title,
+            twitter_description,
+            create_json_ld(&self.person_json_ld(user)),
+        ];
+        if let Some(ref username) = user.username {
+            tags.push(create_meta("profile:username", username));
+        }
+        if let Some(ref avatar_url) = user.avatar_url {
+            let image = self.image_url_for(avatar_url);
+            tags.push(create_meta("og:image", &image));
+            tags.push(create_meta("twitter:image", &image));
+        }
+        tags.extend(extra_meta(&user.extra_meta));
+        tags.extend(self.feed_links(Some(user), locale));
+
+        join_meta(tags)
+    }
+
+    pub fn meta_for_useful(&self, list: &UsefulList, locale: Option<&str>) -> String {
+        let russian = locale.is_some_and(|locale| locale.starts_with("ru"));
+        let heading = if russian {
+            format!("Полезные карточки — {}", list.user.display_name)
+        } else {
+            format!("Useful cards of {}", list.user.display_name)
+        };
+        let about = useful_description(list, russian);
+
+        let title = create_meta("title", &heading);
+        let page_title = self.page_title(&heading, locale);
+        let description = create_meta("description", &about);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "website");
+        let og_title = create_meta("og:title", &heading);
+        let og_description = create_meta("og:description", &about);
+        let og_url = create_meta(
+            "og:url",
+            format!("{}/open/users/{}/useful", self.public_url, list.user.id),
+        );
+
+        let twitter_card = create_meta("twitter:card", "summary");
+        let twitter_site = self.twitter_site();
+        let twitter_title = create_meta("twitter:title", &heading);
+        let twitter_description = create_meta("twitter:description", &about);
+
+        let mut tags = vec![
+            page_title,
+            title,
+            description,
+            og_sitename,
+            og_type,
+            og_title,
+            og_description,
+            og_url,
+            twitter_card,
+            twitter_site,
+            twitter_title,
+            twitter_description,
+        ];
+        tags.extend(self.feed_links(None, locale));
+
+        join_meta(tags)
+    }
+
+    pub fn meta_for_tag(&self, tag: &Tag, locale: Option<&str>) -> String {
+        let russian = locale.is_some_and(|locale| locale.starts_with("ru"));
+        let heading = if russian {
+            format!("{} — карточки по теме", tag.name)
+        } else {
+            format!("{} — cards on the topic", tag.name)
+        };
+        let about = match tag.description.as_deref().map(str::trim) {
+            Some(description) if !description.is_empty() => plain_description(description),
+            _ => tag_description(tag, russian),
+        };
+
+        let title = create_meta("title", &heading);
+        let page_title = self.page_title(&heading, locale);
+        let description = create_meta("description", &about);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "website");
+        let og_title = create_meta("og:title", &heading);
+        let og_description = create_meta("og:description", &about);
+        let og_url = create_meta("og:url", format!("{}/tag/{}", self.public_url, tag.slug));
+
+        let twitter_card = create_meta("twitter:card", "summary");
+        let twitter_site = self.twitter_site();
+        let twitter_title = create_meta("twitter:title", &heading);
+        let twitter_description = create_meta("twitter:description", &about);
+
+        let mut tags = vec![
+            page_title,
+            title,
+            description,
+            og_sitename,
+            og_type,
+            og_title,
+            og_description,
+            og_url,
+            twitter_card,
+            twitter_site,
+            twitter_title,
+            twitter_description,
+        ];
+        // Thin topic pages hurt the whole site in search results
+        if tag.cards_count < self.tag_min_cards {
+            tags.push(create_named_meta("robots", "noindex"));
+        }
+        tags.extend(self.breadcrumbs(
+            "tag",
+            &[(
+                tag.name.as_str(),
+                format!("{}/tag/{}", self.public_url, tag.slug),
+            )],
+            locale,
+        ));
+        tags.extend(extra_meta(&tag.extra_meta));
+        tags.extend(self.feed_links(None, locale));
+
+        join_meta(tags)
+    }
+
+    pub fn meta_for_listing(
+        &self,
+        name: &str,
+        page: u32,
+        listing: &Listing,
+        locale: Option<&str>,
+    ) -> String {
+        let russian = locale.is_some_and(|locale| locale.starts_with("ru"));
+        let base = match (name, russian) {
+            ("popular", false) => "Popular cards",
+            ("popular", true) => "Популярные карточки",
+            (_, false) => "Latest cards",
+            (_, true) => "Новые карточки",
+        };
+        let heading = match (page, russian) {
+            (1, _) => base.to_string(),
+            (page, false) => format!("{} — page {}", base, page),
+            (page, true) => format!("{} — страница {}", base, page),
+        };
+        let base_url = format!("{}/{}", self.public_url, name);
+        let url = if page == 1 {
+            base_url.clone()
+        } else {
+            format!("{}?page={}", base_url, page)
+        };
+
+        let title = create_meta("title", &heading);
+        let page_title = self.page_title(&heading, locale);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "website");
+        let og_title = create_meta("og:title", &heading);
+        let og_url = create_meta("og:url", url);
+
+        let twitter_card = create_meta("twitter:card", "summary");
+        let twitter_site = self.twitter_site();
+        let twitter_title = create_meta("twitter:title", &heading);
+
+        let mut tags = vec![
+            page_title,
+            title,
+            og_sitename,
+            og_type,
+            og_title,
+            og_url,
+            twitter_card,
+            twitter_site,
+            twitter_title,
+        ];
+        tags.extend(pagination_links(&base_url, page, listing.pages));
+        tags.extend(self.feed_links(None, locale));
+
+        join_meta(tags)
+    }
+
+    pub fn meta_for_collection(&self, collection: &Collection, locale: Option<&str>) -> String {
+        let about = collection
+            .description
+            .as_deref()
+            .map(plain_description)
+            .unwrap_or_default();
+
+        let title = create_meta("title", &collection.title);
+        let page_title = self.page_title(&collection.title, locale);
+        let description = create_meta("description", &about);
+
+        let og_sitename = create_meta("og:site_name", self.sitename.get(locale));
+        let og_type = create_meta("og:type", "website");
+        let og_title = create_meta("og:title", &collection.title);
+        let og_description = create_meta("og:description", &about);
+        let og_url = create_meta(
+            "og:url",
+            format!("{}/open/collection/{}", self.public_url, collection.id),
+        );
+        let image = match collection.cover_url {
+            Some(ref cover_url) => Some(self.image_url_for(cover_url)),
+            None if collection
+                .cards
+                .iter()
+                .any(|card| card.preview_url.is_some()) =>
+            {
+                Some(self.collage_url(collection))
+            }
+            None => None,
+        };
+
+        let twitter_card = create_meta(
+            "twitter:card",
+            if image.is_some() {
+                "summary_large_image"
+            } else {
+                "summary"
+            },
+        );
+        let twitter_site = self.twitter_site();
+        let twitter_title = create_meta("twitter:title", &collection.title);
+        let twitter_description = create_meta("twitter:description", &about);
+
+        let mut tags = vec![
+            page_title,
+            title,
+            description,
+            og_sitename,
+            og_type,
+            og_title,
+            og_description,
+            og_url,
+            twitter_card,
+            twitter_site,
+            twitter_title,
+            twitter_description,
+        ];
+        if let Some(ref image) = image {
+            tags.push(create_meta("og:image", image));
+            tags.push(create_meta("twitter:image", image));
+        }
+        tags.extend(self.breadcrumbs(
+            "collection",
+            &[(
+                collection.title.as_str(),
+                format!("{}/open/collection/{}", self.public_url, collection.id),
+            )],
+            locale,
+        ));
+        tags.extend(extra_meta(&collection.extra_meta));
+        tags.extend(self.feed_links(None, locale));
+
+        join_meta(tags)
+    }
+
+    /// `rel="alternate"` links for feed readers: site-wide `FEED_URL` on every page,
+    /// the author's `USER_FEED_URL` on profile pages
+    pub fn feed_links(&self, user: Option<&User>, locale: Option<&str>) -> Vec<String> {
+        let sitename = self.sitename.get(locale);
+        let mut links = Vec::new();
+
+        if let Some(ref url) = self.feed_url {
+            links.push(create_alternate(
+                &self.feed_type,
+                sitename,
+                self.absolute_url(url),
+            ));
+        }
+
+        if let (Some(ref url), Some(user)) = (&self.user_feed_url, user) {
+            links.push(create_alternate(
+                &self.feed_type,
+                format!("{} — {}", user.display_name, sitename),
+                self.absolute_url(&url.replace("{id}", &user.id.to_string())),
+            ));
+        }
+
+        links
+    }
+
+    /// Paths are relative to PUBLIC_URL
+    fn absolute_url(&self, url: &str) -> String {
+        if url.starts_with('/') {
+            format!("{}{}", self.public_url.trim_end_matches('/'), url)
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// Version in query busts crawler caches when collection changes
+    pub fn collage_url(&self, collection: &Collection) -> String {
+        format!(
+            "{}/og-image/collection/{}.jpg?v={}",
+            self.public_url,
+            collection.id,
+            images::version_hash(&collection.updated_at)
+        )
+    }
+
+    /// Generated image of a card without a preview, versioned like the collage
+    pub fn social_card_url(&self, card: &Card) -> String {
+        format!(
+            "{}/og-image/{}.png?v={}",
+            self.public_url,
+            card.id,
+            images::card_version(card)
+        )
+    }
+
+    /// `og:locale` of the card language and `og:locale:alternate` with a `hreflang` link
+    /// for each translation. Invalid languages of the backend are skipped.
+    fn card_languages(&self, card: &Card) -> String {
+        let lang = match card.lang.as_deref().and_then(language_tag) {
+            Some(lang) => lang,
+            None => return "".to_string(),
+        };
+
+        let translations = card
+            .translations
+            .iter()
+            .filter_map(|translation| {
+                language_tag(&translation.lang)
+                    .filter(|other| *other != lang)
+                    .map(|other| (other, translation.id))
+            })
+            .collect::<Vec<_>>();
+
+        let mut tags = vec![create_meta("og:locale", og_locale(&lang))];
+        tags.extend(
+            translations
+                .iter()
+                .map(|(other, _)| create_meta("og:locale:alternate", og_locale(other))),
+        );
+
+        if !translations.is_empty() {
+            tags.push(create_hreflang(
+                &lang,
+                format!("{}/open/{}", self.public_url, card.id),
+            ));
+            tags.extend(translations.iter().map(|(other, id)| {
+                create_hreflang(other, format!("{}/open/{}", self.public_url, id))
+            }));
+        }
+
+        tags.join("\n")
+    }
+
+    /// `twitter:site` of every page, none without `TWITTER_SITE`
+    pub fn twitter_site(&self) -> String {
+        self.twitter_site
+            .as_ref()
+            .map_or("".to_string(), |site| create_meta("twitter:site", site))
+    }
+
+    fn author_url(&self, author: &Author) -> Option<String> {
+        match author.profile_url {
+            Some(ref url) if !url.trim().is_empty() => Some(self.absolute_url(url.trim())),
+            _ => author
+                .id
+                .map(|id| format!("{}/user/{}", self.public_url, id)),
+        }
+    }
+
+    /// Images under IMAGE_URL are served through `/img/{size}/` with `IMAGE_PROXY`,
+    /// absolute URLs of other hosts aren't proxied
+    fn proxies_image(&self, url: &str) -> bool {
+        self.image_proxy && !url.starts_with("http://") && !url.starts_with("https://")
+    }
+
+    pub fn proxied_image_url(&self, url: &str, (width, height): (u32, u32)) -> Option<String> {
+        self.proxies_image(url).then(|| {
+            format!(
+                "{}/img/{}x{}/{}",
+                self.public_url,
+                width,
+                height,
+                url.trim_start_matches('/')
+            )
+        })
+    }
+
+    /// Backend gives image paths relative to IMAGE_URL, but avatars may be absolute urls
+    pub fn image_url_for(&self, url: &str) -> String {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_string()
+        } else {
+            format!(
+                "{}/{}",
+                self.image_url.trim_end_matches('/'),
+                url.trim_start_matches('/')
+            )
+        }
+    }
+}
+
+pub const DESCRIPTION_LIMIT: usize = 200;
+/// Name of `CARD_META_TEMPLATE` in its `Tera`
+pub const CARD_TEMPLATE: &str = "card";
+/// Google ignores `Article` headlines longer than that
+const HEADLINE_LIMIT: usize = 110;
+/// Backend tags beyond that are dropped
+const MAX_EXTRA_META: usize = 20;
+/// Crawlers use the first few `article:tag` only
+const MAX_ARTICLE_TAGS: usize = 10;
+/// Average reading speed and word length, `contentLength` is in characters
+const WORDS_PER_MINUTE: u64 = 200;
+const CHARS_PER_WORD: u64 = 6;
+/// Related cards beyond that only bloat the head
+const MAX_RELATED: usize = 10;
+
+/// Reading time and author as `twitter:label1`/`twitter:data1` pairs, like Medium.
+/// Pairs are numbered in order, so the author is the first one without reading time.
+fn twitter_labels(card: &Card, locale: Option<&str>) -> String {
+    let russian = locale.is_some_and(|locale| locale.starts_with("ru"));
+    let mut labels = Vec::new();
+
+    if let Some(minutes) = reading_minutes(card) {
+        labels.push(if russian {
+            ("Время чтения", format!("{} мин", minutes))
+        } else {
+            ("Reading time", format!("{} min", minutes))
+        });
+    }
+
+    let author = card
+        .author
+        .as_ref()
+        .and_then(|author| author.display_name.as_deref())
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if let Some(author) = author {
+        labels.push((
+            if russian { "Автор" } else { "Written by" },
+            author.to_string(),
+        ));
+    }
+
+    labels
+        .iter()
+        .enumerate()
+        .map(|(index, (label, data))| {
+            format!(
+                "{}\n{}",
+                create_meta(format!("twitter:label{}", index + 1), label),
+                create_meta(format!("twitter:data{}", index + 1), data)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// At least a minute, rounded up
+fn reading_minutes(card: &Card) -> Option<u64> {
+    let words = card
+        .word_count
+        .or_else(|| card.content_length.map(|length| length / CHARS_PER_WORD))?;
+
+    (words > 0).then(|| words.div_ceil(WORDS_PER_MINUTE).max(1))
+}
+
+/// "42 cards · 1.3k useful marks · joined 2019", in russian for `ru` locale
+fn profile_description(user: &User, locale: Option<&str>) -> String {
+    let russian = locale.is_some_and(|locale| locale.starts_with("ru"));
+    let mut parts = Vec::new();
+
+    if let Some(cards) = user.cards_count {
+        parts.push(if russian {
+            format!(
+                "{} {}",
+                compact_number(cards, true),
+                plural_ru(cards, "карточка", "карточки", "карточек")
+            )
+        } else {
+            format!(
+                "{} {}",
+                compact_number(cards, false),
+                if cards == 1 { "card" } else { "cards" }
+            )
+        });
+    }
+
+    if let Some(marks) = user.useful_marks_count {
+        parts.push(if russian {
+            format!(
+                "{} {}",
+                compact_number(marks, true),
+                plural_ru(
+                    marks,
+                    "полезная отметка",
+                    "полезные отметки",
+                    "полезных отметок"
+                )
+            )
+        } else {
+            format!(
+                "{} {}",
+                compact_number(marks, false),
+                if marks == 1 {
+                    "useful mark"
+                } else {
+                    "useful marks"
+                }
+            )
+        });
+    }
+
+    if let Some(year) = user
+        .created_at
+        .as_ref()
+        .and_then(|created| created.get(..4))
+        .filter(|year| year.bytes().all(|byte| byte.is_ascii_digit()))
+    {
+        parts.push(if russian {
+            format!("с нами с {} года", year)
+        } else {
+            format!("joined {}", year)
+        });
+    }
+
+    truncate(&parts.join(" · "), DESCRIPTION_LIMIT)
+}
+
+/// "12 cards: How to center a div, Git rebase, …"
+fn tag_description(tag: &Tag, russian: bool) -> String {
+    if russian {
+        format!(
+            "{} {} по теме «{}»",
+            compact_number(tag.cards_count, true),
+            plural_ru(tag.cards_count, "карточка", "карточки", "карточек"),
+            tag.name
+        )
+    } else {
+        format!(
+            "{} {} about {}",
+            compact_number(tag.cards_count, false),
+            if tag.cards_count == 1 {
+                "card"
+            } else {
+                "cards"
+            },
+            tag.name
+        )
+    }
+}
+
+fn useful_description(list: &UsefulList, russian: bool) -> String {
+    let count = if russian {
+        format!(
+            "{} {}",
+            compact_number(list.count, true),
+            plural_ru(list.count, "карточка", "карточки", "карточек")
+        )
+    } else {
+        format!(
+            "{} {}",
+            compact_number(list.count, false),
+            if list.count == 1 { "card" } else { "cards" }
+        )
+    };
+
+    let titles = list
+        .cards
+        .iter()
+        .take(3)
+        .map(|card| card.title.as_str())
+        .collect::<Vec<_>>();
+
+    if titles.is_empty() {
+        count
+    } else {
+        truncate(
+            &format!("{}: {}", count, titles.join(", ")),
+            DESCRIPTION_LIMIT,
+        )
+    }
+}
+
+/// 950 → "950", 1340 → "1.3k", 2500000 → "2.5M"
+fn compact_number(value: u64, russian: bool) -> String {
+    let (divider, suffix) = match value {
+        0..=999 => return value.to_string(),
+        1_000..=999_999 => (1_000.0, if russian { " тыс." } else { "k" }),
+        _ => (1_000_000.0, if russian { " млн" } else { "M" }),
+    };
+
+    let compact = format!("{:.1}", value as f64 / divider);
+    let compact = compact.trim_end_matches(".0");
+
+    if russian {
+        format!("{}{}", compact.replace('.', ","), suffix)
+    } else {
+        format!("{}{}", compact, suffix)
+    }
+}
+
+fn plural_ru<'a>(value: u64, one: &'a str, few: &'a str, many: &'a str) -> &'a str {
+    match (value % 10, value % 100) {
+        (1, rem) if rem != 11 => one,
+        (2..=4, rem) if !(12..=14).contains(&rem) => few,
+        _ => many,
+    }
+}
+
+/// At most `limit` graphemes with the ellipsis, emoji and combining marks are never split
+pub fn truncate(text: &str, limit: usize) -> String {
+    if text.graphemes(true).nth(limit).is_none() {
+        return text.to_string();
+    }
+
+    let cut = text
+        .graphemes(true)
+        .take(limit.saturating_sub(1))
+        .collect::<String>();
+    format!("{}…", cut.trim_end())
+}
+
+/// Description for tags from user-written markdown
+pub fn plain_description(markdown: &str) -> String {
+    truncate(&markdown::to_text(markdown), DESCRIPTION_LIMIT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_text_of_the_limit() {
+        assert_eq!(truncate("abcde", 5), "abcde");
+        assert_eq!(truncate("abcdef", 5), "abcd…");
+        assert_eq!(truncate("", 5), "");
+    }
+
+    #[test]
+    fn truncate_keeps_emoji_sequences_whole() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let flag = "\u{1F1FA}\u{1F1E6}";
+
+        assert_eq!(truncate(&family.repeat(3), 3), family.repeat(3));
+        assert_eq!(
+            truncate(&family.repeat(4), 3),
+            format!("{}…", family.repeat(2))
+        );
+        assert_eq!(
+            truncate(&format!("ab{}cd", flag), 4),
+            format!("ab{}…", flag)
+        );
+    }
+
+    #[test]
+    fn truncate_keeps_combining_marks() {
+        let accented = "e\u{301}";
+
+        assert_eq!(truncate(&accented.repeat(4), 4), accented.repeat(4));
+        assert_eq!(
+            truncate(&accented.repeat(5), 4),
+            format!("{}…", accented.repeat(3))
+        );
+    }
+
+    #[test]
+    fn truncate_counts_cjk_as_one_each() {
+        assert_eq!(truncate("日本語のテキスト", 8), "日本語のテキスト");
+        assert_eq!(truncate("日本語のテキスト", 4), "日本語…");
+    }
+
+    #[test]
+    fn truncate_drops_trailing_space_before_ellipsis() {
+        assert_eq!(truncate("ab cdef", 4), "ab…");
+    }
+
+    #[test]
+    fn create_meta_truncates_limited_properties() {
+        let title = "\u{1F44D}\u{1F3FD}".repeat(100);
+
+        assert_eq!(
+            create_meta("og:title", &title),
+            format!(
+                r#"<meta property="og:title" content="{}…" />"#,
+                "\u{1F44D}\u{1F3FD}".repeat(94)
+            )
+        );
+        assert_eq!(
+            create_meta("og:site_name", &title),
+            format!(r#"<meta property="og:site_name" content="{}" />"#, title)
+        );
+    }
+
+    #[test]
+    fn create_meta_escapes_content() {
+        assert_eq!(
+            create_meta("og:title", "<b>\"Tom & Jerry\"</b>"),
+            r#"<meta property="og:title" content="&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;" />"#
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use futures::Future;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, Histogram, HistogramTimer, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::alloc;
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_http_requests_total",
+        "Answered requests by route pattern and status",
+        &["route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_IN_FLIGHT: IntGauge = register_int_gauge!(
+        "ssi_http_requests_in_flight",
+        "Requests being handled right now"
+    )
+    .unwrap();
+    static ref REJECTED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_rejected_requests_total",
+        "Requests rejected before reaching the backend",
+        &["resource", "reason"]
+    )
+    .unwrap();
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_requests_total",
+        "Served pages by resource type and how the meta was obtained",
+        &["resource", "outcome"]
+    )
+    .unwrap();
+    static ref BACKEND_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_backend_requests_total",
+        "Requests to the backend by resource type and result",
+        &["resource", "outcome"]
+    )
+    .unwrap();
+    static ref BACKEND_DURATION: Histogram = register_histogram!(
+        "ssi_backend_request_duration_seconds",
+        "Time to fetch meta from the backend, including the body"
+    )
+    .unwrap();
+    static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "ssi_cache_lookups_total",
+        "Cache lookups by cache (namespace, microcache or image) and outcome: hit or miss",
+        &["cache", "outcome"]
+    )
+    .unwrap();
+    static ref THROTTLED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_backend_throttled_requests_total",
+        "Backend requests answered with 429 (limited) or not sent during backoff (skipped)",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref PUBLISH_JOBS: IntCounterVec = register_int_counter_vec!(
+        "ssi_publish_jobs_total",
+        "Cards pre-rendered after the publish webhook",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref CDN_PURGES: IntCounterVec = register_int_counter_vec!(
+        "ssi_cdn_purges_total",
+        "Card purges sent to the CDN API",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref OVERLOADED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ssi_overloaded_requests_total",
+        "Requests answered with 503 because the service is at capacity",
+        &["reason"]
+    )
+    .unwrap();
+    static ref DROPPED_CONNECTIONS: IntCounterVec = register_int_counter_vec!(
+        "ssi_dropped_connections_total",
+        "Connections closed by the service before a request was read",
+        &["reason"]
+    )
+    .unwrap();
+    static ref COALESCED_FETCHES: IntCounterVec = register_int_counter_vec!(
+        "ssi_coalesced_fetches_total",
+        "Requests that waited for a backend fetch of the same entity instead of sending their own",
+        &["namespace"]
+    )
+    .unwrap();
+    static ref REVALIDATED: IntCounterVec = register_int_counter_vec!(
+        "ssi_revalidated_total",
+        "Expired entries served while a fresh one is fetched in the background",
+        &["namespace"]
+    )
+    .unwrap();
+    static ref PANICS: IntCounter =
+        register_int_counter!("ssi_panics_total", "Handler panics answered with 500").unwrap();
+    static ref RESIDENT_MEMORY: IntGauge = register_int_gauge!(
+        "ssi_process_resident_memory_bytes",
+        "Resident set size of the process"
+    )
+    .unwrap();
+    static ref ALLOCATOR_MEMORY: IntGaugeVec = register_int_gauge_vec!(
+        "ssi_allocator_memory_bytes",
+        "Memory usage reported by the global allocator",
+        &["allocator", "kind"]
+    )
+    .unwrap();
+    static ref TEMPLATE_SIZE: IntGaugeVec = register_int_gauge_vec!(
+        "ssi_template_bytes",
+        "Size of loaded html templates",
+        &["template"]
+    )
+    .unwrap();
+    static ref TEMPLATE_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "ssi_template_info",
+        "Hash of the active html template",
+        &["hash"]
+    )
+    .unwrap();
+    static ref TEMPLATE_RELOADS: IntCounterVec = register_int_counter_vec!(
+        "ssi_template_reloads_total",
+        "Template reloads after startup by outcome: ok or failed",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref CACHE_MEMORY: IntGaugeVec = register_int_gauge_vec!(
+        "ssi_cache_memory_bytes",
+        "Approximate memory used by in-process caches",
+        &["cache"]
+    )
+    .unwrap();
+    static ref MEMORY_LIMIT: IntGauge =
+        register_int_gauge!("ssi_memory_limit_bytes", "Memory limit of the container").unwrap();
+}
+
+/// Kind of page being served, used as `resource` label on every metric
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    Card,
+    User,
+    UsefulList,
+    Collection,
+    Listing,
+    Tag,
+    /// Routes of `ROUTES_FILE`
+    Route,
+    Embed,
+    Api,
+    Home,
+    /// Any other SPA route
+    Page,
+    Sitemap,
+    Feed,
+}
+
+impl Resource {
+    pub fn label(self) -> &'static str {
+        match self {
+            Resource::Card => "card",
+            Resource::User => "user",
+            Resource::UsefulList => "useful_list",
+            Resource::Collection => "collection",
+            Resource::Listing => "listing",
+            Resource::Tag => "tag",
+            Resource::Route => "route",
+            Resource::Embed => "embed",
+            Resource::Api => "api",
+            Resource::Home => "home",
+            Resource::Page => "page",
+            Resource::Sitemap => "sitemap",
+            Resource::Feed => "feed",
+        }
+    }
+}
+
+/// How the response for a page was produced, used as `outcome` label
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Meta served from the cache
+    Hit,
+    /// Meta fetched from the backend and injected
+    Miss,
+    /// Backend failed or returned nothing, plain template served
+    Fallback,
+    /// Backend skipped because degraded mode is on
+    Degraded,
+    /// Request rejected before doing any work
+    Rejected,
+    /// Site meta from config, no entity behind the page
+    Static,
+    /// Client's copy of the page is still valid, answered with 304
+    NotModified,
+}
+
+impl Outcome {
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::Miss => "miss",
+            Outcome::Fallback => "fallback",
+            Outcome::Degraded => "degraded",
+            Outcome::Rejected => "rejected",
+            Outcome::Static => "static",
+            Outcome::NotModified => "not_modified",
+        }
+    }
+}
+
+pub fn record_request(resource: Resource, outcome: Outcome) {
+    REQUESTS
+        .with_label_values(&[resource.label(), outcome.label()])
+        .inc();
+}
+
+pub fn record_rejected(resource: Resource, reason: &str) {
+    REJECTED_REQUESTS
+        .with_label_values(&[resource.label(), reason])
+        .inc();
+    record_request(resource, Outcome::Rejected);
+}
+
+pub fn record_backend(resource: Resource, ok: bool) {
+    BACKEND_REQUESTS
+        .with_label_values(&[resource.label(), if ok { "ok" } else { "error" }])
+        .inc();
+}
+
+/// Observes the fetch when dropped, so failed fetches are timed as well
+pub fn backend_timer() -> HistogramTimer {
+    BACKEND_DURATION.start_timer()
+}
+
+pub fn record_cache_lookup(cache: &str, hit: bool) {
+    CACHE_LOOKUPS
+        .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+pub fn record_throttled(outcome: &str) {
+    THROTTLED_REQUESTS.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_publish(outcome: &str) {
+    PUBLISH_JOBS.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_cdn_purge(outcome: &str) {
+    CDN_PURGES.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_overloaded(reason: &str) {
+    OVERLOADED_REQUESTS.with_label_values(&[reason]).inc();
+}
+
+pub fn record_dropped_connection(reason: &str) {
+    DROPPED_CONNECTIONS.with_label_values(&[reason]).inc();
+}
+
+pub fn record_coalesced(namespace: &str) {
+    COALESCED_FETCHES.with_label_values(&[namespace]).inc();
+}
+
+pub fn record_revalidated(namespace: &str) {
+    REVALIDATED.with_label_values(&[namespace]).inc();
+}
+
+pub fn record_panic() {
+    PANICS.inc();
+}
+
+/// Pattern of a resource, the `route` label of its requests
+#[derive(Debug)]
+struct Route(String);
+
+/// Resource whose requests are counted under its pattern,
+/// requests of other resources and of the default service are counted as `other`
+pub fn resource(pattern: &str) -> actix_web::Resource {
+    web::resource(pattern).data(Route(pattern.to_string()))
+}
+
+/// Decrements the gauge however the request ends, including dropped futures
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        HTTP_IN_FLIGHT.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        HTTP_IN_FLIGHT.dec();
+    }
+}
+
+/// Counts answered requests by route and status, and the ones being handled
+pub fn track<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let in_flight = InFlight::start();
+
+    Box::new(srv.call(req).then(move |res| {
+        drop(in_flight);
+        match res {
+            // Resource data is put into the request by routing
+            Ok(ref res) => record_http(
+                res.request()
+                    .app_data::<Route>()
+                    .map_or("other", |route| &route.0),
+                res.status(),
+            ),
+            Err(ref err) => record_http("other", err.as_response_error().error_response().status()),
+        }
+        res
+    }))
+}
+
+fn record_http(route: &str, status: StatusCode) {
+    HTTP_REQUESTS
+        .with_label_values(&[route, status.as_str()])
+        .inc();
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/metrics").route(web::get().to(render)));
+}
+
+fn render() -> HttpResponse {
+    update_memory();
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+pub fn set_template_size(template: &str, bytes: usize) {
+    TEMPLATE_SIZE
+        .with_label_values(&[template])
+        .set(bytes as i64);
+}
+
+pub fn set_template_hash(hash: &str) {
+    TEMPLATE_INFO.reset();
+    TEMPLATE_INFO.with_label_values(&[hash]).set(1);
+}
+
+pub fn record_template_reload(ok: bool) {
+    TEMPLATE_RELOADS
+        .with_label_values(&[if ok { "ok" } else { "failed" }])
+        .inc();
+}
+
+pub fn set_cache_memory(cache: &str, bytes: u64) {
+    CACHE_MEMORY.with_label_values(&[cache]).set(bytes as i64);
+}
+
+pub fn set_memory_limit(bytes: u64) {
+    MEMORY_LIMIT.set(bytes as i64);
+}
+
+fn update_memory() {
+    if let Some(resident) = alloc::resident_memory() {
+        RESIDENT_MEMORY.set(resident as i64);
+    }
+
+    if let Some(stats) = alloc::stats() {
+        ALLOCATOR_MEMORY
+            .with_label_values(&[alloc::name(), "allocated"])
+            .set(stats.allocated as i64);
+        ALLOCATOR_MEMORY
+            .with_label_values(&[alloc::name(), "resident"])
+            .set(stats.resident as i64);
+    }
+}
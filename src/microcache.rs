@@ -0,0 +1,121 @@
+use actix_web::http::{HeaderMap, StatusCode};
+use actix_web::HttpResponse;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{env_duration, env_or};
+use crate::metrics;
+use crate::storage::PageBody;
+
+/// Pages put together during the last `MICROCACHE_TTL`, answered as they are.
+/// A viral card gets one template injection per TTL instead of one per request.
+/// Unlike the meta cache it lives in every process and is never invalidated,
+/// pages are at most TTL behind.
+#[derive(Debug)]
+pub struct Microcache {
+    ttl: Duration,
+    pages: Option<Mutex<LruCache<String, Page>>>,
+}
+
+/// Extension of 200 responses that only stand in for a page,
+/// like the bare template when the backend fails; they are never kept
+#[derive(Debug)]
+pub struct Fallback;
+
+#[derive(Debug)]
+struct Page {
+    headers: HeaderMap,
+    body: PageBody,
+    expires: Instant,
+}
+
+impl Microcache {
+    /// `MICROCACHE_TTL` and `MICROCACHE_CAPACITY` pages, either of them 0 disables it
+    pub fn from_env() -> Self {
+        let ttl = env_duration("MICROCACHE_TTL", Duration::from_secs(1));
+        let capacity = NonZeroUsize::new(env_or("MICROCACHE_CAPACITY", 500));
+
+        Microcache {
+            ttl,
+            pages: capacity
+                .filter(|_| !ttl.is_zero())
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<HttpResponse> {
+        let mut pages = self.pages.as_ref()?.lock().unwrap();
+
+        let response = match pages.get(key) {
+            Some(page) if page.expires > Instant::now() => {
+                let mut response = page.body.clone().respond(&mut HttpResponse::Ok());
+                *response.headers_mut() = page.headers.clone();
+                Some(response)
+            }
+            Some(_) => {
+                pages.pop(key);
+                None
+            }
+            None => None,
+        };
+        metrics::record_cache_lookup("microcache", response.is_some());
+        response
+    }
+
+    /// Successful pages only, errors, fallbacks and 304s are cheap to answer again
+    pub fn put(&self, key: String, response: &HttpResponse) {
+        let pages = match self.pages {
+            Some(ref pages) => pages,
+            None => return,
+        };
+        if response.status() != StatusCode::OK || response.extensions().get::<Fallback>().is_some()
+        {
+            return;
+        }
+
+        if let Some(body) = PageBody::of(response) {
+            pages.lock().unwrap().put(
+                key,
+                Page {
+                    headers: response.headers().clone(),
+                    body,
+                    expires: Instant::now() + self.ttl,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn microcache() -> Microcache {
+        Microcache {
+            ttl: Duration::from_secs(60),
+            pages: Some(Mutex::new(LruCache::new(NonZeroUsize::new(10).unwrap()))),
+        }
+    }
+
+    #[test]
+    fn put_keeps_pages() {
+        let microcache = microcache();
+
+        microcache.put("/open/1".to_string(), &HttpResponse::Ok().body("page"));
+
+        assert!(microcache.get("/open/1").is_some());
+    }
+
+    #[test]
+    fn put_skips_fallbacks() {
+        let microcache = microcache();
+        let mut response = HttpResponse::Ok().body("template");
+        response.extensions_mut().insert(Fallback);
+
+        microcache.put("/open/1".to_string(), &response);
+
+        assert!(microcache.get("/open/1").is_none());
+    }
+}
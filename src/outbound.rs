@@ -0,0 +1,174 @@
+use actix_web::http::{HeaderMap, StatusCode};
+use log::{log, log_enabled, Level};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::flags::env_flag;
+
+/// Header values that never reach the logs
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api-key",
+];
+/// Query parameters that never reach the logs
+const SECRET_PARAMS: &[&str] = &[
+    "token",
+    "access_token",
+    "api_key",
+    "apikey",
+    "key",
+    "secret",
+    "password",
+];
+/// Longer response bodies are cut
+const BODY_LIMIT: usize = 1024;
+const REDACTED: &str = "[redacted]";
+
+/// Backend requests are always logged at debug level.
+/// The switch raises them to info, so they show up without restarting with `RUST_LOG=debug`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    set_enabled(env_flag("BACKEND_LOG"));
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(value: bool) {
+    ENABLED.store(value, Ordering::Relaxed);
+}
+
+fn level() -> Level {
+    if enabled() {
+        Level::Info
+    } else {
+        Level::Debug
+    }
+}
+
+/// A single backend request, logged when it is sent and when it completes
+#[derive(Debug)]
+pub struct Trace {
+    method: &'static str,
+    url: String,
+    started: Instant,
+}
+
+impl Trace {
+    /// `None` when the request wouldn't be logged anyway
+    pub fn start(method: &'static str, url: &str, headers: &HeaderMap) -> Option<Trace> {
+        let level = level();
+        if !log_enabled!(level) {
+            return None;
+        }
+
+        let trace = Trace {
+            method,
+            url: redact_url(url),
+            started: Instant::now(),
+        };
+
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("<binary>")
+                };
+                format!("{}: {}", name, value)
+            })
+            .collect::<Vec<_>>();
+
+        log!(
+            level,
+            "--> {} {} [{}]",
+            trace.method,
+            trace.url,
+            headers.join(", ")
+        );
+        Some(trace)
+    }
+
+    pub fn response(&self, status: StatusCode, body: &[u8]) {
+        let shown = &body[..body.len().min(BODY_LIMIT)];
+        let cut = if shown.len() < body.len() {
+            format!("... ({} bytes)", body.len())
+        } else {
+            String::new()
+        };
+
+        log!(
+            level(),
+            "<-- {} {} {} in {:?}: {}{}",
+            status.as_u16(),
+            self.method,
+            self.url,
+            self.started.elapsed(),
+            String::from_utf8_lossy(shown),
+            cut
+        );
+    }
+
+    pub fn failed(&self, err: &dyn Display) {
+        log!(
+            level(),
+            "<-- failed {} {} in {:?}: {}",
+            self.method,
+            self.url,
+            self.started.elapsed(),
+            err
+        );
+    }
+}
+
+/// Hides credentials in userinfo and secret query parameters
+fn redact_url(url: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (url, None),
+    };
+
+    let base = match base.split_once("://") {
+        Some((scheme, rest)) => {
+            let authority_end = rest.find('/').unwrap_or(rest.len());
+            match rest[..authority_end].rsplit_once('@') {
+                Some((_, host)) => {
+                    format!(
+                        "{}://{}@{}{}",
+                        scheme,
+                        REDACTED,
+                        host,
+                        &rest[authority_end..]
+                    )
+                }
+                None => base.to_string(),
+            }
+        }
+        None => base.to_string(),
+    };
+
+    let query = match query {
+        Some(query) => query,
+        None => return base,
+    };
+
+    let params = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if SECRET_PARAMS.contains(&name.to_lowercase().as_str()) => {
+                format!("{}={}", name, REDACTED)
+            }
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    format!("{}?{}", base, params.join("&"))
+}
@@ -0,0 +1,61 @@
+/// Switches the process to `RUN_AS_USER` (and `RUN_AS_GROUP`, primary group of the user by default)
+/// once the listening sockets are bound, so the service can start as root to bind ports below 1024
+/// without serving requests as root.
+#[cfg(unix)]
+pub fn drop_from_env() -> Result<(), String> {
+    use log::info;
+    use nix::unistd::{self, Group, User};
+
+    let user_name = match env("RUN_AS_USER") {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+
+    let user = User::from_name(&user_name)
+        .map_err(|err| format!("cannot look up user {:?}: {}", user_name, err))?
+        .ok_or_else(|| format!("no such user {:?}", user_name))?;
+
+    let gid = match env("RUN_AS_GROUP") {
+        Some(group_name) => {
+            Group::from_name(&group_name)
+                .map_err(|err| format!("cannot look up group {:?}: {}", group_name, err))?
+                .ok_or_else(|| format!("no such group {:?}", group_name))?
+                .gid
+        }
+        None => user.gid,
+    };
+
+    if unistd::geteuid() == user.uid && unistd::getegid() == gid {
+        return Ok(());
+    }
+
+    // Supplementary groups first, changing them needs root just like the group itself
+    unistd::setgroups(&[gid]).map_err(|err| format!("cannot set groups: {}", err))?;
+    unistd::setgid(gid).map_err(|err| format!("cannot set group {}: {}", gid, err))?;
+    unistd::setuid(user.uid).map_err(|err| format!("cannot set user {}: {}", user.uid, err))?;
+
+    if unistd::setuid(unistd::Uid::from_raw(0)).is_ok() {
+        return Err("root privileges can be regained after dropping them".to_string());
+    }
+
+    info!(
+        "Dropped privileges to user {} ({}), group {}",
+        user_name, user.uid, gid
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_from_env() -> Result<(), String> {
+    match env("RUN_AS_USER") {
+        Some(_) => Err("RUN_AS_USER is supported on Unix only".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,96 @@
+use actix_web::web;
+
+/// CPU profiler endpoint, compiled in only with `--features profiling`.
+///
+/// `GET /_debug/pprof?seconds=10&frequency=99&format=flamegraph` samples the running process
+/// and answers with an SVG flamegraph, `format=proto` gives a pprof protobuf for `go tool pprof`.
+#[cfg(not(feature = "profiling"))]
+pub fn configure(_cfg: &mut web::ServiceConfig) {}
+
+#[cfg(feature = "profiling")]
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        crate::metrics::resource("/_debug/pprof").route(web::get().to_async(handler::pprof)),
+    );
+}
+
+#[cfg(feature = "profiling")]
+mod handler {
+    use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+    use futures::{future, Future};
+    use serde::Deserialize;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use crate::{admin, config::Config};
+
+    #[derive(Debug, Deserialize)]
+    pub struct ProfileQuery {
+        seconds: Option<u64>,
+        frequency: Option<i32>,
+        format: Option<String>,
+    }
+
+    pub fn pprof(
+        req: HttpRequest,
+        query: web::Query<ProfileQuery>,
+        config: web::Data<Arc<Config>>,
+    ) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+        use log::info;
+        use tokio_timer::Delay;
+
+        if !admin::authorized(&req, &config) {
+            return Box::new(future::ok(admin::forbidden()));
+        }
+
+        let seconds = query.seconds.unwrap_or(10).clamp(1, 120);
+        let frequency = query.frequency.unwrap_or(99).clamp(1, 1000);
+        let proto = query
+            .format
+            .as_ref()
+            .is_some_and(|format| format == "proto");
+
+        let guard = match pprof::ProfilerGuardBuilder::default()
+            .frequency(frequency)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+        {
+            Ok(guard) => guard,
+            Err(err) => return Box::new(future::err(error::ErrorConflict(err.to_string()))),
+        };
+
+        info!("Profiling for {}s at {}Hz", seconds, frequency);
+
+        Box::new(
+            Delay::new(Instant::now() + Duration::from_secs(seconds))
+                .map_err(error::ErrorInternalServerError)
+                .and_then(move |_| {
+                    let report = guard
+                        .report()
+                        .build()
+                        .map_err(error::ErrorInternalServerError)?;
+
+                    if proto {
+                        use pprof::protos::Message;
+
+                        let profile = report.pprof().map_err(error::ErrorInternalServerError)?;
+                        let mut body = Vec::new();
+                        profile
+                            .encode(&mut body)
+                            .map_err(error::ErrorInternalServerError)?;
+
+                        Ok(HttpResponse::Ok()
+                            .content_type("application/octet-stream")
+                            .body(body))
+                    } else {
+                        let mut body = Vec::new();
+                        report
+                            .flamegraph(&mut body)
+                            .map_err(error::ErrorInternalServerError)?;
+
+                        Ok(HttpResponse::Ok().content_type("image/svg+xml").body(body))
+                    }
+                }),
+        )
+    }
+}
@@ -0,0 +1,354 @@
+use actix_web::{client::Client, web, Error, HttpRequest, HttpResponse};
+use futures::sync::mpsc;
+use futures::{future, Future, Stream};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::admin;
+use crate::audit;
+use crate::backend::{self, Card, Fetched};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::{env_or, Config};
+use crate::flags::Flags;
+use crate::images;
+use crate::metrics::{self, Resource};
+use crate::storage::{Storage, Templates};
+
+/// Warms caches for freshly published cards, so the very first crawler hit
+/// doesn't wait for the backend.
+///
+/// The backend calls `POST /_hooks/published` with `{"cardId": 1}` and
+/// `Authorization: Bearer {HOOKS_TOKEN}`. Jobs are processed by a dedicated thread:
+/// meta is fetched into the cache, the page is rendered into the `page` namespace,
+/// then every `PUBLISH_PING_URLS` is requested.
+/// In degraded mode the backend isn't asked: the hook answers 503 so it is sent again later,
+/// jobs queued before are dropped.
+#[derive(Debug)]
+pub struct Publisher {
+    sender: Mutex<mpsc::Sender<Job>>,
+}
+
+#[derive(Debug)]
+struct Job {
+    card_id: u32,
+}
+
+#[derive(Clone)]
+struct Worker {
+    client: Client,
+    config: Arc<Config>,
+    templates: Arc<Templates>,
+    chaos: Arc<Chaos>,
+    cache: Arc<Cache>,
+    flags: Arc<Flags>,
+    ping_urls: Arc<Vec<String>>,
+}
+
+impl Publisher {
+    /// `PUBLISH_QUEUE_SIZE` jobs may wait, `PUBLISH_CONCURRENCY` are processed at once
+    pub fn spawn(
+        config: Arc<Config>,
+        templates: Arc<Templates>,
+        chaos: Arc<Chaos>,
+        cache: Arc<Cache>,
+        flags: Arc<Flags>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(env_or("PUBLISH_QUEUE_SIZE", 1000));
+        let concurrency = env_or("PUBLISH_CONCURRENCY", 4).max(1);
+        let ping_urls = std::env::var("PUBLISH_PING_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        std::thread::Builder::new()
+            .name("publisher".to_string())
+            .spawn(move || {
+                let mut system = actix_rt::System::new("publisher");
+
+                let worker = Worker {
+                    client: Client::default(),
+                    config,
+                    templates,
+                    chaos,
+                    cache,
+                    flags,
+                    ping_urls: Arc::new(ping_urls),
+                };
+
+                let jobs = receiver
+                    .map(move |job| worker.clone().warm(job))
+                    .buffer_unordered(concurrency)
+                    .for_each(|_| Ok(()));
+
+                let _ = system.block_on(jobs);
+            })
+            .expect("cannot spawn publisher thread");
+
+        Publisher {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    /// `false` when the queue is full
+    fn enqueue(&self, card_id: u32) -> bool {
+        self.sender
+            .lock()
+            .unwrap()
+            .try_send(Job { card_id })
+            .is_ok()
+    }
+}
+
+impl Worker {
+    fn warm(self, job: Job) -> Box<dyn Future<Item = (), Error = ()>> {
+        use log::{error, info, warn};
+
+        if self.flags.degraded() {
+            warn!(
+                "Degraded mode is on, card {} isn't pre-rendered",
+                job.card_id
+            );
+            metrics::record_publish("degraded");
+            return Box::new(future::ok(()));
+        }
+
+        // Published card replaces whatever was cached before
+        let invalidated = invalidate(&self.cache, &self.config, &self.templates, job.card_id);
+        let fetched = {
+            let (client, chaos, cache) =
+                (self.client.clone(), self.chaos.clone(), self.cache.clone());
+            let (key, url) = (
+                job.card_id.to_string(),
+                self.config.backend_card_url(job.card_id),
+            );
+            invalidated.and_then(move |_| {
+                backend::fetch_cached::<Card>(&client, &chaos, &cache, Namespace::Card, key, url)
+            })
+        };
+        // The edge drops the old page while the new one is rendered
+        let fetched = purge_edge(self.config.clone(), job.card_id)
+            .then(|_| Ok(()))
+            .and_then(move |()| fetched);
+        let fetched = {
+            let client = self.client.clone();
+            let cache = self.cache.clone();
+            let config = self.config.clone();
+            fetched.and_then(move |fetched| {
+                images::check_preview(&client, &cache, &config, fetched.without_private())
+            })
+        };
+
+        Box::new(
+            fetched.then(move |fetched| -> Box<dyn Future<Item = (), Error = ()>> {
+                match fetched {
+                    Ok(Fetched {
+                        entity: Some(card), ..
+                    }) => {
+                        metrics::record_backend(Resource::Card, true);
+
+                        let storage = self.templates.current();
+                        let page = storage.inject(&self.config.meta_for_card(&card, None));
+                        self.cache.put(
+                            Namespace::Page,
+                            prerendered_key(job.card_id, &storage),
+                            serde_json::Value::String(page.into_string()),
+                            None,
+                        );
+
+                        info!("Card {} is pre-rendered", job.card_id);
+                        metrics::record_publish("warmed");
+
+                        Box::new(self.ping())
+                    }
+                    Ok(_) => {
+                        metrics::record_backend(Resource::Card, false);
+                        warn!("Published card {} is not found", job.card_id);
+                        metrics::record_publish("not_found");
+
+                        Box::new(future::ok(()))
+                    }
+                    Err(err) => {
+                        metrics::record_backend(Resource::Card, false);
+                        error!("Failed to pre-render card {}: {:#?}", job.card_id, err);
+                        metrics::record_publish("error");
+
+                        Box::new(future::ok(()))
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Notifies search engines that the sitemap has changed
+    fn ping(&self) -> impl Future<Item = (), Error = ()> {
+        use log::warn;
+
+        let requests = self.ping_urls.iter().cloned().map(|url| {
+            self.client.get(url.as_str()).send().then(move |response| {
+                match response {
+                    Ok(ref response) if response.status().is_success() => {}
+                    Ok(response) => warn!("Ping {} answered {}", url, response.status()),
+                    Err(err) => warn!("Ping {} failed: {}", url, err),
+                }
+                Ok::<_, ()>(())
+            })
+        });
+
+        future::join_all(requests.collect::<Vec<_>>()).map(|_| ())
+    }
+}
+
+/// Both are called with tokens, so calls land in the audit log like admin ones
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        metrics::resource("/_hooks/published")
+            .wrap_fn(audit::record)
+            .route(web::post().to(published)),
+    )
+    .service(
+        metrics::resource("/internal/purge/{card_id}")
+            .wrap_fn(audit::record)
+            .route(web::post().to_async(purge)),
+    );
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Published {
+    card_id: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Queued {
+    card_id: u32,
+    queued: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Purged {
+    card_id: u32,
+    removed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cdn_purged: Option<bool>,
+}
+
+fn published(
+    req: HttpRequest,
+    body: web::Json<Published>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    publisher: web::Data<Arc<Publisher>>,
+) -> Result<HttpResponse, Error> {
+    if !admin::bearer_matches(&req, config.hooks_token.as_deref()) {
+        return Ok(admin::forbidden());
+    }
+
+    // 503 tells the backend to send the hook again later
+    let queued = if flags.degraded() {
+        metrics::record_publish("degraded");
+        false
+    } else if publisher.enqueue(body.card_id) {
+        true
+    } else {
+        log::warn!("Publish queue is full, card {} is dropped", body.card_id);
+        metrics::record_publish("dropped");
+        false
+    };
+
+    let mut response = if queued {
+        HttpResponse::Accepted()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    Ok(response.json(Queued {
+        card_id: body.card_id,
+        queued,
+    }))
+}
+
+/// Evicts meta and the rendered page of an edited card, the next hit fetches it again.
+/// Unlike `/_hooks/published` nothing is fetched ahead. With `CDN_PURGE` the card pages
+/// are purged at the edge as well, the answer waits for the CDN.
+fn purge(
+    req: HttpRequest,
+    card_id: web::Path<u32>,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if !admin::bearer_matches(&req, config.hooks_token.as_deref()) {
+        return Box::new(future::ok(admin::forbidden()));
+    }
+
+    let card_id = card_id.into_inner();
+    let removed = invalidate(&cache, &config, &templates, card_id);
+
+    Box::new(
+        removed
+            .join(purge_edge(config.get_ref().clone(), card_id).then(Ok))
+            .map(move |(removed, cdn_purged)| {
+                HttpResponse::Ok().json(Purged {
+                    card_id,
+                    removed,
+                    cdn_purged: cdn_purged.unwrap_or(Some(false)),
+                })
+            }),
+    )
+}
+
+/// Pre-rendered page of the card in the `page` namespace. Pages of another template
+/// are never served, a frontend deploy doesn't need to clear the namespace.
+pub fn prerendered_key(card_id: u32, storage: &Storage) -> String {
+    format!("{}\n{}", card_id, storage.hash)
+}
+
+/// Card pages at the CDN, `None` without `CDN_PURGE`
+fn purge_edge(config: Arc<Config>, card_id: u32) -> impl Future<Item = Option<bool>, Error = ()> {
+    web::block(move || {
+        Ok::<_, ()>(
+            config
+                .cdn_purge
+                .as_ref()
+                .map(|cdn| cdn.purge_card(&config, card_id)),
+        )
+    })
+    .map_err(|_| ())
+}
+
+/// Meta, content and the pre-rendered page of the card, meta and content of
+/// `/{locale}/open/{card_id}` pages as well. Whether anything was cached.
+fn invalidate(
+    cache: &Cache,
+    config: &Config,
+    templates: &Templates,
+    card_id: u32,
+) -> impl Future<Item = bool, Error = Error> {
+    let key = card_id.to_string();
+    let keys = config
+        .path_locales
+        .iter()
+        .map(|locale| backend::localized_key(&key, locale))
+        .chain(std::iter::once(key.clone()));
+
+    let mut removed = keys
+        .flat_map(|key| {
+            vec![
+                cache.invalidate(Namespace::Card, &key),
+                cache.invalidate(Namespace::Content, &key),
+            ]
+        })
+        .collect::<Vec<_>>();
+    removed.push(cache.invalidate(
+        Namespace::Page,
+        &prerendered_key(card_id, &templates.current()),
+    ));
+
+    future::join_all(removed).map(|removed| removed.into_iter().any(|removed| removed))
+}
@@ -0,0 +1,186 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpResponse, ResponseError};
+use futures::{future, Future};
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use crate::metrics;
+use crate::storage::Templates;
+
+const REQUEST_ID: &str = "x-request-id";
+
+/// Request being served, logged when its handler panics
+#[derive(Debug, Clone)]
+struct Context {
+    request_id: String,
+    path: String,
+    card_id: Option<String>,
+}
+
+/// Handler panicked, answered with 500 instead of a dropped connection
+#[derive(Debug)]
+struct Panicked {
+    request_id: String,
+    /// 500 page of `ERROR_PAGES_DIR`
+    page: Option<String>,
+}
+
+impl std::fmt::Display for Panicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "handler panicked, request {}", self.request_id)
+    }
+}
+
+impl ResponseError for Panicked {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::InternalServerError();
+        response.header(REQUEST_ID, self.request_id.as_str());
+
+        match self.page {
+            Some(ref page) => response
+                .content_type("text/html; charset=utf-8")
+                .header("cache-control", "no-store")
+                .body(page.clone()),
+            None => response
+                .content_type("text/plain; charset=utf-8")
+                .body("Internal Server Error"),
+        }
+    }
+
+    fn render_response(&self) -> HttpResponse {
+        self.error_response()
+    }
+}
+
+/// Outermost middleware: catches panics of handlers and their futures.
+/// Every response gets `X-Request-Id`, taken from the request or generated.
+pub fn catch_panics<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let context = Context {
+        request_id: req
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 128)
+            .map_or_else(|| format!("{:016x}", rand::random::<u64>()), String::from),
+        path: req.path().to_string(),
+        card_id: card_id(req.path()),
+    };
+    let templates = req.app_data::<Arc<Templates>>();
+
+    let called = std::panic::catch_unwind(AssertUnwindSafe(|| srv.call(req)));
+    let response = match called {
+        Ok(response) => response,
+        Err(panic) => return Box::new(future::err(panicked(&context, templates, panic))),
+    };
+
+    Box::new(
+        AssertUnwindSafe(response)
+            .catch_unwind()
+            .then(move |result| match result {
+                Ok(Ok(mut response)) => {
+                    if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+                        response
+                            .headers_mut()
+                            .insert(HeaderName::from_static(REQUEST_ID), value);
+                    }
+                    Ok(response)
+                }
+                Ok(Err(err)) => Err(err),
+                Err(panic) => Err(panicked(&context, templates, panic)),
+            }),
+    )
+}
+
+/// Routing isn't done yet when the middleware runs, so the id is taken from the path
+fn card_id(path: &str) -> Option<String> {
+    ["/open/", "/api/meta/cards/"]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))
+        .and_then(|rest| rest.split('/').next())
+        .filter(|id| !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit()))
+        .map(String::from)
+}
+
+fn panicked(
+    context: &Context,
+    templates: Option<web::Data<Arc<Templates>>>,
+    panic: Box<dyn Any + Send>,
+) -> Error {
+    use log::error;
+
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+
+    error!(
+        "Handler panicked: {}, request {} {} card {}",
+        message,
+        context.request_id,
+        context.path,
+        context.card_id.as_deref().unwrap_or("-")
+    );
+    metrics::record_panic();
+    report(context, &message);
+
+    Panicked {
+        request_id: context.request_id.clone(),
+        page: templates.and_then(|templates| templates.current().errors.get(500)),
+    }
+    .into()
+}
+
+/// Sentry client is alive while the guard is
+#[cfg(feature = "sentry")]
+pub type Guard = Option<sentry::ClientInitGuard>;
+
+#[cfg(not(feature = "sentry"))]
+pub type Guard = Option<()>;
+
+/// Panics are sent to `SENTRY_DSN` when built with `--features sentry`
+#[cfg(feature = "sentry")]
+pub fn init() -> Guard {
+    let dsn = std::env::var("SENTRY_DSN")
+        .ok()
+        .filter(|dsn| !dsn.is_empty())?;
+
+    Some(sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            ..Default::default()
+        },
+    )))
+}
+
+#[cfg(not(feature = "sentry"))]
+pub fn init() -> Guard {
+    None
+}
+
+#[cfg(feature = "sentry")]
+fn report(context: &Context, message: &str) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("request_id", &context.request_id);
+            scope.set_tag("path", &context.path);
+            if let Some(ref card_id) = context.card_id {
+                scope.set_tag("card_id", card_id);
+            }
+        },
+        || sentry::capture_message(message, sentry::Level::Fatal),
+    );
+}
+
+#[cfg(not(feature = "sentry"))]
+fn report(_context: &Context, _message: &str) {}
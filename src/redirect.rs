@@ -0,0 +1,76 @@
+use actix_web::dev::ServiceRequest;
+
+use crate::flags::env_flag;
+
+use crate::acme::CHALLENGE_PREFIX;
+
+/// Paths that are never redirected nor put under maintenance: probes and internal tooling
+/// reach instances directly, ACME validates HTTP-01 challenges over plain http
+/// on whatever host it was asked for
+pub const EXEMPT_PREFIXES: &[&str] = &[
+    "/_admin",
+    "/_debug",
+    "/_hooks",
+    "/internal",
+    "/metrics",
+    "/readyz",
+    CHALLENGE_PREFIX,
+];
+
+/// Request normalization: redirects alternative hosts (`www.` and others)
+/// to `CANONICAL_HOST` and plain http to https when `FORCE_HTTPS` is on,
+/// so every page is rendered with the same og:url.
+#[derive(Debug, Clone)]
+pub struct Canonical {
+    host: Option<String>,
+    https: bool,
+}
+
+impl Canonical {
+    pub fn from_env() -> Self {
+        Canonical {
+            host: std::env::var("CANONICAL_HOST")
+                .ok()
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty()),
+            https: env_flag("FORCE_HTTPS"),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.host.is_some() || self.https
+    }
+
+    /// Location to permanently redirect to, if request is not canonical.
+    /// Scheme and host respect X-Forwarded-Proto and X-Forwarded-Host.
+    pub fn redirect_for(&self, req: &ServiceRequest) -> Option<String> {
+        if !self.enabled()
+            || EXEMPT_PREFIXES
+                .iter()
+                .any(|prefix| req.path().starts_with(prefix))
+        {
+            return None;
+        }
+
+        let info = req.connection_info();
+        let scheme = info.scheme().to_lowercase();
+        let host = info.host().to_lowercase();
+
+        let target_scheme = if self.https { "https" } else { scheme.as_str() };
+        let target_host = self.host.as_ref().unwrap_or(&host);
+
+        if target_scheme == scheme && *target_host == host {
+            return None;
+        }
+
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+
+        Some(format!(
+            "{}://{}{}",
+            target_scheme, target_host, path_and_query
+        ))
+    }
+}
@@ -0,0 +1,214 @@
+use log::warn;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cache::{CacheStore, Lookup, Namespace, Settings};
+use crate::config::{env_duration, env_or};
+
+/// Cache shared by all replicas, so an edited card is fetched and invalidated once.
+///
+/// Entries are `{"expires": <unix ms>, "value": ...}` under `{prefix}:{namespace}:{key}`,
+/// kept in Redis `CACHE_REDIS_STALE` past their TTL for stale reads.
+/// Redis errors are logged and treated as misses, the backend is asked instead.
+/// Calls wait for the network, `Cache` makes them on the blocking thread pool.
+/// After `BREAKER_FAILURES` failed calls in a row Redis is skipped for `CACHE_REDIS_COOLDOWN`,
+/// so a dead Redis doesn't hold every lookup for `CACHE_REDIS_TIMEOUT`.
+pub struct RedisStore {
+    client: redis::Client,
+    prefix: String,
+    timeout: Duration,
+    stale: Duration,
+    settings: Vec<(Namespace, Settings)>,
+    /// Idle connections, a request takes one or opens a new one
+    idle: Mutex<Vec<redis::Connection>>,
+    cooldown: Duration,
+    breaker: Mutex<Breaker>,
+}
+
+/// Failed calls in a row that make Redis skipped
+const BREAKER_FAILURES: u32 = 3;
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    /// Calls fail right away until then
+    open_until: Option<Instant>,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("prefix", &self.prefix)
+            .field("timeout", &self.timeout)
+            .field("stale", &self.stale)
+            .finish()
+    }
+}
+
+impl RedisStore {
+    /// `CACHE_REDIS_PREFIX`, `CACHE_REDIS_TIMEOUT` and `CACHE_REDIS_STALE`,
+    /// TTLs and entry size limits come from the namespace settings
+    pub fn from_env(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|err| format!("CACHE_REDIS_URL: {}", err))?;
+
+        Ok(RedisStore {
+            client,
+            prefix: env_or("CACHE_REDIS_PREFIX", "ssi".to_string()),
+            timeout: env_duration("CACHE_REDIS_TIMEOUT", Duration::from_millis(100)),
+            stale: env_duration("CACHE_REDIS_STALE", Duration::from_secs(60 * 60)),
+            settings: Namespace::ALL
+                .iter()
+                .filter_map(|namespace| Some((*namespace, Settings::from_env(*namespace)?)))
+                .collect(),
+            idle: Mutex::new(Vec::new()),
+            cooldown: env_duration("CACHE_REDIS_COOLDOWN", Duration::from_secs(5)),
+            breaker: Mutex::new(Breaker::default()),
+        })
+    }
+
+    fn settings(&self, namespace: Namespace) -> Option<&Settings> {
+        self.settings
+            .iter()
+            .find(|(candidate, _)| *candidate == namespace)
+            .map(|(_, settings)| settings)
+    }
+
+    fn key(&self, namespace: Namespace, key: &str) -> String {
+        format!("{}:{}:{}", self.prefix, namespace.name(), key)
+    }
+
+    fn connect(&self) -> redis::RedisResult<redis::Connection> {
+        if let Some(connection) = self.idle.lock().unwrap().pop() {
+            return Ok(connection);
+        }
+
+        let connection = self.client.get_connection_with_timeout(self.timeout)?;
+        connection.set_read_timeout(Some(self.timeout))?;
+        connection.set_write_timeout(Some(self.timeout))?;
+        Ok(connection)
+    }
+
+    /// Broken connections are dropped, healthy ones go back to the pool
+    fn query<T: redis::FromRedisValue>(&self, command: &redis::Cmd) -> Option<T> {
+        if self
+            .breaker
+            .lock()
+            .unwrap()
+            .open_until
+            .is_some_and(|open_until| Instant::now() < open_until)
+        {
+            return None;
+        }
+
+        let result = self
+            .connect()
+            .and_then(|mut connection| Ok((command.query(&mut connection)?, connection)));
+
+        let mut breaker = self.breaker.lock().unwrap();
+        match result {
+            Ok((value, connection)) => {
+                *breaker = Breaker::default();
+                self.idle.lock().unwrap().push(connection);
+                Some(value)
+            }
+            Err(err) => {
+                warn!("Redis cache: {}", err);
+
+                // A failed call after the cooldown opens the breaker again
+                breaker.failures += 1;
+                if breaker.failures >= BREAKER_FAILURES {
+                    warn!("Redis cache is skipped for {:?}", self.cooldown);
+                    breaker.open_until = Some(Instant::now() + self.cooldown);
+                }
+                None
+            }
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+impl CacheStore for RedisStore {
+    fn lookup(&self, namespace: Namespace, key: &str) -> Option<Lookup> {
+        self.settings(namespace)?;
+
+        let raw: Option<String> = self.query(redis::cmd("GET").arg(self.key(namespace, key)))?;
+        let mut entry: Value = serde_json::from_str(&raw?).ok()?;
+        let expires = entry["expires"].as_u64()?;
+        let now = now_millis();
+
+        Some(Lookup {
+            value: Arc::new(entry["value"].take()),
+            expired_for: Some(Duration::from_millis(now.saturating_sub(expires)))
+                .filter(|_| expires <= now),
+        })
+    }
+
+    fn put(&self, namespace: Namespace, key: String, value: Value, ttl: Option<Duration>) {
+        let settings = match self.settings(namespace) {
+            Some(settings) => settings,
+            None => return,
+        };
+
+        let ttl = settings.ttl_for(ttl, &value);
+        let entry = json!({
+            "expires": now_millis() + ttl.as_millis() as u64,
+            "value": value,
+        })
+        .to_string();
+
+        if key.len() + entry.len() > settings.max_bytes {
+            return;
+        }
+
+        let _: Option<()> = self.query(
+            redis::cmd("SET")
+                .arg(self.key(namespace, &key))
+                .arg(entry)
+                .arg("PX")
+                .arg((ttl + self.stale).as_millis() as u64),
+        );
+    }
+
+    fn invalidate(&self, namespace: Namespace, key: &str) -> bool {
+        self.query::<u64>(redis::cmd("DEL").arg(self.key(namespace, key)))
+            .is_some_and(|removed| removed > 0)
+    }
+
+    fn remote(&self) -> bool {
+        true
+    }
+
+    fn clear(&self, namespace: Namespace) {
+        let pattern = self.key(namespace, "*");
+        let mut cursor = 0;
+
+        loop {
+            let (next, keys): (u64, Vec<String>) = match self.query(
+                redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(500),
+            ) {
+                Some(page) => page,
+                None => return,
+            };
+
+            if !keys.is_empty() {
+                let _: Option<u64> = self.query(redis::cmd("DEL").arg(keys));
+            }
+
+            if next == 0 {
+                return;
+            }
+            cursor = next;
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::{client::Client, web, Error, HttpResponse};
+use futures::{future, Future};
+use std::sync::Arc;
+
+use super::card::CardPath;
+use super::validate_id;
+use crate::backend::{self, Answer, Card, Fetched};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::meta::CardMeta;
+use crate::metrics::{self, Outcome, Resource};
+
+/// `GET /api/meta/cards/{card_id}`: normalized card preview for partner sites and tools
+pub fn card_meta(
+    path: web::Path<CardPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let card_id = match validate_id(&path.card_id, config.card_id_max_length) {
+        Ok(card_id) => card_id,
+        Err(reason) => {
+            metrics::record_rejected(Resource::Api, reason);
+            return Box::new(future::ok(error(HttpResponse::BadRequest(), "invalid_id")));
+        }
+    };
+
+    if flags.degraded() {
+        metrics::record_request(Resource::Api, Outcome::Degraded);
+        return Box::new(future::ok(error(
+            HttpResponse::ServiceUnavailable(),
+            "unavailable",
+        )));
+    }
+
+    Box::new(
+        backend::fetch_cached::<Card>(
+            &client,
+            &chaos,
+            &cache,
+            Namespace::Card,
+            card_id.to_string(),
+            config.backend_card_url(card_id),
+        )
+        .then(move |fetched| {
+            let fetched = fetched.unwrap_or_else(|err| {
+                log::error!("Failed to get info about card for api: {:#?}", err);
+                Fetched {
+                    entity: None,
+                    cached: false,
+                    gone: false,
+                }
+            });
+            if !fetched.cached {
+                metrics::record_backend(Resource::Api, fetched.entity.is_some());
+            }
+            let fetched = fetched.without_private();
+            metrics::record_request(Resource::Api, fetched.outcome());
+
+            Ok(match fetched.entity {
+                Some(card) => cors(HttpResponse::Ok())
+                    .header("cache-control", "public, max-age=300")
+                    .json(Answer::<CardMeta>::Ok {
+                        ok: true,
+                        result: config.normalized_card(&card),
+                    }),
+                None => error(HttpResponse::NotFound(), "card_not_found"),
+            })
+        }),
+    )
+}
+
+/// Answers CORS preflight requests
+pub fn preflight() -> HttpResponse {
+    cors(HttpResponse::NoContent())
+        .header("access-control-allow-methods", "GET, OPTIONS")
+        .header("access-control-allow-headers", "content-type")
+        .header("access-control-max-age", "86400")
+        .finish()
+}
+
+fn cors(mut builder: actix_web::dev::HttpResponseBuilder) -> actix_web::dev::HttpResponseBuilder {
+    builder.header("access-control-allow-origin", "*");
+    builder
+}
+
+fn error(builder: actix_web::dev::HttpResponseBuilder, error: &str) -> HttpResponse {
+    cors(builder).json(Answer::<()>::Err {
+        ok: false,
+        error: error.to_string(),
+    })
+}
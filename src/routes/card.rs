@@ -0,0 +1,505 @@
+use actix_web::http::header::{self, HeaderName};
+use actix_web::{client::Client, error, web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::{accept_language, bad_request, degraded, image, render_conditional, validate_id};
+use crate::backend::{self, Card, CardContent, Visibility};
+use crate::cache::{Cache, CacheFuture, Namespace};
+use crate::cdn;
+use crate::chaos::Chaos;
+use crate::conditional::Validators;
+use crate::config::Config;
+use crate::crawler;
+use crate::flags::Flags;
+use crate::images::{self, ImageCache};
+use crate::locale;
+use crate::markdown;
+use crate::metrics::{self, Outcome, Resource};
+use crate::microcache::Microcache;
+use crate::publish::prerendered_key;
+use crate::stats::TopCards;
+use crate::storage::{inject_body, LocaleTemplates, PageBody, Storage, Templates};
+
+#[derive(Debug, Deserialize)]
+pub struct CardPath {
+    pub card_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocalizedCardPath {
+    pub locale: String,
+    pub card_id: String,
+}
+
+/// `/open/{card_id}`, and `/{locale}/open/{card_id}` of `PATH_LOCALES`.
+/// Link checkers and crawlers send HEAD: the page is put together all the same,
+/// so headers and length match GET, and the body is dropped.
+pub fn configure(cfg: &mut web::ServiceConfig, locales: &[String]) {
+    for path in &["/open/{card_id}", "/open/{card_id}/"] {
+        cfg.service(
+            metrics::resource(path)
+                .route(web::get().to_async(card))
+                .route(web::head().to_async(card)),
+        );
+    }
+
+    if locales.is_empty() {
+        return;
+    }
+
+    let prefix = format!("/{{locale:{}}}", locales.join("|"));
+    for path in &["/open/{card_id}", "/open/{card_id}/"] {
+        cfg.service(
+            metrics::resource(&format!("{}{}", prefix, path))
+                .route(web::get().to_async(localized_card))
+                .route(web::head().to_async(localized_card)),
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn card(
+    req: HttpRequest,
+    path: web::Path<CardPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    top_cards: web::Data<Arc<TopCards>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    card_page(
+        req,
+        &path.card_id,
+        None,
+        templates.current(),
+        client,
+        config,
+        flags,
+        chaos,
+        top_cards,
+        cache,
+    )
+}
+
+/// `/{locale}/open/{card_id}` of `PATH_LOCALES`: the backend gets the locale
+/// as `?lang=`, the page is made of the `LOCALE_INDEX_HTML` template of the locale
+#[allow(clippy::too_many_arguments)]
+pub fn localized_card(
+    req: HttpRequest,
+    path: web::Path<LocalizedCardPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    locale_templates: web::Data<Arc<LocaleTemplates>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    top_cards: web::Data<Arc<TopCards>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let locale = path.locale.to_lowercase();
+    let storage = locale_templates
+        .get(&locale)
+        .unwrap_or(&templates)
+        .current();
+
+    card_page(
+        req,
+        &path.card_id,
+        Some(locale),
+        storage,
+        client,
+        config,
+        flags,
+        chaos,
+        top_cards,
+        cache,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn card_page(
+    req: HttpRequest,
+    card_id: &str,
+    path_locale: Option<String>,
+    storage: Arc<Storage>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    top_cards: web::Data<Arc<TopCards>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let card_id = match validate_id(card_id, config.card_id_max_length) {
+        Ok(card_id) => card_id,
+        Err(reason) => return Box::new(future::ok(bad_request(Resource::Card, reason))),
+    };
+
+    top_cards.record(card_id);
+
+    let backend_locale = path_locale.clone();
+    let locale = path_locale.or_else(|| {
+        accept_language(&req).and_then(|value| locale::negotiate(value, config.sitename.locales()))
+    });
+    let (key, url) = config.localized(
+        card_id.to_string(),
+        config.backend_card_url(card_id),
+        backend_locale.as_deref(),
+    );
+
+    if flags.degraded() {
+        return Box::new(degraded_page(
+            req.path(),
+            storage,
+            &cache,
+            config.get_ref().clone(),
+            &key,
+            locale,
+        ));
+    }
+
+    // Pre-rendered pages have no card text
+    let with_body = config.render_card_body && crawler::is_crawler(req.headers());
+    let vary = vary(&config, backend_locale.is_some());
+
+    let page_key = format!(
+        "{}\n{}\n{}\n{}",
+        req.path(),
+        storage.hash,
+        locale.as_deref().unwrap_or_default(),
+        with_body
+    );
+    // Conditional requests are answered without putting the page together anyway
+    let conditional = req.headers().contains_key(header::IF_NONE_MATCH)
+        || req.headers().contains_key(header::IF_MODIFIED_SINCE);
+    // Out of the handler arguments, `localized_card` has as many as actix allows
+    let microcache = req.app_data::<Arc<Microcache>>().cloned();
+    if !conditional {
+        if let Some(response) = microcache.as_ref().and_then(|cache| cache.get(&page_key)) {
+            metrics::record_request(Resource::Card, Outcome::Hit);
+            return Box::new(future::ok(response));
+        }
+    }
+
+    let fetched = backend::fetch_cached::<Card>(&client, &chaos, &cache, Namespace::Card, key, url);
+    let fetched = {
+        let client = client.get_ref().clone();
+        let cache = cache.get_ref().clone();
+        let config = config.get_ref().clone();
+        fetched.and_then(move |fetched| {
+            images::check_preview(&client, &cache, &config, fetched.without_private())
+        })
+    };
+
+    // Pages differ by locale, and for crawlers by the card text
+    let variant = format!("{}\n{}", locale.as_deref().unwrap_or_default(), with_body);
+    let validators = {
+        let config = config.get_ref().clone();
+        move |storage: &Storage, card: &Card| {
+            Validators::for_card(storage, card, &variant)
+                .cdn(&config, &cdn::card_key(card_id))
+                .vary(&vary)
+        }
+    };
+
+    let page: Box<dyn Future<Item = HttpResponse, Error = Error>> = if with_body {
+        let (key, url) = config.localized(
+            card_id.to_string(),
+            config.backend_card_content_url(card_id),
+            backend_locale.as_deref(),
+        );
+        let content = backend::fetch_cached::<CardContent>(
+            &client,
+            &chaos,
+            &cache,
+            Namespace::Content,
+            key,
+            url,
+        )
+        .then(move |content| {
+            // The page is still useful with meta only
+            if let Err(ref err) = content {
+                log::warn!("Failed to get content of card {}: {}", card_id, err);
+            }
+            Ok(content.ok().and_then(|content| content.entity))
+        });
+        Box::new(content.and_then(move |content| {
+            render_conditional(
+                &req,
+                Resource::Card,
+                fetched,
+                storage,
+                config.meta_for_not_found(locale.as_deref()),
+                validators,
+                move |storage, card| {
+                    let page = storage.inject(&config.meta_for_card(card, locale.as_deref()));
+                    match content {
+                        Some(content) => {
+                            inject_body(&page.into_string(), &card_body(card, &content)).into()
+                        }
+                        None => page,
+                    }
+                },
+            )
+        }))
+    } else {
+        // Published cards may be rendered ahead, pages of other locales never are
+        let prerendered: CacheFuture<Option<Arc<serde_json::Value>>> = if locale.is_none() {
+            cache.get(Namespace::Page, &prerendered_key(card_id, &storage))
+        } else {
+            Box::new(future::ok(None))
+        };
+        Box::new(prerendered.and_then(move |prerendered| {
+            render_conditional(
+                &req,
+                Resource::Card,
+                fetched,
+                storage,
+                config.meta_for_not_found(locale.as_deref()),
+                validators,
+                move |storage, card| {
+                    prerendered
+                        .and_then(|page| page.as_str().map(|page| PageBody::from(page.to_string())))
+                        .unwrap_or_else(|| {
+                            storage.inject(&config.meta_for_card(card, locale.as_deref()))
+                        })
+                },
+            )
+        }))
+    };
+
+    Box::new(page.map(move |response| {
+        if let Some(microcache) = microcache {
+            microcache.put(page_key, &response);
+        }
+        response
+    }))
+}
+
+/// Card page without asking the backend: meta of the cached card, private ones stay missing,
+/// otherwise the site meta. The card may well exist, so the page isn't kept out of search.
+fn degraded_page(
+    path: &str,
+    storage: Arc<Storage>,
+    cache: &Cache,
+    config: Arc<Config>,
+    key: &str,
+    locale: Option<String>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let cached = backend::stale::<Card>(cache, Namespace::Card, key)
+        .map(|card| card.filter(|card| card.visibility != Visibility::Private));
+
+    degraded(
+        Resource::Card,
+        storage,
+        cached,
+        config.meta_for_page(path, locale.as_deref()),
+        move |card| config.meta_for_card(card, locale.as_deref()),
+    )
+}
+
+/// Request headers a card page depends on: the locale is negotiated by `Accept-Language`
+/// unless it is in the path, and with `RENDER_CARD_BODY` crawlers get the card text
+fn vary(config: &Config, path_locale: bool) -> Vec<HeaderName> {
+    let mut headers = Vec::new();
+    if !path_locale && config.sitename.locales().next().is_some() {
+        headers.push(header::ACCEPT_LANGUAGE);
+    }
+    if config.render_card_body {
+        headers.push(header::USER_AGENT);
+    }
+    headers
+}
+
+/// Title and text of the card, as the frontend would show them
+fn card_body(card: &Card, content: &CardContent) -> String {
+    format!(
+        "<article><h1>{}</h1>{}</article>",
+        htmlescape::encode_minimal(&card.title),
+        markdown::to_html(&content.content)
+    )
+}
+
+/// `/og-image/{card_id}.png`: title and author of the card, when `OG_IMAGE_FONT` is set.
+/// Cached on disk by everything the image shows.
+#[allow(clippy::too_many_arguments)]
+pub fn social_image(
+    path: web::Path<CardPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    meta_cache: web::Data<Arc<Cache>>,
+    cache: web::Data<Arc<ImageCache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let card_id = match validate_id(&path.card_id, config.card_id_max_length) {
+        Ok(card_id) => card_id,
+        Err(reason) => return Box::new(future::ok(bad_request(Resource::Card, reason))),
+    };
+
+    if config.social_card.is_none() {
+        return Box::new(future::ok(HttpResponse::NotFound().finish()));
+    }
+
+    if flags.degraded() {
+        return Box::new(future::ok(HttpResponse::ServiceUnavailable().finish()));
+    }
+
+    let cache = cache.get_ref().clone();
+    let config = config.get_ref().clone();
+
+    Box::new(
+        backend::fetch_cached::<Card>(
+            &client,
+            &chaos,
+            &meta_cache,
+            Namespace::Card,
+            card_id.to_string(),
+            config.backend_card_url(card_id),
+        )
+        .and_then(
+            move |fetched| -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+                let card = match fetched.without_private().entity {
+                    Some(card) => card,
+                    None => return Box::new(future::ok(HttpResponse::NotFound().finish())),
+                };
+
+                let key = format!("card-{}-{}.png", card.id, images::card_version(&card));
+
+                if let Some(bytes) = cache.get(&key) {
+                    return Box::new(future::ok(image("image/png", bytes)));
+                }
+
+                Box::new(
+                    web::block(move || {
+                        let social_card = config.social_card.as_ref().expect("checked above");
+                        let author = card
+                            .author
+                            .as_ref()
+                            .and_then(|author| author.display_name.as_deref());
+                        let bytes = images::encode_png(&social_card.render(&card.title, author))?;
+
+                        if let Err(err) = cache.put(&key, &bytes) {
+                            log::warn!("Failed to cache social image {}: {}", key, err);
+                        }
+
+                        Ok::<_, image::ImageError>(bytes)
+                    })
+                    .map_err(error::ErrorInternalServerError)
+                    .map(|bytes| image("image/png", bytes)),
+                )
+            },
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn storage() -> Storage {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static TEMPLATES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "ssi-card-{}-{}.html",
+            std::process::id(),
+            TEMPLATES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(
+            &path,
+            "<html><head><title>Site</title></head><body></body></html>",
+        )
+        .unwrap();
+        let storage =
+            Storage::read_from(path.to_string_lossy().into_owned(), "</head>".into(), true);
+        std::fs::remove_file(path).unwrap();
+        storage.unwrap()
+    }
+
+    fn cached(cache: &Cache, id: i32, visibility: &str) {
+        cache.put(
+            Namespace::Card,
+            id.to_string(),
+            json!({
+                "id": id,
+                "title": "Secret plans",
+                "description": "",
+                "createdAt": "2020-01-01T00:00:00Z",
+                "updatedAt": "2020-01-01T00:00:00Z",
+                "previewUrl": null,
+                "visibility": visibility
+            }),
+            None,
+        );
+    }
+
+    fn page(storage: &Arc<Storage>, cache: &Cache, config: &Arc<Config>, id: i32) -> HttpResponse {
+        degraded_page(
+            &format!("/open/{}", id),
+            storage.clone(),
+            cache,
+            config.clone(),
+            &id.to_string(),
+            None,
+        )
+        .wait()
+        .unwrap()
+    }
+
+    fn body(response: &HttpResponse) -> String {
+        PageBody::of(response).unwrap().into_string()
+    }
+
+    #[test]
+    fn degraded_page_has_meta_of_the_cached_card() {
+        let (storage, cache, config) = (
+            Arc::new(storage()),
+            Cache::in_memory(),
+            Arc::new(Config::example()),
+        );
+        cached(&cache, 1, "public");
+
+        let response = page(&storage, &cache, &config, 1);
+
+        assert_eq!(response.status(), 200);
+        assert!(body(&response).contains("<title>Secret plans — Howtocards</title>"));
+    }
+
+    #[test]
+    fn degraded_page_keeps_private_cards_out() {
+        let (storage, cache, config) = (
+            Arc::new(storage()),
+            Cache::in_memory(),
+            Arc::new(Config::example()),
+        );
+        cached(&cache, 2, "private");
+
+        let page = body(&page(&storage, &cache, &config, 2));
+
+        assert!(!page.contains("Secret plans"));
+        assert!(
+            page.contains(r#"<meta property="og:url" content="https://howtocards.io/open/2" />"#)
+        );
+    }
+
+    #[test]
+    fn degraded_page_of_uncached_card_stays_indexed() {
+        let (storage, cache, config) = (
+            Arc::new(storage()),
+            Cache::in_memory(),
+            Arc::new(Config::example()),
+        );
+
+        let response = page(&storage, &cache, &config, 3);
+        let page = body(&response);
+
+        assert_eq!(response.status(), 200);
+        assert!(page.contains("<title>Howtocards</title>"));
+        assert!(!page.contains("noindex"));
+    }
+}
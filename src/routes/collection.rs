@@ -0,0 +1,137 @@
+use actix_web::{client::Client, error, web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::provider::MetaProvider;
+use super::{bad_request, image, match_param, validate_id};
+use crate::backend::{self, Collection};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::images::{self, ImageCache};
+use crate::metrics::Resource;
+
+/// Collage is composed of previews of this many first cards
+const COLLAGE_CARDS: usize = 4;
+
+#[derive(Debug, Deserialize)]
+pub struct CollectionPath {
+    collection_id: String,
+}
+
+/// `/open/collection/{collection_id}` and `/box/{collection_id}`
+#[derive(Clone, Debug)]
+pub struct CollectionMeta;
+
+impl MetaProvider for CollectionMeta {
+    type Entity = Collection;
+
+    fn resource(&self) -> Resource {
+        Resource::Collection
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::Collection
+    }
+
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let collection_id =
+            validate_id(match_param(req, "collection_id"), config.card_id_max_length)?;
+
+        Ok((
+            collection_id.to_string(),
+            config.backend_collection_url(collection_id),
+        ))
+    }
+
+    fn meta(
+        &self,
+        config: &Config,
+        _path: &str,
+        collection: &Collection,
+        locale: Option<&str>,
+    ) -> String {
+        config.meta_for_collection(collection, locale)
+    }
+}
+
+/// `/og-image/collection/{id}.jpg`: 2×2 grid of first cards previews,
+/// cached on disk by collection id and `updated_at`
+pub fn collage(
+    path: web::Path<CollectionPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    meta_cache: web::Data<Arc<Cache>>,
+    cache: web::Data<Arc<ImageCache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let collection_id = match validate_id(&path.collection_id, config.card_id_max_length) {
+        Ok(collection_id) => collection_id,
+        Err(reason) => return Box::new(future::ok(bad_request(Resource::Collection, reason))),
+    };
+
+    if flags.degraded() {
+        return Box::new(future::ok(HttpResponse::ServiceUnavailable().finish()));
+    }
+
+    let cache = cache.get_ref().clone();
+    let image_client = client.get_ref().clone();
+
+    Box::new(
+        backend::fetch_cached::<Collection>(
+            &client,
+            &chaos,
+            &meta_cache,
+            Namespace::Collection,
+            collection_id.to_string(),
+            config.backend_collection_url(collection_id),
+        )
+        .and_then(
+            move |fetched| -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+                let collection = match fetched.entity {
+                    Some(collection) => collection,
+                    None => return Box::new(future::ok(HttpResponse::NotFound().finish())),
+                };
+
+                let key = format!(
+                    "collection-{}-{}.jpg",
+                    collection.id,
+                    images::version_hash(&collection.updated_at)
+                );
+
+                if let Some(bytes) = cache.get(&key) {
+                    return Box::new(future::ok(image("image/jpeg", bytes)));
+                }
+
+                let previews = collection
+                    .cards
+                    .iter()
+                    .filter_map(|card| card.preview_url.as_ref())
+                    .take(COLLAGE_CARDS)
+                    .map(|url| images::fetch(&image_client, config.image_url_for(url)))
+                    .collect::<Vec<_>>();
+
+                Box::new(
+                    future::join_all(previews)
+                        .and_then(move |pictures| {
+                            web::block(move || {
+                                let pictures = pictures.into_iter().flatten().collect::<Vec<_>>();
+                                let bytes = images::encode_jpeg(&images::collage(&pictures))?;
+
+                                if let Err(err) = cache.put(&key, &bytes) {
+                                    log::warn!("Failed to cache collage {}: {}", key, err);
+                                }
+
+                                Ok::<_, image::ImageError>(bytes)
+                            })
+                            .map_err(error::ErrorInternalServerError)
+                        })
+                        .map(|bytes| image("image/jpeg", bytes)),
+                )
+            },
+        ),
+    )
+}
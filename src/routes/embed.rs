@@ -0,0 +1,161 @@
+use actix_web::{client::Client, web, Error, HttpResponse};
+use futures::{future, Future};
+use htmlescape::encode_minimal as escape;
+use std::sync::Arc;
+
+use super::card::CardPath;
+use super::{bad_request, validate_id};
+use crate::backend::{self, Card, Fetched};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::markdown;
+use crate::meta::truncate;
+use crate::metrics::{self, Outcome, Resource};
+
+const EXCERPT_LIMIT: usize = 160;
+
+/// Replaces `<blockquote class="howtocards-card" data-id="…">` on a host page with widget iframes
+const LOADER: &str = include_str!("../../assets/embed.js");
+
+/// Widget reports its height, so loader can resize the iframe
+const RESIZE_SCRIPT: &str = "<script>parent.postMessage({howtocardsEmbed:document.body.getAttribute('data-id'),height:document.body.scrollHeight},'*')</script>";
+
+const STYLE: &str = "\
+*{box-sizing:border-box}\
+body{margin:0;font:14px/1.4 -apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,sans-serif;color:#242424;background:transparent}\
+a.card{display:flex;overflow:hidden;border:1px solid #e0e0e6;border-radius:8px;background:#fff;color:inherit;text-decoration:none}\
+a.card:hover{border-color:#7986cb}\
+img{width:35%;max-height:200px;object-fit:cover;flex-shrink:0}\
+.body{padding:12px 16px;min-width:0}\
+h1{margin:0 0 6px;font-size:17px;line-height:1.3}\
+p{margin:0 0 10px;color:#5c5c66}\
+.brand{font-size:12px;color:#7986cb;font-weight:600}";
+
+/// `/open/{card_id}/embed`: standalone widget for iframes on other sites
+pub fn embed(
+    path: web::Path<CardPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let card_id = match validate_id(&path.card_id, config.card_id_max_length) {
+        Ok(card_id) => card_id,
+        Err(reason) => return Box::new(future::ok(bad_request(Resource::Embed, reason))),
+    };
+
+    if flags.degraded() {
+        metrics::record_request(Resource::Embed, Outcome::Degraded);
+        return Box::new(future::ok(embeddable(
+            HttpResponse::Ok(),
+            fallback(&config, card_id),
+        )));
+    }
+
+    Box::new(
+        backend::fetch_cached::<Card>(
+            &client,
+            &chaos,
+            &cache,
+            Namespace::Card,
+            card_id.to_string(),
+            config.backend_card_url(card_id),
+        )
+        .then(move |fetched| {
+            let fetched = fetched.unwrap_or_else(|err| {
+                log::error!("Failed to get info about card for embed: {:#?}", err);
+                Fetched {
+                    entity: None,
+                    cached: false,
+                    gone: false,
+                }
+            });
+            if !fetched.cached {
+                metrics::record_backend(Resource::Embed, fetched.entity.is_some());
+            }
+            let fetched = fetched.without_private();
+            metrics::record_request(Resource::Embed, fetched.outcome());
+
+            Ok(match fetched.entity {
+                Some(card) => embeddable(HttpResponse::Ok(), widget(&config, &card)),
+                None => embeddable(HttpResponse::NotFound(), fallback(&config, card_id)),
+            })
+        }),
+    )
+}
+
+/// `/embed.js`, loaded by third party sites
+pub fn loader(config: web::Data<Arc<Config>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .header("access-control-allow-origin", "*")
+        .header("cross-origin-resource-policy", "cross-origin")
+        .header("cache-control", "public, max-age=3600")
+        .body(LOADER.replace("__PUBLIC_URL__", config.public_url.trim_end_matches('/')))
+}
+
+/// Any site may frame the widget
+fn embeddable(mut builder: actix_web::dev::HttpResponseBuilder, body: String) -> HttpResponse {
+    builder
+        .content_type("text/html; charset=utf-8")
+        .header("content-security-policy", "frame-ancestors *")
+        .header("cache-control", "public, max-age=300")
+        .body(body)
+}
+
+fn document(config: &Config, card_id: u32, title: &str, content: String) -> String {
+    format!(
+        r#"<!doctype html><html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1"><meta name="robots" content="noindex"><title>{title} — {sitename}</title><style>{style}</style></head><body data-id="{id}">{content}{resize}</body></html>"#,
+        title = escape(title),
+        sitename = escape(config.sitename.get(None)),
+        style = STYLE,
+        id = card_id,
+        content = content,
+        resize = RESIZE_SCRIPT,
+    )
+}
+
+fn widget(config: &Config, card: &Card) -> String {
+    let image = card.preview_url.as_ref().map_or(String::new(), |url| {
+        format!(
+            r#"<img src="{}" alt="">"#,
+            escape(&config.image_url_for(url))
+        )
+    });
+
+    document(
+        config,
+        card.id as u32,
+        &card.title,
+        format!(
+            r#"<a class="card" href="{url}" target="_blank" rel="noopener">{image}<div class="body"><h1>{title}</h1><p>{excerpt}</p><span class="brand">{sitename}</span></div></a>"#,
+            url = escape(&format!("{}/open/{}", config.public_url, card.id)),
+            image = image,
+            title = escape(&card.title),
+            excerpt = escape(&truncate(
+                &markdown::to_text(&card.description),
+                EXCERPT_LIMIT
+            )),
+            sitename = escape(config.sitename.get(None)),
+        ),
+    )
+}
+
+/// Plain link to the card when its meta is unavailable
+fn fallback(config: &Config, card_id: u32) -> String {
+    let sitename = config.sitename.get(None);
+
+    document(
+        config,
+        card_id,
+        sitename,
+        format!(
+            r#"<a class="card" href="{url}" target="_blank" rel="noopener"><div class="body"><h1>{sitename}</h1><span class="brand">{url}</span></div></a>"#,
+            url = escape(&format!("{}/open/{}", config.public_url, card_id)),
+            sitename = escape(sitename),
+        ),
+    )
+}
@@ -0,0 +1,136 @@
+use actix_web::{client::Client, web, Error, HttpResponse};
+use futures::{future, Future};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::backend::{self, Card, LatestCards, Visibility};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::meta::plain_description;
+use crate::metrics::{self, Resource};
+
+/// Readers show the newest entries only
+const MAX_ENTRIES: usize = 50;
+
+/// `/feed.xml`: Atom feed of the latest cards of `/api/cards/latest/meta/`,
+/// cached for `CACHE_FEED_TTL`
+pub fn feed(
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if flags.degraded() {
+        return Box::new(future::ok(unavailable()));
+    }
+
+    let config = config.get_ref().clone();
+
+    Box::new(
+        backend::fetch_cached::<LatestCards>(
+            &client,
+            &chaos,
+            &cache,
+            Namespace::Feed,
+            "latest".to_string(),
+            config.backend_latest_url(),
+        )
+        .then(move |fetched| {
+            let fetched = match fetched {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    log::error!("Failed to get latest cards for the feed: {}", err);
+                    metrics::record_backend(Resource::Feed, false);
+                    return Ok(unavailable());
+                }
+            };
+
+            metrics::record_request(Resource::Feed, fetched.outcome());
+            // Only cards open to everyone belong to a public feed
+            let cards = fetched
+                .entity
+                .map(|latest| latest.cards)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|card| card.visibility == Visibility::Public)
+                .collect::<Vec<_>>();
+
+            Ok(HttpResponse::Ok()
+                .content_type("application/atom+xml; charset=utf-8")
+                .header("cache-control", "public, max-age=300")
+                .body(atom(&config, &cards)))
+        }),
+    )
+}
+
+fn atom(config: &Config, cards: &[Card]) -> String {
+    let escape = htmlescape::encode_minimal;
+    let cards = &cards[..cards.len().min(MAX_ENTRIES)];
+    let sitename = config.sitename.get(None);
+    // An empty feed still needs `updated`
+    let updated = cards
+        .iter()
+        .map(|card| card.updated_at.clone())
+        .max()
+        .unwrap_or_else(|| humantime::format_rfc3339_seconds(SystemTime::now()).to_string());
+
+    let mut xml = String::with_capacity(cards.len() * 600 + 600);
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape(sitename)));
+    if let Some(ref description) = config.site_description {
+        xml.push_str(&format!(
+            "<subtitle>{}</subtitle>\n",
+            escape(description.get(None))
+        ));
+    }
+    xml.push_str(&format!(
+        "<link href=\"{0}/\" />\n<link rel=\"self\" href=\"{0}/feed.xml\" />\n<id>{0}/</id>\n",
+        escape(&config.public_url)
+    ));
+    xml.push_str(&format!("<updated>{}</updated>\n", escape(&updated)));
+    xml.push_str(&format!(
+        "<author><name>{}</name></author>\n",
+        escape(sitename)
+    ));
+
+    for card in cards {
+        let url = format!("{}/open/{}", config.public_url, card.id);
+
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&card.title)));
+        xml.push_str(&format!(
+            "<link href=\"{0}\" />\n<id>{0}</id>\n",
+            escape(&url)
+        ));
+        xml.push_str(&format!(
+            "<published>{}</published>\n<updated>{}</updated>\n",
+            escape(&card.created_at),
+            escape(&card.updated_at)
+        ));
+        let summary = plain_description(&card.description);
+        if !summary.is_empty() {
+            xml.push_str(&format!("<summary>{}</summary>\n", escape(&summary)));
+        }
+        if let Some(name) = card
+            .author
+            .as_ref()
+            .and_then(|author| author.display_name.as_deref())
+        {
+            xml.push_str(&format!("<author><name>{}</name></author>\n", escape(name)));
+        }
+        xml.push_str("</entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .header("retry-after", "60")
+        .finish()
+}
@@ -0,0 +1,61 @@
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::sync::Arc;
+
+use super::{accept_language, html};
+use crate::config::Config;
+use crate::locale;
+use crate::metrics::{self, Outcome, Resource};
+use crate::redirect::EXEMPT_PREFIXES;
+use crate::storage::Templates;
+
+/// `/`: site meta and `WebSite` structured data, the backend isn't involved
+pub fn home(
+    req: HttpRequest,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+) -> HttpResponse {
+    let locale =
+        accept_language(&req).and_then(|value| locale::negotiate(value, config.site_locales()));
+
+    metrics::record_request(Resource::Home, Outcome::Static);
+
+    html(
+        templates
+            .current()
+            .inject(&config.meta_for_home(locale.as_deref())),
+    )
+}
+
+/// Any other route of the SPA: the template with site meta, the frontend renders the rest.
+/// Assets (a file extension in the last segment), service paths and methods other
+/// than GET and HEAD still get 404.
+pub fn page(
+    req: HttpRequest,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+) -> HttpResponse {
+    let path = req.path();
+    let asset = path
+        .rsplit('/')
+        .next()
+        .is_some_and(|segment| segment.contains('.'));
+    let service = EXEMPT_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix));
+
+    if asset || service || !(req.method() == Method::GET || req.method() == Method::HEAD) {
+        return super::not_found(config, templates);
+    }
+
+    let locale =
+        accept_language(&req).and_then(|value| locale::negotiate(value, config.site_locales()));
+
+    metrics::record_request(Resource::Page, Outcome::Static);
+
+    html(
+        templates
+            .current()
+            .inject(&config.meta_for_page(path, locale.as_deref())),
+    )
+}
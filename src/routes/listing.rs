@@ -0,0 +1,92 @@
+use actix_web::{client::Client, web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::{accept_language, bad_request, degraded, render};
+use crate::backend::{self, Listing};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::locale;
+use crate::metrics::Resource;
+use crate::storage::Templates;
+
+/// Deeper pages are not linked from anywhere and only waste backend time
+const MAX_PAGE: u32 = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListingPath {
+    listing: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    page: Option<u32>,
+}
+
+impl PageQuery {
+    pub fn validate(&self) -> Result<u32, &'static str> {
+        match self.page {
+            None => Ok(1),
+            Some(page) if (1..=MAX_PAGE).contains(&page) => Ok(page),
+            Some(_) => Err("page"),
+        }
+    }
+}
+
+/// `/popular` and `/latest` with `?page=N`
+#[allow(clippy::too_many_arguments)]
+pub fn listing(
+    req: HttpRequest,
+    path: web::Path<ListingPath>,
+    query: web::Query<PageQuery>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let page = match query.validate() {
+        Ok(page) => page,
+        Err(reason) => return Box::new(future::ok(bad_request(Resource::Listing, reason))),
+    };
+
+    let locale = accept_language(&req).and_then(|value| {
+        locale::negotiate(value, config.sitename.locales().chain(vec!["en", "ru"]))
+    });
+    let name = path.listing.clone();
+    let key = format!("{}:{}", name, page);
+
+    if flags.degraded() {
+        let site_meta = config.meta_for_page(req.path(), locale.as_deref());
+        let config = config.get_ref().clone();
+
+        return Box::new(degraded(
+            Resource::Listing,
+            templates.current(),
+            backend::stale::<Listing>(&cache, Namespace::Listing, &key),
+            site_meta,
+            move |listing| config.meta_for_listing(&name, page, listing, locale.as_deref()),
+        ));
+    }
+
+    let fetched = backend::fetch_cached::<Listing>(
+        &client,
+        &chaos,
+        &cache,
+        Namespace::Listing,
+        key,
+        config.backend_listing_url(&name, page),
+    );
+
+    Box::new(render(
+        Resource::Listing,
+        fetched,
+        templates.current(),
+        config.meta_for_not_found(locale.as_deref()),
+        move |listing| config.meta_for_listing(&name, page, listing, locale.as_deref()),
+    ))
+}
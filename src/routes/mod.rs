@@ -0,0 +1,278 @@
+use actix_web::http::{ContentEncoding, StatusCode};
+use actix_web::middleware::BodyEncoding;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use std::sync::Arc;
+
+use crate::backend::{self, Fetched};
+use crate::conditional::Validators;
+use crate::config::Config;
+use crate::metrics::{self, Outcome, Resource};
+use crate::microcache::Fallback;
+use crate::storage::{PageBody, Storage, Templates};
+
+pub mod api;
+pub mod card;
+pub mod collection;
+pub mod embed;
+pub mod feed;
+pub mod home;
+pub mod listing;
+pub mod provider;
+pub mod proxy;
+pub mod robots;
+pub mod sitemap;
+pub mod tag;
+pub mod user;
+
+/// Entity ids must be short decimal numbers, anything else never reaches the backend
+pub fn validate_id(id: &str, max_length: usize) -> Result<u32, &'static str> {
+    if id.is_empty() || id.len() > max_length {
+        return Err("length");
+    }
+
+    if !id.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err("pattern");
+    }
+
+    id.parse().map_err(|_| "overflow")
+}
+
+/// Segment of the matched route pattern, empty when there is none
+pub fn match_param<'a>(req: &'a HttpRequest, name: &str) -> &'a str {
+    req.match_info().get(name).unwrap_or_default()
+}
+
+pub fn accept_language(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Pages can't be framed by other sites, embeds have their own headers
+pub fn html(body: impl Into<PageBody>) -> HttpResponse {
+    body.into().respond(
+        HttpResponse::build(StatusCode::OK)
+            .content_type("text/html; charset=utf-8")
+            .header("x-frame-options", "SAMEORIGIN"),
+    )
+}
+
+/// Generated images, their URLs change with the content.
+/// Already compressed, so they are sent as they are.
+pub fn image(content_type: &'static str, bytes: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .encoding(ContentEncoding::Identity)
+        .content_type(content_type)
+        .header("cache-control", "public, max-age=86400")
+        .body(bytes)
+}
+
+pub fn bad_request(resource: Resource, reason: &'static str) -> HttpResponse {
+    metrics::record_rejected(resource, reason);
+
+    HttpResponse::BadRequest()
+        .content_type("text/plain; charset=utf-8")
+        .body(format!("Invalid {} id", resource.label()))
+}
+
+/// The backend isn't asked while degraded: the page gets meta of the cached entity,
+/// an expired one too, otherwise the site meta. The entity may exist, so `site_meta`
+/// must not keep the page out of search.
+pub fn degraded<T, F>(
+    resource: Resource,
+    storage: Arc<Storage>,
+    cached: impl Future<Item = Option<T>, Error = Error>,
+    site_meta: String,
+    to_meta: F,
+) -> impl Future<Item = HttpResponse, Error = Error>
+where
+    F: FnOnce(&T) -> String,
+{
+    metrics::record_request(resource, Outcome::Degraded);
+
+    cached.map(move |cached| {
+        let meta = match cached {
+            Some(ref entity) => to_meta(entity),
+            None => site_meta,
+        };
+        html(storage.inject(&meta))
+    })
+}
+
+/// Page from `ERROR_PAGES_DIR` with its real status, never cached by proxies
+pub fn error_page(storage: &Storage, status: StatusCode) -> Option<HttpResponse> {
+    storage.errors.get(status.as_u16()).map(|page| {
+        HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .header("cache-control", "no-store")
+            .body(page)
+    })
+}
+
+/// 404 or 410 page of `ERROR_PAGES_DIR` with site meta and noindex,
+/// deleted entities get the 404 page when there is no 410 one
+pub fn not_found_page(storage: &Storage, status: StatusCode, meta: &str) -> Option<HttpResponse> {
+    storage
+        .errors
+        .get(status.as_u16())
+        .or_else(|| storage.errors.get(404))
+        .map(|page| {
+            HttpResponse::build(status)
+                .content_type("text/html; charset=utf-8")
+                .header("x-robots-tag", "noindex")
+                .body(storage.inject_into(&page, meta))
+        })
+}
+
+/// Unknown paths
+pub fn not_found(
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+) -> HttpResponse {
+    not_found_page(
+        &templates.current(),
+        StatusCode::NOT_FOUND,
+        &config.meta_for_not_found(None),
+    )
+    .unwrap_or_else(|| HttpResponse::NotFound().finish())
+}
+
+/// Injects meta of a fetched entity into the template.
+/// Missing entity is answered with 404 and the 404 page, the template if there is none,
+/// deleted one with 410.
+/// When the backend fails, answers with 502 (503 while it is throttling) error page if there is one,
+/// otherwise falls back to the bare template.
+pub fn render<T, F>(
+    resource: Resource,
+    fetched: impl Future<Item = Fetched<T>, Error = Error>,
+    storage: Arc<Storage>,
+    not_found_meta: String,
+    to_meta: F,
+) -> impl Future<Item = HttpResponse, Error = Error>
+where
+    F: FnOnce(&T) -> String,
+{
+    render_page(
+        resource,
+        fetched,
+        storage,
+        not_found_meta,
+        move |storage, entity| storage.inject(&to_meta(entity)),
+    )
+}
+
+/// Same as `render_page` with conditional GET: pages of fetched entities get `ETag`
+/// and `Last-Modified`, clients with the same page get 304 without it being put together
+pub fn render_conditional<T, V, F>(
+    req: &HttpRequest,
+    resource: Resource,
+    fetched: impl Future<Item = Fetched<T>, Error = Error> + 'static,
+    storage: Arc<Storage>,
+    not_found_meta: String,
+    validators: V,
+    to_page: F,
+) -> impl Future<Item = HttpResponse, Error = Error>
+where
+    T: 'static,
+    V: FnOnce(&Storage, &T) -> Validators + 'static,
+    F: FnOnce(&Storage, &T) -> PageBody + 'static,
+{
+    let method = req.method().clone();
+    let headers = req.headers().clone();
+
+    fetched.then(
+        move |fetched| -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+            let validators = fetched
+                .as_ref()
+                .ok()
+                .and_then(|fetched| fetched.entity.as_ref())
+                .map(|entity| validators(&storage, entity));
+
+            match (fetched, validators) {
+                (Ok(fetched), Some(ref validators)) if validators.fresh(&method, &headers) => {
+                    if !fetched.cached {
+                        metrics::record_backend(resource, true);
+                    }
+                    metrics::record_request(resource, Outcome::NotModified);
+
+                    Box::new(future::ok(validators.not_modified()))
+                }
+                (fetched, validators) => Box::new(
+                    render_page(
+                        resource,
+                        future::result(fetched),
+                        storage,
+                        not_found_meta,
+                        to_page,
+                    )
+                    .map(move |mut response| {
+                        if let Some(validators) = validators {
+                            validators.apply(&mut response);
+                        }
+                        response
+                    }),
+                ),
+            }
+        },
+    )
+}
+
+/// Same as `render` for pages that are more than the template with meta
+pub fn render_page<T, F>(
+    resource: Resource,
+    fetched: impl Future<Item = Fetched<T>, Error = Error>,
+    storage: Arc<Storage>,
+    not_found_meta: String,
+    to_page: F,
+) -> impl Future<Item = HttpResponse, Error = Error>
+where
+    F: FnOnce(&Storage, &T) -> PageBody,
+{
+    let storage_copy = storage.clone();
+
+    fetched
+        .map(move |fetched| {
+            if !fetched.cached {
+                metrics::record_backend(resource, fetched.entity.is_some());
+            }
+
+            metrics::record_request(resource, fetched.outcome());
+
+            let status = if fetched.gone {
+                StatusCode::GONE
+            } else {
+                StatusCode::NOT_FOUND
+            };
+
+            match fetched.entity {
+                Some(entity) => html(to_page(&storage, &entity)),
+                None => not_found_page(&storage, status, &not_found_meta).unwrap_or_else(|| {
+                    storage.inject(&not_found_meta).respond(
+                        HttpResponse::build(status)
+                            .content_type("text/html; charset=utf-8")
+                            .header("x-robots-tag", "noindex"),
+                    )
+                }),
+            }
+        })
+        .or_else(move |err| {
+            use log::error;
+
+            error!("Failed to get info about {}: {:#?}", resource.label(), err);
+            metrics::record_backend(resource, false);
+            metrics::record_request(resource, Outcome::Fallback);
+
+            let status = if backend::throttled() {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+
+            Ok(error_page(&storage_copy, status).unwrap_or_else(|| {
+                let mut response = html(storage_copy.index_html.clone());
+                response.extensions_mut().insert(Fallback);
+                response
+            }))
+        })
+}
@@ -0,0 +1,343 @@
+use actix_web::{client::Client, web, Error, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+use super::collection::CollectionMeta;
+use super::tag::TagMeta;
+use super::user::{UsefulMeta, UserMeta, UsernameMeta};
+use super::{accept_language, bad_request, degraded, render};
+use crate::backend;
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::locale;
+use crate::metrics::{self, Resource};
+use crate::storage::Templates;
+
+/// Meta of one entity type: which backend entity a path points to and which tags it gets.
+/// Pages of every provider share validation, degraded mode, caching, 404 and fallbacks.
+pub trait MetaProvider: 'static {
+    type Entity: DeserializeOwned + Serialize + 'static;
+
+    fn resource(&self) -> Resource;
+
+    fn namespace(&self) -> Namespace;
+
+    /// Cache key and backend URL of the requested entity, the rejection reason for invalid paths
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str>;
+
+    /// Locale of the tags, site name locales by default
+    fn locale(&self, req: &HttpRequest, config: &Config) -> Option<String> {
+        accept_language(req).and_then(|value| locale::negotiate(value, config.sitename.locales()))
+    }
+
+    fn meta(
+        &self,
+        config: &Config,
+        path: &str,
+        entity: &Self::Entity,
+        locale: Option<&str>,
+    ) -> String;
+}
+
+/// Entity pages of built-in providers
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    register(cfg, &["/user/{user_id}", "/user/{user_id}/"], UserMeta);
+    register(cfg, &["/@{username}", "/@{username}/"], UsernameMeta);
+    register(
+        cfg,
+        &[
+            "/open/users/{user_id}/useful",
+            "/open/users/{user_id}/useful/",
+        ],
+        UsefulMeta,
+    );
+    register(
+        cfg,
+        &[
+            "/open/collection/{collection_id}",
+            "/open/collection/{collection_id}/",
+            // "Useful boxes" as the frontend calls them
+            "/box/{collection_id}",
+            "/box/{collection_id}/",
+        ],
+        CollectionMeta,
+    );
+    register(cfg, &["/tag/{slug}", "/tag/{slug}/"], TagMeta);
+}
+
+/// Serves `patterns` with the pages of `provider`
+pub fn register<P>(cfg: &mut web::ServiceConfig, patterns: &[&str], provider: P)
+where
+    P: MetaProvider + Clone,
+{
+    for pattern in patterns {
+        cfg.service(
+            metrics::resource(pattern)
+                .data(provider.clone())
+                .to_async(page::<P>),
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn page<P: MetaProvider>(
+    req: HttpRequest,
+    provider: web::Data<P>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    templates: web::Data<Arc<Templates>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let resource = provider.resource();
+    let (key, url) = match provider.locate(&req, &config) {
+        Ok(location) => location,
+        Err(reason) => return Box::new(future::ok(bad_request(resource, reason))),
+    };
+
+    let locale = provider.locale(&req, &config);
+    let path = req.path().to_string();
+
+    if flags.degraded() {
+        let site_meta = config.meta_for_page(&path, locale.as_deref());
+        let config = config.get_ref().clone();
+
+        return Box::new(degraded(
+            resource,
+            templates.current(),
+            backend::stale::<P::Entity>(&cache, provider.namespace(), &key),
+            site_meta,
+            move |entity| provider.meta(&config, &path, entity, locale.as_deref()),
+        ));
+    }
+
+    let fetched =
+        backend::fetch_cached::<P::Entity>(&client, &chaos, &cache, provider.namespace(), key, url);
+
+    Box::new(render(
+        resource,
+        fetched,
+        templates.current(),
+        config.meta_for_not_found(locale.as_deref()),
+        move |entity| provider.meta(&config, &path, entity, locale.as_deref()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use crate::routing::Route;
+
+    fn request(params: &[(&'static str, &'static str)]) -> HttpRequest {
+        params
+            .iter()
+            .fold(TestRequest::default(), |req, (name, value)| {
+                req.param(name, value)
+            })
+            .to_http_request()
+    }
+
+    fn located(key: &str, url: &str) -> Result<(String, String), &'static str> {
+        Ok((key.to_string(), url.to_string()))
+    }
+
+    fn entity<T: DeserializeOwned>(value: serde_json::Value) -> T {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn user_is_located_by_id() {
+        let config = Config::example();
+
+        assert_eq!(
+            UserMeta.locate(&request(&[("user_id", "42")]), &config),
+            located("42", "http://backend/api/users/42/meta/")
+        );
+        assert_eq!(
+            UserMeta.locate(&request(&[("user_id", "4x")]), &config),
+            Err("pattern")
+        );
+        assert_eq!(
+            UserMeta.locate(&request(&[("user_id", "")]), &config),
+            Err("length")
+        );
+    }
+
+    #[test]
+    fn user_meta_is_a_profile() {
+        let config = Config::example();
+        let user = entity(json!({"id": 42, "displayName": "Jane"}));
+        let meta = UserMeta.meta(&config, "/user/42", &user, None);
+
+        assert!(meta.contains("<title>Jane — Howtocards</title>"));
+        assert!(meta.contains(r#"<meta property="og:type" content="profile" />"#));
+        assert!(
+            meta.contains(r#"<meta property="og:url" content="https://howtocards.io/user/42" />"#)
+        );
+    }
+
+    #[test]
+    fn user_locale_falls_back_to_description_locales() {
+        let config = Config::example();
+        let req = TestRequest::default()
+            .header("accept-language", "ru-RU,ru;q=0.9")
+            .to_http_request();
+
+        assert_eq!(UserMeta.locale(&req, &config).as_deref(), Some("ru"));
+        assert_eq!(UserMeta.locale(&request(&[]), &config), None);
+    }
+
+    #[test]
+    fn username_is_located_by_handle() {
+        let config = Config::example();
+
+        assert_eq!(
+            UsernameMeta.locate(&request(&[("username", "jane.doe")]), &config),
+            located("@jane.doe", "http://backend/api/users/@jane.doe/meta/")
+        );
+        assert_eq!(
+            UsernameMeta.locate(&request(&[("username", "jane/doe")]), &config),
+            Err("pattern")
+        );
+        assert_eq!(
+            UsernameMeta.locate(&request(&[("username", "")]), &config),
+            Err("length")
+        );
+    }
+
+    #[test]
+    fn useful_list_is_located_by_user() {
+        let config = Config::example();
+
+        assert_eq!(
+            UsefulMeta.locate(&request(&[("user_id", "42")]), &config),
+            located("42", "http://backend/api/users/42/useful/meta/")
+        );
+        assert_eq!(
+            UsefulMeta.locate(&request(&[("user_id", "-1")]), &config),
+            Err("pattern")
+        );
+    }
+
+    #[test]
+    fn useful_list_meta_names_the_user() {
+        let config = Config::example();
+        let list = entity(json!({"user": {"id": 42, "displayName": "Jane"}, "count": 3}));
+        let meta = UsefulMeta.meta(&config, "/open/users/42/useful", &list, None);
+
+        assert!(meta.contains("<title>Useful cards of Jane — Howtocards</title>"));
+        assert!(meta.contains(r#"<meta property="og:description" content="3 cards" />"#));
+    }
+
+    #[test]
+    fn collection_is_located_by_id() {
+        let config = Config::example();
+
+        assert_eq!(
+            CollectionMeta.locate(&request(&[("collection_id", "7")]), &config),
+            located("7", "http://backend/api/collections/7/meta/")
+        );
+        assert_eq!(
+            CollectionMeta.locate(&request(&[("collection_id", "99999999999")]), &config),
+            Err("length")
+        );
+    }
+
+    #[test]
+    fn collection_meta_has_the_title() {
+        let config = Config::example();
+        let collection = entity(json!({
+            "id": 7,
+            "title": "Kitchen",
+            "updatedAt": "2020-01-01T00:00:00Z"
+        }));
+        let meta = CollectionMeta.meta(&config, "/box/7", &collection, None);
+
+        assert!(meta.contains("<title>Kitchen — Howtocards</title>"));
+        assert!(meta.contains(r#"<meta property="og:title" content="Kitchen" />"#));
+    }
+
+    #[test]
+    fn tag_is_located_by_slug() {
+        let config = Config::example();
+
+        assert_eq!(
+            TagMeta.locate(&request(&[("slug", "rust-lang")]), &config),
+            located("rust-lang", "http://backend/api/tags/rust-lang/meta/")
+        );
+        assert_eq!(
+            TagMeta.locate(&request(&[("slug", "Rust")]), &config),
+            Err("pattern")
+        );
+    }
+
+    #[test]
+    fn tag_meta_counts_cards() {
+        let config = Config::example();
+        let tag = entity(json!({"slug": "rust", "name": "Rust", "cardsCount": 12}));
+        let meta = TagMeta.meta(&config, "/tag/rust", &tag, None);
+
+        assert!(meta.contains("<title>Rust — cards on the topic — Howtocards</title>"));
+        assert!(
+            meta.contains(r#"<meta property="og:description" content="12 cards about Rust" />"#)
+        );
+        assert!(
+            meta.contains(r#"<meta property="og:url" content="https://howtocards.io/tag/rust" />"#)
+        );
+    }
+
+    #[test]
+    fn route_puts_params_into_the_backend_path() {
+        let config = Config::example();
+        let route: Route = toml::from_str(
+            r#"
+            path = "/event/{id}"
+            backend = "/api/events/{id}/meta/"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            route.locate(&request(&[("id", "rust-conf")]), &config),
+            located(
+                "/api/events/rust-conf/meta/",
+                "http://backend/api/events/rust-conf/meta/"
+            )
+        );
+        assert_eq!(
+            route.locate(&request(&[("id", "../admin")]), &config),
+            Err("pattern")
+        );
+    }
+
+    #[test]
+    fn route_meta_is_filled_from_the_entity() {
+        let config = Config::example();
+        let route: Route = toml::from_str(
+            r#"
+            path = "/event/{id}"
+            backend = "/api/events/{id}/meta/"
+
+            [meta]
+            type = "article"
+            title = "{title}"
+            description = "{place.city}"
+            "#,
+        )
+        .unwrap();
+        let event = json!({"title": "RustConf", "place": {"city": "Portland"}});
+        let meta = route.meta(&config, "/event/rust-conf", &event, None);
+
+        assert!(meta.contains("<title>RustConf — Howtocards</title>"));
+        assert!(meta.contains(r#"<meta property="og:type" content="article" />"#));
+        assert!(meta.contains(r#"<meta property="og:description" content="Portland" />"#));
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::{client::Client, error, web, Error, HttpResponse};
+use futures::{future, Future};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::image;
+use crate::config::Config;
+use crate::images::{self, ImageCache};
+
+/// Paths are put into `IMAGE_URL`, so they stay plain relative file paths
+const PATH_MAX_LENGTH: usize = 512;
+
+#[derive(Debug, Deserialize)]
+pub struct ProxyPath {
+    size: String,
+    path: String,
+}
+
+/// `/img/{size}/{path}`: image of `IMAGE_URL` cropped to one of the social card sizes,
+/// `1200x630` or `800x418`. Cached on disk by path, uploaded images never change.
+pub fn proxy(
+    path: web::Path<ProxyPath>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    cache: web::Data<Arc<ImageCache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let size = match images::proxy_size(&path.size) {
+        Some(size) => size,
+        None => return Box::new(future::ok(HttpResponse::NotFound().finish())),
+    };
+
+    if !valid_path(&path.path) {
+        return Box::new(future::ok(HttpResponse::BadRequest().finish()));
+    }
+
+    let key = format!(
+        "proxy-{}x{}-{}.jpg",
+        size.0,
+        size.1,
+        images::version_hash(&path.path)
+    );
+
+    if let Some(bytes) = cache.get(&key) {
+        return Box::new(future::ok(image("image/jpeg", bytes)));
+    }
+
+    let cache = cache.get_ref().clone();
+
+    Box::new(
+        images::fetch(&client, config.image_url_for(&path.path)).and_then(
+            move |picture| -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+                let picture = match picture {
+                    Some(picture) => picture,
+                    None => return Box::new(future::ok(HttpResponse::NotFound().finish())),
+                };
+
+                Box::new(
+                    web::block(move || {
+                        let bytes = images::encode_jpeg(&images::crop(&picture, size))?;
+
+                        if let Err(err) = cache.put(&key, &bytes) {
+                            log::warn!("Failed to cache image {}: {}", key, err);
+                        }
+
+                        Ok::<_, image::ImageError>(bytes)
+                    })
+                    .map_err(error::ErrorInternalServerError)
+                    .map(|bytes| image("image/jpeg", bytes)),
+                )
+            },
+        ),
+    )
+}
+
+fn valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.len() <= PATH_MAX_LENGTH
+        && path.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_'))
+        })
+}
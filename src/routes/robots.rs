@@ -0,0 +1,62 @@
+use actix_service::Service;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpResponse};
+use futures::Future;
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// `/robots.txt`: `ROBOTS_TXT` or allow everything, with the sitemap unless the file
+/// names one itself. With `STAGING` every crawler is turned away whatever the file says.
+pub fn robots(config: web::Data<Arc<Config>>) -> HttpResponse {
+    let body = if config.staging {
+        "User-agent: *\nDisallow: /\n".to_string()
+    } else {
+        let mut body = config
+            .robots_txt
+            .clone()
+            .unwrap_or_else(|| "User-agent: *\nAllow: /\n".to_string());
+
+        let has_sitemap = body
+            .lines()
+            .any(|line| line.trim_start().to_lowercase().starts_with("sitemap:"));
+        if !has_sitemap {
+            if !body.ends_with('\n') {
+                body.push('\n');
+            }
+            body.push_str(&format!("\nSitemap: {}/sitemap.xml\n", config.public_url));
+        }
+        body
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .header("cache-control", "public, max-age=3600")
+        .body(body)
+}
+
+/// With `NOINDEX` responses that aren't made of the template, like images,
+/// embeds and sitemaps, are kept out of search indexes as well
+pub fn noindex<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Box<dyn Future<Item = ServiceResponse, Error = Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let noindex = req
+        .app_data::<Arc<Config>>()
+        .is_some_and(|config| config.noindex);
+
+    Box::new(srv.call(req).map(move |mut res| {
+        if noindex {
+            res.headers_mut().insert(
+                HeaderName::from_static("x-robots-tag"),
+                HeaderValue::from_static("noindex, nofollow"),
+            );
+        }
+        res
+    }))
+}
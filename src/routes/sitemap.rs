@@ -0,0 +1,217 @@
+use actix_web::{client::Client, web, Error, HttpResponse};
+use futures::future::{self, Loop};
+use futures::Future;
+use std::sync::Arc;
+
+use crate::backend::{self, PublishedCard, PublishedCards};
+use crate::cache::{Cache, Namespace};
+use crate::chaos::Chaos;
+use crate::config::Config;
+use crate::flags::Flags;
+use crate::metrics::{self, Outcome, Resource};
+
+/// Limit of the sitemap protocol, larger sitemaps are split and listed by an index
+const MAX_URLS: usize = 50_000;
+/// Guards against a backend that never runs out of pages
+const MAX_BACKEND_PAGES: u32 = 10_000;
+const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics::resource("/sitemap.xml").route(web::get().to_async(sitemap)))
+        .service(metrics::resource("/sitemap-{part}.xml").route(web::get().to_async(part)));
+}
+
+/// `/sitemap.xml`: the home page and every published card of the backend, paged through
+/// `/api/cards/published/meta/?page=N`. Pages are cached for `CACHE_SITEMAP_TTL`.
+/// Past 50 000 URLs it's an index of `/sitemap-{part}.xml` files.
+pub fn sitemap(
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if flags.degraded() {
+        return Box::new(future::ok(unavailable(Outcome::Degraded)));
+    }
+
+    let config = config.get_ref().clone();
+
+    Box::new(
+        published_cards(&client, &chaos, &cache, &config).then(move |cards| {
+            let cards = match cards {
+                Ok(cards) => cards,
+                Err(err) => return Ok(failed(err)),
+            };
+
+            let entries = entries(&config, &cards);
+            metrics::record_request(Resource::Sitemap, Outcome::Miss);
+
+            Ok(if entries.len() <= MAX_URLS {
+                xml(urlset(&entries))
+            } else {
+                xml(index(&config, &entries))
+            })
+        }),
+    )
+}
+
+/// `/sitemap-{part}.xml`: one of the files the index lists, counted from 1
+pub fn part(
+    part: web::Path<usize>,
+    client: web::Data<Client>,
+    config: web::Data<Arc<Config>>,
+    flags: web::Data<Arc<Flags>>,
+    chaos: web::Data<Arc<Chaos>>,
+    cache: web::Data<Arc<Cache>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let part = part.into_inner();
+    if part == 0 {
+        return Box::new(future::ok(HttpResponse::NotFound().finish()));
+    }
+
+    if flags.degraded() {
+        return Box::new(future::ok(unavailable(Outcome::Degraded)));
+    }
+
+    let config = config.get_ref().clone();
+
+    Box::new(
+        published_cards(&client, &chaos, &cache, &config).then(move |cards| {
+            let cards = match cards {
+                Ok(cards) => cards,
+                Err(err) => return Ok(failed(err)),
+            };
+
+            let entries = entries(&config, &cards);
+            metrics::record_request(Resource::Sitemap, Outcome::Miss);
+
+            Ok(match entries.chunks(MAX_URLS).nth(part - 1) {
+                Some(chunk) => xml(urlset(chunk)),
+                None => HttpResponse::NotFound().finish(),
+            })
+        }),
+    )
+}
+
+/// Location and `lastmod` of a URL
+type Entry = (String, Option<String>);
+
+fn entries(config: &Config, cards: &[PublishedCard]) -> Vec<Entry> {
+    let mut entries = Vec::with_capacity(cards.len() + 1);
+    entries.push((format!("{}/", config.public_url), None));
+    entries.extend(cards.iter().map(|card| {
+        (
+            format!("{}/open/{}", config.public_url, card.id),
+            Some(card.updated_at.clone()),
+        )
+    }));
+    entries
+}
+
+/// Every page of published cards, in the order of the backend
+fn published_cards(
+    client: &Client,
+    chaos: &Arc<Chaos>,
+    cache: &Arc<Cache>,
+    config: &Arc<Config>,
+) -> impl Future<Item = Vec<PublishedCard>, Error = Error> {
+    let client = client.clone();
+    let chaos = chaos.clone();
+    let cache = cache.clone();
+    let config = config.clone();
+
+    future::loop_fn((1, Vec::new()), move |(page, mut cards)| {
+        backend::fetch_cached::<PublishedCards>(
+            &client,
+            &chaos,
+            &cache,
+            Namespace::Sitemap,
+            page.to_string(),
+            config.backend_published_url(page),
+        )
+        .map(move |fetched| match fetched.entity {
+            Some(published) => {
+                cards.extend(published.cards);
+                if page < published.pages.min(MAX_BACKEND_PAGES) {
+                    Loop::Continue((page + 1, cards))
+                } else {
+                    Loop::Break(cards)
+                }
+            }
+            None => Loop::Break(cards),
+        })
+    })
+}
+
+fn urlset(entries: &[Entry]) -> String {
+    let mut xml = String::with_capacity(entries.len() * 100 + 200);
+    xml.push_str(XML_DECLARATION);
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+
+    for (location, modified) in entries {
+        xml.push_str("<url><loc>");
+        xml.push_str(&htmlescape::encode_minimal(location));
+        xml.push_str("</loc>");
+        if let Some(modified) = modified {
+            xml.push_str("<lastmod>");
+            xml.push_str(&htmlescape::encode_minimal(modified));
+            xml.push_str("</lastmod>");
+        }
+        xml.push_str("</url>\n");
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// `lastmod` of a part is the latest one of its URLs
+fn index(config: &Config, entries: &[Entry]) -> String {
+    let mut xml = String::new();
+    xml.push_str(XML_DECLARATION);
+    xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+
+    for (number, chunk) in entries.chunks(MAX_URLS).enumerate() {
+        xml.push_str("<sitemap><loc>");
+        xml.push_str(&htmlescape::encode_minimal(&format!(
+            "{}/sitemap-{}.xml",
+            config.public_url,
+            number + 1
+        )));
+        xml.push_str("</loc>");
+        if let Some(modified) = chunk
+            .iter()
+            .filter_map(|(_, modified)| modified.as_ref())
+            .max()
+        {
+            xml.push_str("<lastmod>");
+            xml.push_str(&htmlescape::encode_minimal(modified));
+            xml.push_str("</lastmod>");
+        }
+        xml.push_str("</sitemap>\n");
+    }
+
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+fn xml(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .header("cache-control", "public, max-age=3600")
+        .body(body)
+}
+
+/// A partial sitemap would hide cards from crawlers, they'd better come back later
+fn failed(err: Error) -> HttpResponse {
+    log::error!("Failed to build sitemap: {}", err);
+    metrics::record_backend(Resource::Sitemap, false);
+    unavailable(Outcome::Fallback)
+}
+
+fn unavailable(outcome: Outcome) -> HttpResponse {
+    metrics::record_request(Resource::Sitemap, outcome);
+    HttpResponse::ServiceUnavailable()
+        .header("retry-after", "600")
+        .finish()
+}
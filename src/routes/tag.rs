@@ -0,0 +1,59 @@
+use actix_web::HttpRequest;
+
+use super::provider::MetaProvider;
+use super::{accept_language, match_param};
+use crate::backend::Tag;
+use crate::cache::Namespace;
+use crate::config::Config;
+use crate::locale;
+use crate::metrics::Resource;
+
+/// Slugs are short lowercase handles, anything else never reaches the backend
+const SLUG_MAX_LENGTH: usize = 64;
+
+/// `/tag/{slug}`: topic page with the name and cards count of the tag
+#[derive(Clone, Debug)]
+pub struct TagMeta;
+
+impl MetaProvider for TagMeta {
+    type Entity = Tag;
+
+    fn resource(&self) -> Resource {
+        Resource::Tag
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::Tag
+    }
+
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let slug = match_param(req, "slug");
+        validate_slug(slug)?;
+
+        Ok((slug.to_string(), config.backend_tag_url(slug)))
+    }
+
+    fn locale(&self, req: &HttpRequest, config: &Config) -> Option<String> {
+        accept_language(req).and_then(|value| {
+            locale::negotiate(value, config.sitename.locales().chain(vec!["en", "ru"]))
+        })
+    }
+
+    fn meta(&self, config: &Config, _path: &str, tag: &Tag, locale: Option<&str>) -> String {
+        config.meta_for_tag(tag, locale)
+    }
+}
+
+fn validate_slug(slug: &str) -> Result<(), &'static str> {
+    if slug.is_empty() || slug.len() > SLUG_MAX_LENGTH {
+        return Err("length");
+    }
+
+    if !slug.bytes().all(|byte| {
+        byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-' || byte == b'_'
+    }) {
+        return Err("pattern");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use actix_web::HttpRequest;
+
+use super::provider::MetaProvider;
+use super::{accept_language, match_param, validate_id};
+use crate::backend::{UsefulList, User};
+use crate::cache::Namespace;
+use crate::config::Config;
+use crate::locale;
+use crate::metrics::Resource;
+
+/// Languages of the composed profile description
+const DESCRIPTION_LOCALES: &[&str] = &["en", "ru"];
+
+/// Usernames are short ASCII handles, anything else never reaches the backend
+const USERNAME_MAX_LENGTH: usize = 64;
+
+/// `/user/{user_id}`: profile by id
+#[derive(Clone, Debug)]
+pub struct UserMeta;
+
+/// `/@{username}`: same profile, looked up by username
+#[derive(Clone, Debug)]
+pub struct UsernameMeta;
+
+/// `/open/users/{user_id}/useful`: public list of cards the user found useful
+#[derive(Clone, Debug)]
+pub struct UsefulMeta;
+
+impl MetaProvider for UserMeta {
+    type Entity = User;
+
+    fn resource(&self) -> Resource {
+        Resource::User
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::User
+    }
+
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let user_id = validate_id(match_param(req, "user_id"), config.card_id_max_length)?;
+
+        Ok((user_id.to_string(), config.backend_user_url(user_id)))
+    }
+
+    fn locale(&self, req: &HttpRequest, config: &Config) -> Option<String> {
+        description_locale(req, config)
+    }
+
+    fn meta(&self, config: &Config, _path: &str, user: &User, locale: Option<&str>) -> String {
+        config.meta_for_user(user, locale)
+    }
+}
+
+impl MetaProvider for UsernameMeta {
+    type Entity = User;
+
+    fn resource(&self) -> Resource {
+        Resource::User
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::User
+    }
+
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let username = match_param(req, "username");
+        validate_username(username)?;
+
+        Ok((
+            format!("@{}", username),
+            config.backend_username_url(username),
+        ))
+    }
+
+    fn locale(&self, req: &HttpRequest, config: &Config) -> Option<String> {
+        description_locale(req, config)
+    }
+
+    fn meta(&self, config: &Config, _path: &str, user: &User, locale: Option<&str>) -> String {
+        config.meta_for_user(user, locale)
+    }
+}
+
+impl MetaProvider for UsefulMeta {
+    type Entity = UsefulList;
+
+    fn resource(&self) -> Resource {
+        Resource::UsefulList
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::UsefulList
+    }
+
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let user_id = validate_id(match_param(req, "user_id"), config.card_id_max_length)?;
+
+        Ok((user_id.to_string(), config.backend_useful_url(user_id)))
+    }
+
+    fn locale(&self, req: &HttpRequest, config: &Config) -> Option<String> {
+        description_locale(req, config)
+    }
+
+    fn meta(
+        &self,
+        config: &Config,
+        _path: &str,
+        list: &UsefulList,
+        locale: Option<&str>,
+    ) -> String {
+        config.meta_for_useful(list, locale)
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), &'static str> {
+    if username.is_empty() || username.len() > USERNAME_MAX_LENGTH {
+        return Err("length");
+    }
+
+    if !username
+        .bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' || byte == b'.')
+    {
+        return Err("pattern");
+    }
+
+    Ok(())
+}
+
+fn description_locale(req: &HttpRequest, config: &Config) -> Option<String> {
+    accept_language(req).and_then(|value| {
+        locale::negotiate(
+            value,
+            config
+                .sitename
+                .locales()
+                .chain(DESCRIPTION_LOCALES.iter().cloned()),
+        )
+    })
+}
@@ -0,0 +1,205 @@
+use actix_web::{web, HttpRequest};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cache::Namespace;
+use crate::config::Config;
+use crate::meta::{create_meta, create_title, join_meta, truncate, DESCRIPTION_LIMIT};
+use crate::metrics::Resource;
+use crate::routes::provider::{self, MetaProvider};
+
+/// Path parameters are put into backend URLs, so they are short plain handles
+const PARAM_MAX_LENGTH: usize = 64;
+
+/// Entity pages declared in `ROUTES_FILE` instead of code:
+///
+/// ```toml
+/// [[route]]
+/// path = "/event/{id}"
+/// backend = "/api/events/{id}/meta/"
+///
+/// [route.meta]
+/// type = "article"
+/// title = "{title}"
+/// description = "{description}"
+/// image = "{previewUrl}"
+/// ```
+///
+/// `backend` is relative to `BACKEND_URL` and answers like other meta endpoints,
+/// `{"ok": true, "result": {"meta": {...}}}`.
+/// Meta templates take `{field}` or `{nested.field}` of the entity meta.
+/// Built-in routes win over routes of the table.
+#[derive(Debug, Default, Deserialize)]
+pub struct RoutingTable {
+    #[serde(default, rename = "route")]
+    routes: Vec<Route>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Route {
+    /// actix pattern, `/event/{id}`
+    path: String,
+    backend: String,
+    #[serde(default)]
+    meta: MetaTemplate,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MetaTemplate {
+    /// `og:type`, `website` by default
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    /// Relative images are served from `IMAGE_URL`
+    image: Option<String>,
+    /// Canonical path, the requested one by default
+    url: Option<String>,
+}
+
+impl RoutingTable {
+    pub fn from_env() -> Result<Self, String> {
+        let path = match std::env::var("ROUTES_FILE") {
+            Ok(ref path) if !path.is_empty() => path.clone(),
+            _ => return Ok(RoutingTable::default()),
+        };
+
+        let source = std::fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?;
+        let table: RoutingTable =
+            toml::from_str(&source).map_err(|err| format!("{}: {}", path, err))?;
+
+        for route in &table.routes {
+            if !route.path.starts_with('/') || !route.backend.starts_with('/') {
+                return Err(format!(
+                    "{}: path and backend of {:?} must start with /",
+                    path, route.path
+                ));
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        for route in &self.routes {
+            provider::register(cfg, &[&route.path], route.clone());
+        }
+    }
+}
+
+impl MetaProvider for Route {
+    type Entity = Value;
+
+    fn resource(&self) -> Resource {
+        Resource::Route
+    }
+
+    fn namespace(&self) -> Namespace {
+        Namespace::Route
+    }
+
+    /// Backend path with path parameters, fails when one of them isn't a plain handle
+    fn locate(&self, req: &HttpRequest, config: &Config) -> Result<(String, String), &'static str> {
+        let mut path = self.backend.clone();
+
+        for (name, value) in req.match_info().iter() {
+            if value.is_empty() || value.len() > PARAM_MAX_LENGTH {
+                return Err("length");
+            }
+            if !value
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+            {
+                return Err("pattern");
+            }
+            path = path.replace(&format!("{{{}}}", name), value);
+        }
+
+        let url = format!("{}{}", config.backend_url, path);
+        Ok((path, url))
+    }
+
+    fn meta(&self, config: &Config, path: &str, entity: &Value, locale: Option<&str>) -> String {
+        let template = &self.meta;
+        let text = |text: &Option<String>| {
+            text.as_ref()
+                .map(|text| fill(text, entity))
+                .filter(|text| !text.trim().is_empty())
+        };
+
+        let sitename = config.sitename.get(locale);
+        let heading = text(&template.title);
+        let page_title = match heading {
+            Some(ref heading) => config.page_title(heading, locale),
+            None => create_title(sitename),
+        };
+        let title = heading.unwrap_or_else(|| sitename.to_string());
+        let description = text(&template.description)
+            .map(|description| truncate(description.trim(), DESCRIPTION_LIMIT));
+        let image = text(&template.image).map(|image| config.image_url_for(&image));
+        let url = format!(
+            "{}{}",
+            config.public_url,
+            text(&template.url).unwrap_or_else(|| path.to_string())
+        );
+
+        let mut tags = vec![
+            page_title,
+            create_meta("title", &title),
+            create_meta("og:site_name", sitename),
+            create_meta("og:type", template.kind.as_deref().unwrap_or("website")),
+            create_meta("og:title", &title),
+            create_meta("og:url", &url),
+            create_meta(
+                "twitter:card",
+                if image.is_some() {
+                    "summary_large_image"
+                } else {
+                    "summary"
+                },
+            ),
+            config.twitter_site(),
+            create_meta("twitter:title", &title),
+        ];
+
+        if let Some(ref description) = description {
+            tags.push(create_meta("description", description));
+            tags.push(create_meta("og:description", description));
+            tags.push(create_meta("twitter:description", description));
+        }
+
+        if let Some(ref image) = image {
+            tags.push(create_meta("og:image", image));
+            tags.push(create_meta("twitter:image", image));
+        }
+
+        join_meta(tags)
+    }
+}
+
+/// Replaces `{field}` and `{nested.field}` with string or number values of the entity
+fn fill(template: &str, entity: &Value) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(length) => start + length,
+            None => break,
+        };
+
+        result.push_str(&rest[..start]);
+        let value = rest[start + 1..end]
+            .split('.')
+            .try_fold(entity, |value, key| value.get(key));
+        match value {
+            Some(Value::String(text)) => result.push_str(text),
+            Some(Value::Number(number)) => result.push_str(&number.to_string()),
+            _ => {}
+        }
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    result
+}
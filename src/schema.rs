@@ -0,0 +1,671 @@
+use serde_json::{json, Map, Value};
+
+use crate::cache::Namespace;
+
+/// Kind of an environment variable value
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Url,
+    Integer,
+    Number,
+    Boolean,
+    /// humantime format: `30s`, `5m`, `1h 30m`
+    Duration,
+}
+
+#[derive(Debug)]
+struct Setting {
+    name: String,
+    kind: Kind,
+    default: Option<Value>,
+    required: bool,
+    description: String,
+}
+
+fn required(name: &str, kind: Kind, description: &str) -> Setting {
+    Setting {
+        name: name.to_string(),
+        kind,
+        default: None,
+        required: true,
+        description: description.to_string(),
+    }
+}
+
+fn optional(name: &str, kind: Kind, default: Value, description: &str) -> Setting {
+    Setting {
+        name: name.to_string(),
+        kind,
+        default: if default.is_null() {
+            None
+        } else {
+            Some(default)
+        },
+        required: false,
+        description: description.to_string(),
+    }
+}
+
+fn settings() -> Vec<Setting> {
+    let mut settings = vec![
+        required("PUBLIC_URL", Kind::Url, "Public URL of the service"),
+        required("IMAGE_URL", Kind::Url, "Public URL for image storage"),
+        required("BACKEND_URL", Kind::Url, "Internal backend URL"),
+        required(
+            "LISTEN_HOST",
+            Kind::String,
+            "Comma separated host:port list to listen, with optional ;backlog=N;v6only=bool",
+        ),
+        optional(
+            "ADMIN_LISTEN_HOST",
+            Kind::String,
+            Value::Null,
+            "Separate listeners for admin, metrics and health endpoints",
+        ),
+        optional(
+            "RUN_AS_USER",
+            Kind::String,
+            Value::Null,
+            "Unix user to switch to once sockets are bound",
+        ),
+        optional(
+            "RUN_AS_GROUP",
+            Kind::String,
+            Value::Null,
+            "Unix group to switch to, primary group of RUN_AS_USER by default",
+        ),
+        optional(
+            "WORKERS",
+            Kind::Integer,
+            Value::Null,
+            "Worker threads, number of CPUs by default",
+        ),
+        optional(
+            "MAX_CONNECTIONS",
+            Kind::Integer,
+            json!(0),
+            "Open connections across all workers, 0 keeps the actix default",
+        ),
+        optional(
+            "MAX_IN_FLIGHT",
+            Kind::Integer,
+            json!(0),
+            "Requests handled at once before answering 503, 0 disables the limit",
+        ),
+        optional(
+            "CLIENT_HEADER_TIMEOUT",
+            Kind::Duration,
+            json!("5s"),
+            "Connections are dropped when request headers don't arrive in time",
+        ),
+        optional(
+            "KEEP_ALIVE",
+            Kind::Duration,
+            json!("5s"),
+            "Idle keep-alive connections are closed after it",
+        ),
+        required(
+            "SITENAME",
+            Kind::String,
+            "Site name for meta tags, plain string or JSON object of names per locale",
+        ),
+        optional(
+            "SITE_DESCRIPTION",
+            Kind::String,
+            Value::Null,
+            "Description of pages without an entity, plain string or JSON object per locale",
+        ),
+        optional(
+            "SITE_IMAGE",
+            Kind::String,
+            Value::Null,
+            "Preview image of pages without an entity, absolute URL or path under PUBLIC_URL",
+        ),
+        optional(
+            "TWITTER_SITE",
+            Kind::String,
+            json!("@howtocards_io"),
+            "Twitter account of the site for twitter:site, none when empty",
+        ),
+        optional(
+            "DEFAULT_OG_IMAGE",
+            Kind::String,
+            Value::Null,
+            "Preview image of cards without one, absolute URL or path under PUBLIC_URL",
+        ),
+        optional(
+            "PREVIEW_CHECK",
+            Kind::Boolean,
+            json!(false),
+            "Replace card previews the image server doesn't have with the fallback image",
+        ),
+        optional(
+            "IMAGE_PROXY",
+            Kind::Boolean,
+            json!(false),
+            "Serve card previews through /img/{size}/ cropped to social card sizes",
+        ),
+        optional(
+            "OG_IMAGE_FONT",
+            Kind::String,
+            Value::Null,
+            "TrueType font of previews generated for cards without one, disabled when empty",
+        ),
+        optional(
+            "OG_IMAGE_BACKGROUND",
+            Kind::String,
+            Value::Null,
+            "Background image of generated previews",
+        ),
+        optional(
+            "OG_IMAGE_COLOR",
+            Kind::String,
+            json!("#1b1b1f"),
+            "Text color of generated previews, #rrggbb",
+        ),
+        optional(
+            "STAGING",
+            Kind::Boolean,
+            json!(false),
+            "Non-production deployment, kept out of search engines",
+        ),
+        optional(
+            "NOINDEX",
+            Kind::Boolean,
+            json!(false),
+            "Keep every page and response out of search indexes, on with STAGING as well",
+        ),
+        optional(
+            "ROBOTS_TXT",
+            Kind::String,
+            Value::Null,
+            "File served as /robots.txt, everything is allowed when empty",
+        ),
+        optional(
+            "INDEX_HTML_PATH",
+            Kind::String,
+            Value::Null,
+            "Template file, comma separated fragments or directory of *.html fragments, \
+             required unless INDEX_HTML_URL is set",
+        ),
+        optional(
+            "INDEX_HTML_URL",
+            Kind::Url,
+            Value::Null,
+            "Remote template used when INDEX_HTML_PATH is empty",
+        ),
+        optional(
+            "PATH_LOCALES",
+            Kind::String,
+            Value::Null,
+            "Comma separated locales of /{locale}/open/{card_id} pages",
+        ),
+        optional(
+            "LOCALE_INDEX_HTML",
+            Kind::String,
+            Value::Null,
+            "JSON object of template paths per locale for localized pages",
+        ),
+        optional(
+            "INDEX_HTML_REFRESH_INTERVAL",
+            Kind::Duration,
+            json!("1m"),
+            "How often INDEX_HTML_URL is fetched again, 0s disables refreshing",
+        ),
+        optional(
+            "INJECT_MARKER",
+            Kind::String,
+            json!("</head>"),
+            "Meta tags are injected right before this string of the template",
+        ),
+        optional(
+            "TEMPLATE_STRICT",
+            Kind::Boolean,
+            json!(false),
+            "Refuse to start when the template has no INJECT_MARKER",
+        ),
+        optional(
+            "TEMPLATE_CHECK_INTERVAL",
+            Kind::Duration,
+            json!("2s"),
+            "How often INDEX_HTML_PATH is checked for changes, 0s disables the check",
+        ),
+        optional(
+            "COMPRESSION",
+            Kind::Boolean,
+            json!(true),
+            "Compress responses with gzip or br, pages from pre-gzipped template parts",
+        ),
+        optional(
+            "ERROR_PAGES_DIR",
+            Kind::String,
+            Value::Null,
+            "Directory with {status}.html, 50x.html and 404.html error pages",
+        ),
+        optional(
+            "CRITICAL_CSS_PATH",
+            Kind::String,
+            Value::Null,
+            "CSS file inlined into the template head",
+        ),
+        optional(
+            "CRITICAL_CSS_DEFER",
+            Kind::Boolean,
+            json!(false),
+            "Load stylesheet links without blocking the first paint",
+        ),
+        optional(
+            "ADMIN_TOKEN",
+            Kind::String,
+            Value::Null,
+            "Bearer token for /_admin, admin API is disabled when empty",
+        ),
+        optional(
+            "HOOKS_TOKEN",
+            Kind::String,
+            Value::Null,
+            "Bearer token for /_hooks, hooks are disabled when empty",
+        ),
+        optional(
+            "DEGRADED_MODE",
+            Kind::Boolean,
+            json!(false),
+            "Start without calling the backend, serving cached or site meta only",
+        ),
+        optional(
+            "MAINTENANCE_MODE",
+            Kind::Boolean,
+            json!(false),
+            "Serve the maintenance page to browsers and cached meta to crawlers",
+        ),
+        optional(
+            "MAINTENANCE_PAGE",
+            Kind::String,
+            Value::Null,
+            "Static maintenance page, 503 error page by default",
+        ),
+        optional(
+            "MAINTENANCE_RETRY_AFTER",
+            Kind::Duration,
+            json!("1h"),
+            "Retry-After of the maintenance page",
+        ),
+        optional(
+            "CRAWLER_USER_AGENTS",
+            Kind::String,
+            Value::Null,
+            "Comma separated User-Agent substrings of crawlers",
+        ),
+        optional(
+            "BACKEND_LOG",
+            Kind::Boolean,
+            json!(false),
+            "Log backend requests and responses at info level, secrets redacted",
+        ),
+        optional(
+            "CARD_ID_MAX_LENGTH",
+            Kind::Integer,
+            json!(10),
+            "Longer entity ids are rejected without calling the backend",
+        ),
+        optional(
+            "FEED_URL",
+            Kind::String,
+            Value::Null,
+            "Site-wide feed linked from every page for feed discovery",
+        ),
+        optional(
+            "USER_FEED_URL",
+            Kind::String,
+            Value::Null,
+            "Author feed linked from profile pages, {id} is the user id",
+        ),
+        optional(
+            "FEED_TYPE",
+            Kind::String,
+            json!("application/rss+xml"),
+            "Content type of the feed links",
+        ),
+        optional(
+            "SEARCH_URL",
+            Kind::String,
+            json!("/search?q={search_term_string}"),
+            "Frontend search URL template for the WebSite SearchAction, empty disables it",
+        ),
+        optional(
+            "RELATED_LINK_TAGS",
+            Kind::Boolean,
+            json!(false),
+            "Add <link rel=\"related\"> for related cards",
+        ),
+        optional(
+            "CARD_STATE_VAR",
+            Kind::String,
+            json!("__CARD_META__"),
+            "Global with the fetched card on card pages, empty disables it",
+        ),
+        optional(
+            "RENDER_CARD_BODY",
+            Kind::Boolean,
+            json!(false),
+            "Render the card text into #root for crawlers",
+        ),
+        optional(
+            "CARD_META_TEMPLATE",
+            Kind::String,
+            Value::Null,
+            "Tera template replacing the built-in card meta",
+        ),
+        optional(
+            "BREADCRUMBS",
+            Kind::String,
+            json!("card,collection,tag"),
+            "Pages with BreadcrumbList structured data, comma separated",
+        ),
+        optional(
+            "ROUTES_FILE",
+            Kind::String,
+            Value::Null,
+            "TOML table of extra entity pages mapped to backend meta endpoints",
+        ),
+        optional(
+            "TAG_MIN_CARDS",
+            Kind::Integer,
+            json!(3),
+            "Tag pages with fewer cards get noindex",
+        ),
+        optional(
+            "CHAOS_RATE",
+            Kind::Number,
+            json!(0.0),
+            "Fraction of backend requests with injected faults, staging only",
+        ),
+        optional(
+            "CHAOS_FAULTS",
+            Kind::String,
+            json!("latency,timeout,malformed,partial"),
+            "Comma separated faults to inject",
+        ),
+        optional(
+            "CHAOS_LATENCY_MS",
+            Kind::Integer,
+            json!(3000),
+            "Delay of latency and timeout faults in milliseconds",
+        ),
+        optional(
+            "STATS_TOP_RETENTION",
+            Kind::Duration,
+            json!("24h"),
+            "How long requested card ids are kept for /_admin/stats/top",
+        ),
+        optional(
+            "MEMORY_WARN_FRACTION",
+            Kind::Number,
+            json!(0.5),
+            "Warn when caches take more than this fraction of the memory limit",
+        ),
+        optional(
+            "MEMORY_CHECK_INTERVAL",
+            Kind::Duration,
+            json!("30s"),
+            "How often cache memory is checked",
+        ),
+        optional(
+            "MEMORY_LIMIT_BYTES",
+            Kind::Integer,
+            Value::Null,
+            "Memory limit, detected from cgroup when empty",
+        ),
+        optional(
+            "CANONICAL_HOST",
+            Kind::String,
+            Value::Null,
+            "Requests for other hosts are redirected to this host",
+        ),
+        optional(
+            "FORCE_HTTPS",
+            Kind::Boolean,
+            json!(false),
+            "Redirect plain http requests to https",
+        ),
+        optional(
+            "ACME_CHALLENGE_DIR",
+            Kind::String,
+            Value::Null,
+            "Webroot of an ACME client, HTTP-01 challenges are answered from it",
+        ),
+        optional(
+            "IMAGE_CACHE_DIR",
+            Kind::String,
+            Value::Null,
+            "Directory for generated images, system temp dir when empty",
+        ),
+        optional(
+            "CACHE_STALE_WHILE_REVALIDATE",
+            Kind::Duration,
+            json!("0s"),
+            "Serve entries expired within it and refresh them in the background, 0 disables it",
+        ),
+        optional(
+            "CACHE_NOT_FOUND_TTL",
+            Kind::Duration,
+            json!("30s"),
+            "How long entities missing on the backend are answered with 404 without asking it, 0 disables it",
+        ),
+        optional(
+            "MICROCACHE_TTL",
+            Kind::Duration,
+            json!("1s"),
+            "How long finished card pages are answered as they are, 0 disables it",
+        ),
+        optional(
+            "MICROCACHE_CAPACITY",
+            Kind::Integer,
+            json!(500),
+            "Finished card pages kept per process",
+        ),
+        optional(
+            "CACHE_REDIS_URL",
+            Kind::Url,
+            Value::Null,
+            "Redis shared by replicas for the meta cache, in-memory cache when empty",
+        ),
+        optional(
+            "CACHE_REDIS_PREFIX",
+            Kind::String,
+            json!("ssi"),
+            "Prefix of Redis cache keys",
+        ),
+        optional(
+            "CACHE_REDIS_TIMEOUT",
+            Kind::Duration,
+            json!("100ms"),
+            "Redis connect and command timeout, cache misses after it",
+        ),
+        optional(
+            "CACHE_REDIS_STALE",
+            Kind::Duration,
+            json!("1h"),
+            "How long expired entries stay in Redis for maintenance and backend outages",
+        ),
+        optional(
+            "CACHE_REDIS_COOLDOWN",
+            Kind::Duration,
+            json!("5s"),
+            "How long Redis is skipped after failed calls in a row",
+        ),
+        optional(
+            "CDN_MAX_AGE",
+            Kind::Duration,
+            json!("0s"),
+            "s-maxage of card pages for a CDN in front, 0 keeps them out of shared caches",
+        ),
+        optional(
+            "SURROGATE_KEY_HEADER",
+            Kind::String,
+            json!("Surrogate-Key"),
+            "Header with the card-{id} purge key of card pages, empty leaves it out",
+        ),
+        optional(
+            "CDN_PURGE",
+            Kind::String,
+            Value::Null,
+            "Purge card pages at the CDN along with the cache: fastly or cloudflare",
+        ),
+        optional(
+            "CDN_PURGE_ZONE",
+            Kind::String,
+            Value::Null,
+            "Fastly service id or Cloudflare zone id for CDN_PURGE",
+        ),
+        optional(
+            "CDN_PURGE_TOKEN",
+            Kind::String,
+            Value::Null,
+            "API token of CDN_PURGE allowed to purge",
+        ),
+        optional(
+            "PUBLISH_QUEUE_SIZE",
+            Kind::Integer,
+            json!(1000),
+            "Published cards waiting for pre-rendering",
+        ),
+        optional(
+            "PUBLISH_CONCURRENCY",
+            Kind::Integer,
+            json!(4),
+            "Published cards pre-rendered at once",
+        ),
+        optional(
+            "PUBLISH_PING_URLS",
+            Kind::String,
+            Value::Null,
+            "Comma separated URLs requested after every pre-rendered card",
+        ),
+        optional(
+            "READY_ERROR_RATE",
+            Kind::Number,
+            json!(0.5),
+            "/readyz fails when more backend requests than this fraction fail",
+        ),
+        optional(
+            "READY_ERROR_WINDOW",
+            Kind::Duration,
+            json!("1m"),
+            "Window of the backend error rate",
+        ),
+        optional(
+            "READY_MIN_REQUESTS",
+            Kind::Integer,
+            json!(20),
+            "Backend requests needed within the window to judge the error rate",
+        ),
+        optional(
+            "SENTRY_DSN",
+            Kind::Url,
+            Value::Null,
+            "Handler panics are reported here, requires --features sentry",
+        ),
+        optional(
+            "AUDIT_LOG_SIZE",
+            Kind::Integer,
+            json!(1000),
+            "Admin API calls kept in memory for /_admin/audit",
+        ),
+        optional(
+            "ACCESS_LOG_FORMAT",
+            Kind::String,
+            json!(r#"{remote} "{method} {path}" {status} {duration}ms "{referer}" "{user_agent}""#),
+            "Access log line with {remote}, {method}, {path}, {status}, {duration}, {user_agent}, {referer}, empty turns it off",
+        ),
+        optional(
+            "ACCESS_LOG_EXCLUDE",
+            Kind::String,
+            json!("/readyz,/metrics"),
+            "Comma separated paths left out of the access log",
+        ),
+    ];
+
+    for namespace in Namespace::ALL.iter() {
+        let (ttl, capacity, max_bytes) = namespace.defaults();
+        let prefix = namespace.env_prefix();
+        let name = namespace.name();
+
+        settings.push(optional(
+            &format!("{}_TTL", prefix),
+            Kind::Duration,
+            json!(humantime::format_duration(ttl).to_string()),
+            &format!("TTL of cached {} entries", name),
+        ));
+        settings.push(optional(
+            &format!("{}_MIN_TTL", prefix),
+            Kind::Duration,
+            json!("10s"),
+            &format!("Lower bound of {} TTL set by backend Cache-Control", name),
+        ));
+        settings.push(optional(
+            &format!("{}_MAX_TTL", prefix),
+            Kind::Duration,
+            json!("24h"),
+            &format!("Upper bound of {} TTL set by backend Cache-Control", name),
+        ));
+        settings.push(optional(
+            &format!("{}_CAPACITY", prefix),
+            Kind::Integer,
+            json!(capacity),
+            &format!("Max cached {} entries, 0 disables the cache", name),
+        ));
+        settings.push(optional(
+            &format!("{}_MAX_BYTES", prefix),
+            Kind::Integer,
+            json!(max_bytes),
+            &format!("Memory budget of cached {} entries", name),
+        ));
+    }
+
+    settings
+}
+
+/// JSON Schema (draft 7) of the environment, for validating deployment values
+pub fn config_schema() -> Value {
+    let settings = settings();
+
+    let properties = settings
+        .iter()
+        .map(|setting| {
+            let mut property = match setting.kind {
+                Kind::String => json!({ "type": "string" }),
+                Kind::Url => json!({ "type": "string", "format": "uri" }),
+                Kind::Integer => json!({ "type": "integer", "minimum": 0 }),
+                Kind::Number => json!({ "type": "number" }),
+                Kind::Boolean => json!({ "type": "boolean" }),
+                Kind::Duration => json!({
+                    "type": "string",
+                    "pattern": "^([0-9]+ ?[a-zA-Z]+ ?)+$",
+                }),
+            };
+            property["description"] = json!(setting.description);
+            if let Some(ref default) = setting.default {
+                property["default"] = default.clone();
+            }
+
+            (setting.name.clone(), property)
+        })
+        .collect::<Map<_, _>>();
+
+    let required = settings
+        .iter()
+        .filter(|setting| setting.required)
+        .map(|setting| json!(setting.name))
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "howtocards ssi configuration",
+        "description": "Environment variables read by the service",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
@@ -0,0 +1,73 @@
+use futures::sync::oneshot;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::cache::Namespace;
+
+/// What followers get from the leader: the entity as cached, or the error message
+pub type Shared = Result<Option<Arc<Value>>, String>;
+
+type Key = (Namespace, String);
+
+lazy_static! {
+    /// Entities being fetched right now, with requests waiting for them
+    static ref IN_FLIGHT: Mutex<HashMap<Key, Vec<oneshot::Sender<Shared>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Only one backend request per entity is sent at a time, across all workers.
+/// The first request becomes the leader and fetches, the rest follow and get its result.
+#[derive(Debug)]
+pub enum Flight {
+    Leader(Leader),
+    Follower(oneshot::Receiver<Shared>),
+}
+
+pub fn join(namespace: Namespace, key: &str) -> Flight {
+    let key = (namespace, key.to_string());
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+
+    if let Some(followers) = in_flight.get_mut(&key) {
+        let (sender, receiver) = oneshot::channel();
+        followers.push(sender);
+        return Flight::Follower(receiver);
+    }
+
+    in_flight.insert(key.clone(), Vec::new());
+    Flight::Leader(Leader { key: Some(key) })
+}
+
+/// Followers are released when the leader finishes. If its request is dropped
+/// before that, they get `Canceled` and fetch on their own.
+#[derive(Debug)]
+pub struct Leader {
+    /// Taken once followers are released
+    key: Option<Key>,
+}
+
+impl Leader {
+    /// `shared` is only evaluated when someone waits
+    pub fn finish(mut self, shared: impl FnOnce() -> Shared) {
+        let followers = match self.key.take() {
+            Some(key) => IN_FLIGHT.lock().unwrap().remove(&key).unwrap_or_default(),
+            None => return,
+        };
+
+        if !followers.is_empty() {
+            let shared = shared();
+            for follower in followers {
+                let _ = follower.send(shared.clone());
+            }
+        }
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            IN_FLIGHT.lock().unwrap().remove(&key);
+        }
+    }
+}
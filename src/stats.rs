@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::memory;
+
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Sliding window counter of requested card ids.
+/// Requests are grouped into one-minute buckets, buckets ended before `retention` are dropped.
+#[derive(Debug)]
+pub struct TopCards {
+    retention: Duration,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    started: Instant,
+    hits: HashMap<u32, u64>,
+}
+
+impl TopCards {
+    pub fn new(retention: Duration) -> Self {
+        TopCards {
+            retention,
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    pub fn record(&self, card_id: u32) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let len_before = buckets.len();
+
+        while buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.started) >= self.retention + BUCKET)
+        {
+            buckets.pop_front();
+        }
+
+        let fresh = buckets
+            .back()
+            .is_some_and(|bucket| now.duration_since(bucket.started) < BUCKET);
+
+        if !fresh {
+            buckets.push_back(Bucket {
+                started: now,
+                hits: HashMap::new(),
+            });
+        }
+
+        if let Some(bucket) = buckets.back_mut() {
+            *bucket.hits.entry(card_id).or_insert(0) += 1;
+        }
+
+        if buckets.len() != len_before {
+            memory::report_cache("top_cards", approximate_size(&buckets));
+        }
+    }
+
+    /// Most requested card ids during the last `window`, most popular first.
+    /// A bucket partly in the window counts whole, so hits up to a minute older are counted too.
+    pub fn top(&self, window: Duration, limit: usize) -> Vec<(u32, u64)> {
+        let now = Instant::now();
+        let buckets = self.buckets.lock().unwrap();
+        let mut totals = HashMap::new();
+
+        for bucket in buckets
+            .iter()
+            .rev()
+            .take_while(|bucket| now.duration_since(bucket.started) < window + BUCKET)
+        {
+            for (card_id, hits) in &bucket.hits {
+                *totals.entry(*card_id).or_insert(0) += hits;
+            }
+        }
+
+        let mut top = totals.into_iter().collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+    }
+}
+
+fn approximate_size(buckets: &VecDeque<Bucket>) -> u64 {
+    let entry = std::mem::size_of::<(u32, u64)>() + std::mem::size_of::<u64>();
+
+    buckets
+        .iter()
+        .map(|bucket| std::mem::size_of::<Bucket>() + bucket.hits.capacity() * entry)
+        .sum::<usize>() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(age: Duration, hits: &[(u32, u64)]) -> Bucket {
+        Bucket {
+            started: Instant::now() - age,
+            hits: hits.iter().cloned().collect(),
+        }
+    }
+
+    fn top_cards(buckets: Vec<Bucket>) -> TopCards {
+        TopCards {
+            retention: Duration::from_secs(3600),
+            buckets: Mutex::new(buckets.into()),
+        }
+    }
+
+    #[test]
+    fn top_counts_the_bucket_partly_in_the_window() {
+        let top_cards = top_cards(vec![
+            bucket(Duration::from_secs(200), &[(1, 5)]),
+            bucket(Duration::from_secs(150), &[(2, 3)]),
+            bucket(Duration::from_secs(30), &[(2, 1), (3, 2)]),
+        ]);
+
+        assert_eq!(
+            top_cards.top(Duration::from_secs(120), 10),
+            vec![(2, 4), (3, 2)]
+        );
+    }
+
+    #[test]
+    fn top_sorts_by_hits_then_id() {
+        let top_cards = top_cards(vec![bucket(
+            Duration::from_secs(10),
+            &[(3, 2), (1, 2), (2, 7)],
+        )]);
+
+        assert_eq!(
+            top_cards.top(Duration::from_secs(60), 2),
+            vec![(2, 7), (1, 2)]
+        );
+    }
+
+    #[test]
+    fn record_adds_to_the_current_bucket() {
+        let top_cards = TopCards::new(Duration::from_secs(3600));
+        top_cards.record(1);
+        top_cards.record(1);
+        top_cards.record(2);
+
+        assert_eq!(top_cards.buckets.lock().unwrap().len(), 1);
+        assert_eq!(
+            top_cards.top(Duration::from_secs(60), 10),
+            vec![(1, 2), (2, 1)]
+        );
+    }
+}
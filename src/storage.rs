@@ -0,0 +1,789 @@
+use actix_web::body::{Body, BodySize, MessageBody, ResponseBody};
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpResponse};
+use futures::{Async, Poll};
+use lol_html::html_content::ContentType;
+use lol_html::{element, RewriteStrSettings};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::compression::Precompressed;
+use crate::critical;
+use crate::flags::env_flag;
+use crate::images::version_hash;
+use crate::metrics;
+
+#[derive(Debug)]
+pub struct Storage {
+    pub index_html: String,
+    pub fragments: Vec<Fragment>,
+    /// Hash of the whole template, tells which frontend build is active
+    pub hash: String,
+    pub loaded_at: SystemTime,
+    pub errors: ErrorPages,
+    /// Parts around the injected meta gzipped ahead, with `COMPRESSION`
+    pub precompressed: Option<Precompressed>,
+    marker: String,
+    /// `index_html` cut around the head, `None` with a custom marker
+    split: Option<HeadSplit>,
+}
+
+/// Template cut at its `<title>` and at the end of its head once it is loaded,
+/// so pages are put together from the parts instead of searching the whole document
+#[derive(Debug)]
+struct HeadSplit {
+    /// Up to the title, or up to the end of the head when there is none
+    prefix: Bytes,
+    /// Own `<title>` element of the template
+    title: Option<Bytes>,
+    /// From the title up to the end of the head
+    middle: Bytes,
+    suffix: Bytes,
+}
+
+/// Page as the chunks it is made of, parts of the template are shared by every page
+/// and sent as they are instead of being copied into one document.
+/// Responses with it keep a copy in their extensions for middlewares that look at the page.
+#[derive(Clone, Debug, Default)]
+pub struct PageBody {
+    chunks: Vec<Bytes>,
+}
+
+impl PageBody {
+    /// Page of a response, `None` for responses that aren't pages
+    pub fn of(response: &HttpResponse) -> Option<PageBody> {
+        if let Some(page) = response.extensions().get::<PageBody>() {
+            return Some(page.clone());
+        }
+
+        match response.body() {
+            ResponseBody::Body(Body::Bytes(ref bytes)) => Some(PageBody {
+                chunks: vec![bytes.clone()],
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn chunks(&self) -> &[Bytes] {
+        &self.chunks
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(Bytes::len).sum()
+    }
+
+    /// Whole page in one string, for pages that are rewritten further
+    pub fn into_string(self) -> String {
+        let mut page = String::with_capacity(self.len());
+        for chunk in &self.chunks {
+            page.push_str(&String::from_utf8_lossy(chunk));
+        }
+        page
+    }
+
+    /// Response of `builder` with the page as its body
+    pub fn respond(self, builder: &mut HttpResponseBuilder) -> HttpResponse {
+        let mut response = builder.body(Body::Message(Box::new(self.clone())));
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+impl From<String> for PageBody {
+    fn from(page: String) -> Self {
+        PageBody {
+            chunks: vec![Bytes::from(page)],
+        }
+    }
+}
+
+impl MessageBody for PageBody {
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.len())
+    }
+
+    fn poll_next(&mut self) -> Poll<Option<Bytes>, Error> {
+        if self.chunks.is_empty() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::Ready(Some(self.chunks.remove(0))))
+        }
+    }
+}
+
+/// Pages for error responses from `ERROR_PAGES_DIR`: `{status}.html` or `{class}0x.html`
+/// (`503.html`, `50x.html`), `__STATUS__` is replaced with the status code.
+/// Without them errors are masked by the bare template.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    pages: HashMap<String, String>,
+}
+
+impl ErrorPages {
+    fn read_from(dir: &str) -> Result<Self, std::io::Error> {
+        let pages = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+            .map(|path| {
+                let name = path
+                    .file_stem()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                Ok((name, std::fs::read_to_string(&path)?))
+            })
+            .collect::<Result<_, std::io::Error>>()?;
+
+        Ok(ErrorPages { pages })
+    }
+
+    pub fn get(&self, status: u16) -> Option<String> {
+        self.pages
+            .get(&status.to_string())
+            .or_else(|| self.pages.get(&format!("{}0x", status / 100)))
+            .map(|page| page.replace("__STATUS__", &status.to_string()))
+    }
+}
+
+/// Part of the template. `INDEX_HTML_PATH` may point to a single file,
+/// a comma separated list of files or a directory of `*.html` files
+/// which are stitched together in file name order (`00-head.html`, `50-analytics.html`, ...).
+/// `INDEX_HTML_URL` is a single remote file.
+#[derive(Debug)]
+pub struct Fragment {
+    pub name: String,
+    pub source: String,
+}
+
+impl Storage {
+    /// Without the marker no meta can be injected, so it is checked right away.
+    /// In strict mode a template without marker is an error, otherwise it is only logged.
+    pub fn read_from(path: String, marker: String, strict: bool) -> Result<Self, std::io::Error> {
+        use log::error;
+
+        let fragments = read_fragments(&path)?;
+        let source = fragments
+            .iter()
+            .map(|fragment| fragment.source.as_str())
+            .collect::<String>();
+
+        let found = if marker == DEFAULT_MARKER {
+            source.to_ascii_lowercase().contains(DEFAULT_MARKER)
+        } else {
+            source.contains(&marker)
+        };
+
+        if !found {
+            let message = format!(
+                "Template {} does not contain injection marker {:?}, pages will be served without meta. \
+                 Check that INDEX_HTML_PATH points to the built index.html, \
+                 that the marker is spelled exactly as in the template (it is case-sensitive), \
+                 or set INJECT_MARKER to a string present in the template head",
+                path, marker
+            );
+
+            if strict {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    message,
+                ));
+            }
+
+            error!("{}", message);
+        }
+
+        Ok(Storage {
+            hash: version_hash(&source),
+            loaded_at: SystemTime::now(),
+            errors: ErrorPages::default(),
+            precompressed: None,
+            index_html: source,
+            fragments,
+            marker,
+            split: None,
+        })
+    }
+
+    /// Puts html right before the injection marker
+    pub fn inject(&self, html: &str) -> PageBody {
+        match self.split {
+            Some(ref split) => split.join(html),
+            None => PageBody::from(self.inject_into(&self.index_html, html)),
+        }
+    }
+
+    /// Same for other pages, e.g. error pages.
+    /// A `<title>` of the meta replaces the page's own one, pages without a title get it with the meta.
+    ///
+    /// With the default marker the page is parsed, so `</head>` in comments, scripts
+    /// or in other case doesn't matter. Other markers are replaced as they are.
+    pub fn inject_into(&self, page: &str, html: &str) -> String {
+        if self.marker == DEFAULT_MARKER {
+            if let Some(page) = rewrite_head(page, html) {
+                return page;
+            }
+        }
+
+        // Inline SVG in the body has titles as well
+        let head = &page[..page.find("</head>").unwrap_or(page.len())];
+        let (page, html) = match (title_range(head), title_range(html)) {
+            (Some(page_title), Some(title)) => (
+                Cow::Owned(format!(
+                    "{}{}{}",
+                    &page[..page_title.start],
+                    &html[title.clone()],
+                    &page[page_title.end..]
+                )),
+                Cow::Owned(format!("{}{}", &html[..title.start], &html[title.end..])),
+            ),
+            _ => (Cow::Borrowed(page), Cow::Borrowed(html)),
+        };
+
+        let replace_to = format!("{}{}", html, self.marker);
+        page.replace(&self.marker, &replace_to)
+    }
+}
+
+/// Puts server-rendered content into the `#root` element of a page, the SPA replaces it
+/// once it starts. The page is returned as is when it has no such element.
+pub fn inject_body(page: &str, body: &str) -> String {
+    lol_html::rewrite_str(
+        page,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("#root", |root| {
+                root.set_inner_content(body, ContentType::Html);
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .unwrap_or_else(|err| {
+        log::error!("Failed to inject body into the page: {}", err);
+        page.to_string()
+    })
+}
+
+/// End of the template head, meta goes right before it
+const DEFAULT_MARKER: &str = "</head>";
+
+/// How the template is read and prepared
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// `ERROR_PAGES_DIR`
+    pub error_pages: Option<String>,
+    /// `INJECT_MARKER`
+    pub marker: String,
+    /// `TEMPLATE_STRICT`
+    pub strict: bool,
+    /// `CRITICAL_CSS_PATH`, inlined into the template head
+    pub critical_css: Option<String>,
+    /// `CRITICAL_CSS_DEFER`, stylesheet links stop blocking the first paint
+    pub defer_stylesheets: bool,
+    /// `NOINDEX` or `STAGING`, the template gets robots `noindex,nofollow`
+    pub noindex: bool,
+    /// `COMPRESSION`, static parts of the template are gzipped once it is loaded
+    pub compression: bool,
+}
+
+impl Options {
+    pub fn from_env() -> Self {
+        let path = |name| std::env::var(name).ok().filter(|path| !path.is_empty());
+
+        Options {
+            error_pages: path("ERROR_PAGES_DIR"),
+            marker: std::env::var("INJECT_MARKER").unwrap_or_else(|_| DEFAULT_MARKER.to_string()),
+            strict: env_flag("TEMPLATE_STRICT"),
+            critical_css: path("CRITICAL_CSS_PATH"),
+            defer_stylesheets: env_flag("CRITICAL_CSS_DEFER"),
+            noindex: env_flag("NOINDEX") || env_flag("STAGING"),
+            compression: std::env::var("COMPRESSION").map_or(true, |_| env_flag("COMPRESSION")),
+        }
+    }
+}
+
+/// Active template. `INDEX_HTML_PATH` may be a symlink to a build directory
+/// that the deploy pipeline swaps atomically (blue/green), the swap is noticed
+/// by the watcher and the new template replaces the old one without a restart.
+/// `INDEX_HTML_URL` is fetched again on every check, the last good copy is kept
+/// while it can't be fetched.
+#[derive(Debug)]
+pub struct Templates {
+    path: String,
+    options: Options,
+    active: RwLock<Arc<Storage>>,
+    signature: Mutex<Vec<Signature>>,
+}
+
+/// Identity of a template file or directory, changes when a symlink is retargeted
+#[derive(Debug, PartialEq)]
+struct Signature {
+    target: PathBuf,
+    /// Unix only, elsewhere a replaced file is noticed by its time and length
+    inode: Option<u64>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    pub fn load(path: String, options: Options) -> Result<Self, std::io::Error> {
+        let storage = read(&path, &options)?;
+        report(&storage);
+
+        let templates = Templates {
+            path,
+            options,
+            active: RwLock::new(Arc::new(storage)),
+            signature: Mutex::default(),
+        };
+        *templates.signature.lock().unwrap() = templates.signature();
+
+        Ok(templates)
+    }
+
+    pub fn current(&self) -> Arc<Storage> {
+        self.active.read().unwrap().clone()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_remote(&self) -> bool {
+        is_remote(&self.path)
+    }
+
+    /// Reads the template again and swaps it in. Requests in flight keep the old one.
+    /// A broken template is not swapped in, the active one keeps being served.
+    pub fn reload(&self) -> Result<Arc<Storage>, std::io::Error> {
+        use log::info;
+
+        let signature = self.signature();
+        let storage = Arc::new(read(&self.path, &self.options).inspect_err(|_| {
+            metrics::record_template_reload(false);
+        })?);
+
+        if storage.hash == self.current().hash {
+            *self.signature.lock().unwrap() = signature;
+            return Ok(self.current());
+        }
+
+        metrics::record_template_reload(true);
+        *self.signature.lock().unwrap() = signature;
+        *self.active.write().unwrap() = storage.clone();
+        report(&storage);
+
+        info!("Template {} is loaded, hash {}", self.path, storage.hash);
+        Ok(storage)
+    }
+
+    /// Polls `INDEX_HTML_PATH` every `interval`, zero interval disables the watcher.
+    /// A remote template is fetched every time, it's swapped in when its hash changes.
+    pub fn spawn_watcher(self: &Arc<Self>, interval: Duration) {
+        use log::error;
+
+        if interval == Duration::from_secs(0) {
+            return;
+        }
+
+        let templates = self.clone();
+
+        std::thread::Builder::new()
+            .name("template-watcher".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+
+                if !templates.is_remote()
+                    && *templates.signature.lock().unwrap() == templates.signature()
+                {
+                    continue;
+                }
+
+                if let Err(err) = templates.reload() {
+                    error!(
+                        "Template {} cannot be loaded, the active one is kept: {}",
+                        templates.path, err
+                    );
+                    // Don't retry until the path changes again
+                    *templates.signature.lock().unwrap() = templates.signature();
+                }
+            })
+            .expect("cannot spawn template watcher thread");
+    }
+
+    /// Error pages and critical CSS usually come with the frontend build
+    /// and are swapped together with it
+    fn signature(&self) -> Vec<Signature> {
+        let mut signature = signature(&self.path);
+        for path in self
+            .options
+            .error_pages
+            .iter()
+            .chain(&self.options.critical_css)
+        {
+            signature.extend(self::signature(path));
+        }
+        signature
+    }
+}
+
+/// `LOCALE_INDEX_HTML`, templates of `/{locale}/` pages: `{"ru": "/app/static/ru/index.html"}`.
+/// Locales without one are served with `INDEX_HTML_PATH`.
+#[derive(Debug, Default)]
+pub struct LocaleTemplates {
+    templates: HashMap<String, Arc<Templates>>,
+}
+
+impl LocaleTemplates {
+    pub fn from_env(options: &Options) -> Result<Self, String> {
+        let paths: HashMap<String, String> = match std::env::var("LOCALE_INDEX_HTML") {
+            Ok(ref value) if !value.trim().is_empty() => {
+                serde_json::from_str(value).map_err(|err| err.to_string())?
+            }
+            _ => return Ok(LocaleTemplates::default()),
+        };
+
+        let mut templates = HashMap::new();
+        for (locale, path) in paths {
+            let loaded = Templates::load(path.clone(), options.clone())
+                .map_err(|err| format!("{}: {}", path, err))?;
+            templates.insert(locale.to_lowercase(), Arc::new(loaded));
+        }
+
+        Ok(LocaleTemplates { templates })
+    }
+
+    pub fn get(&self, locale: &str) -> Option<&Arc<Templates>> {
+        self.templates.get(locale)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Templates>> {
+        self.templates.values()
+    }
+}
+
+fn read(path: &str, options: &Options) -> Result<Storage, std::io::Error> {
+    let mut storage = Storage::read_from(path.to_string(), options.marker.clone(), options.strict)?;
+
+    if let Some(ref css_path) = options.critical_css {
+        let css = std::fs::read_to_string(css_path)?;
+        storage.index_html = critical::inline(
+            &storage.index_html,
+            &css,
+            options.defer_stylesheets,
+            &options.marker,
+        );
+        storage.hash = version_hash(&storage.index_html);
+    }
+
+    // Part of the template, so every page made of it has the tag, even fallbacks
+    if options.noindex {
+        storage.index_html = storage.inject_into(
+            &storage.index_html,
+            r#"<meta name="robots" content="noindex,nofollow" />"#,
+        );
+        storage.hash = version_hash(&storage.index_html);
+    }
+
+    if let Some(ref dir) = options.error_pages {
+        storage.errors = ErrorPages::read_from(dir)?;
+    }
+    if storage.marker == DEFAULT_MARKER {
+        storage.split = HeadSplit::new(&storage.index_html);
+    }
+    if options.compression {
+        storage.precompressed = storage
+            .split
+            .as_ref()
+            .map(|split| Precompressed::new(&split.prefix, &split.suffix));
+    }
+    Ok(storage)
+}
+
+fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+fn fetch(url: &str) -> Result<String, std::io::Error> {
+    let response = ureq::get(url)
+        .timeout(Duration::from_secs(10))
+        .call()
+        .map_err(std::io::Error::other)?;
+
+    response.into_string()
+}
+
+fn signature(path: &str) -> Vec<Signature> {
+    if is_remote(path) {
+        return Vec::new();
+    }
+
+    path.split(',')
+        .filter_map(|part| {
+            let target = std::fs::canonicalize(part.trim()).ok()?;
+            let metadata = std::fs::metadata(&target).ok()?;
+
+            Some(Signature {
+                inode: inode(&metadata),
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+                target,
+            })
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+fn report(storage: &Storage) {
+    metrics::set_template_size("index", storage.index_html.len());
+    for fragment in &storage.fragments {
+        metrics::set_template_size(&fragment.name, fragment.source.len());
+    }
+    metrics::set_template_hash(&storage.hash);
+}
+
+fn read_fragments(path: &str) -> Result<Vec<Fragment>, std::io::Error> {
+    if is_remote(path) {
+        return Ok(vec![Fragment {
+            name: path
+                .rsplit('/')
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("index.html")
+                .to_string(),
+            source: fetch(path)?,
+        }]);
+    }
+
+    let paths = if path.contains(',') {
+        path.split(',')
+            .map(|part| PathBuf::from(part.trim()))
+            .collect()
+    } else if Path::new(path).is_dir() {
+        let mut paths = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        if paths.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no *.html fragments in {}", path),
+            ));
+        }
+
+        paths
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    paths
+        .into_iter()
+        .map(|path| {
+            Ok(Fragment {
+                name: path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+                source: std::fs::read_to_string(&path)?,
+            })
+        })
+        .collect()
+}
+
+/// `<title>…</title>` element with the tags
+fn title_range(html: &str) -> Option<std::ops::Range<usize>> {
+    let start = html.find("<title")?;
+    let end = start + html[start..].find("</title>")? + "</title>".len();
+    Some(start..end)
+}
+
+impl HeadSplit {
+    /// Marks the title and the end of the head with the rewriter and cuts there,
+    /// `None` when the template has no head
+    fn new(page: &str) -> Option<Self> {
+        const TITLE_START: &str = "\u{0}ssi-title-start\u{0}";
+        const TITLE_END: &str = "\u{0}ssi-title-end\u{0}";
+        const HEAD_END: &str = "\u{0}ssi-head-end\u{0}";
+
+        let head_found = Cell::new(false);
+        let title_found = Cell::new(false);
+
+        let marked = lol_html::rewrite_str(
+            page,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("head", |head| {
+                        if !head_found.replace(true) {
+                            head.append(HEAD_END, ContentType::Html);
+                        }
+                        Ok(())
+                    }),
+                    element!("head title", |title| {
+                        if !title_found.replace(true) {
+                            title.before(TITLE_START, ContentType::Html);
+                            title.after(TITLE_END, ContentType::Html);
+                        }
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::new()
+            },
+        )
+        .ok()?;
+
+        let (head, suffix) = marked.split_once(HEAD_END)?;
+        let (prefix, title, middle) = match head.split_once(TITLE_START) {
+            Some((prefix, rest)) => {
+                let (title, middle) = rest.split_once(TITLE_END)?;
+                (prefix, Some(title), middle)
+            }
+            None => (head, None, ""),
+        };
+
+        Some(HeadSplit {
+            prefix: Bytes::from(prefix),
+            title: title.map(Bytes::from),
+            middle: Bytes::from(middle),
+            suffix: Bytes::from(suffix),
+        })
+    }
+
+    /// Template with `html` at the end of the head, a title of `html` takes the place of the template's one
+    fn join(&self, html: &str) -> PageBody {
+        let (title, meta) = match title_range(html) {
+            Some(range) => (
+                Bytes::from(&html[range.clone()]),
+                Bytes::from(format!("{}{}", &html[..range.start], &html[range.end..])),
+            ),
+            None => (self.title.clone().unwrap_or_default(), Bytes::from(html)),
+        };
+
+        PageBody {
+            chunks: vec![
+                self.prefix.clone(),
+                title,
+                self.middle.clone(),
+                meta,
+                self.suffix.clone(),
+            ],
+        }
+    }
+}
+
+/// Appends `html` to the `<head>` element, `None` when the page has none.
+/// The page's `<title>` is dropped when `html` brings its own.
+fn rewrite_head(page: &str, html: &str) -> Option<String> {
+    let replace_title = title_range(html).is_some();
+    let found = Cell::new(false);
+
+    let rewritten = lol_html::rewrite_str(
+        page,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("head", |head| {
+                    if !found.replace(true) {
+                        head.append(html, ContentType::Html);
+                    }
+                    Ok(())
+                }),
+                element!("head title", |title| {
+                    if replace_title {
+                        title.remove();
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+
+    match rewritten {
+        Ok(page) if found.get() => Some(page),
+        Ok(_) => None,
+        Err(err) => {
+            log::error!("Failed to inject meta into the page: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const META: &str = r#"<meta property="og:title" content="Card" />"#;
+
+    #[test]
+    fn rewrite_head_appends_to_the_head() {
+        assert_eq!(
+            rewrite_head("<html><head><link /></head><body></body></html>", META).as_deref(),
+            Some(
+                r#"<html><head><link /><meta property="og:title" content="Card" /></head><body></body></html>"#
+            )
+        );
+    }
+
+    #[test]
+    fn rewrite_head_skips_head_end_in_comments_and_scripts() {
+        let page = concat!(
+            "<html><head><!-- </head> -->",
+            "<script>document.write('</head>')</script>",
+            "</head><body></body></html>"
+        );
+
+        assert_eq!(
+            rewrite_head(page, META).as_deref(),
+            Some(concat!(
+                "<html><head><!-- </head> -->",
+                "<script>document.write('</head>')</script>",
+                r#"<meta property="og:title" content="Card" /></head><body></body></html>"#
+            ))
+        );
+    }
+
+    #[test]
+    fn rewrite_head_finds_uppercase_head() {
+        assert_eq!(
+            rewrite_head(
+                "<HTML><HEAD><TITLE>Site</TITLE></HEAD><BODY></BODY></HTML>",
+                META
+            )
+            .as_deref(),
+            Some(
+                r#"<HTML><HEAD><TITLE>Site</TITLE><meta property="og:title" content="Card" /></HEAD><BODY></BODY></HTML>"#
+            )
+        );
+    }
+
+    #[test]
+    fn rewrite_head_replaces_title() {
+        assert_eq!(
+            rewrite_head(
+                "<html><head><title>Site</title></head><body><svg><title>Icon</title></svg></body></html>",
+                "<title>Card</title>"
+            )
+            .as_deref(),
+            Some("<html><head><title>Card</title></head><body><svg><title>Icon</title></svg></body></html>")
+        );
+    }
+
+    #[test]
+    fn rewrite_head_needs_a_head() {
+        assert_eq!(rewrite_head("<html><body></body></html>", META), None);
+        assert_eq!(rewrite_head("<div id=\"root\"></div>", META), None);
+    }
+}